@@ -0,0 +1,228 @@
+//! Contains registered accounts. Patrons who register keep their name across visits, and get a
+//! mailbox where direct messages wait for them while they're away from the tavern.
+//! Accounts are saved to a JSON file after every change, if the server is configured with one.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::common::unix_millis;
+
+/// Number of mails a mailbox holds before refusing new ones.
+pub const MAILBOX_LEN: usize = 50;
+/// PBKDF2 rounds passwords are hashed with, to make guessing them slow.
+pub const PASSWORD_ROUNDS: u32 = 100_000;
+/// Wrong passwords a connection may give before it can't log in anymore.
+pub const MAX_FAILED_LOGINS: u32 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Account {
+    pub name: String,
+    salt: String,
+    password_hash: String,
+    /// PBKDF2 rounds of the hash. Accounts saved before passwords were stretched have none,
+    /// and a single SHA-256 instead.
+    #[serde(default)]
+    rounds: u32,
+    pub mailbox: Vec<Mail>,
+}
+
+/// A new password, salted and hashed. Hashing takes a while, so the server does it away from
+/// its event loop.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PasswordHash {
+    salt: String,
+    hash: String,
+}
+
+impl PasswordHash {
+    /// Hashes a password, with a new salt.
+    pub fn new(password: &str) -> Self {
+        let mut salt = [0; 16];
+        getrandom::fill(&mut salt).expect("The system provides random numbers");
+        let salt = hex(&salt);
+        let hash = hash_password(&salt, password, PASSWORD_ROUNDS);
+        Self { salt, hash }
+    }
+}
+
+impl std::fmt::Debug for PasswordHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PasswordHash(***)")
+    }
+}
+
+impl Account {
+    fn new(name: &str, password: PasswordHash) -> Self {
+        let mut account = Self {
+            name: name.to_owned(),
+            salt: String::new(),
+            password_hash: String::new(),
+            rounds: 0,
+            mailbox: vec![],
+        };
+        account.set_password(password);
+        account
+    }
+
+    /// Checks a password against the account's hash. This takes a while, as hashing does.
+    pub fn has_password(&self, password: &str) -> bool {
+        let hash = match self.rounds {
+            0 => hex(&Sha256::digest(format!("{}:{password}", self.salt))),
+            rounds => hash_password(&self.salt, password, rounds),
+        };
+        constant_time_eq(hash.as_bytes(), self.password_hash.as_bytes())
+    }
+
+    pub fn set_password(&mut self, password: PasswordHash) {
+        self.salt = password.salt;
+        self.password_hash = password.hash;
+        self.rounds = PASSWORD_ROUNDS;
+    }
+
+    /// Returns true if the password was hashed the way it was before passwords were
+    /// stretched, and should be hashed again.
+    pub fn has_legacy_password(&self) -> bool {
+        self.rounds < PASSWORD_ROUNDS
+    }
+
+    pub fn unread(&self) -> usize {
+        self.mailbox.iter().filter(|mail| !mail.read).count()
+    }
+}
+
+/// A direct message left for a patron who wasn't in the tavern.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mail {
+    pub from: String,
+    pub content: String,
+    #[serde(with = "unix_millis")]
+    pub timestamp: SystemTime,
+    pub read: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Accounts {
+    /// Where accounts are saved. They only live in memory without one.
+    path: Option<PathBuf>,
+    /// Accounts by lowercase name.
+    accounts: BTreeMap<String, Account>,
+}
+
+impl Accounts {
+    /// Loads the accounts saved in `path`, if it exists yet.
+    pub fn load(path: Option<&Path>) -> io::Result<Self> {
+        let accounts = match path {
+            Some(path) if path.exists() => serde_json::from_str(&fs::read_to_string(path)?)?,
+            _ => Default::default(),
+        };
+        Ok(Self {
+            path: path.map(Path::to_owned),
+            accounts,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Account> {
+        self.accounts.get(&name.to_lowercase())
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Account> {
+        self.accounts.get_mut(&name.to_lowercase())
+    }
+
+    /// Registers a new account. Returns false if the name is taken.
+    pub fn register(&mut self, name: &str, password: PasswordHash) -> bool {
+        if self.get(name).is_some() {
+            return false;
+        }
+        self.accounts
+            .insert(name.to_lowercase(), Account::new(name, password));
+        true
+    }
+
+    /// Writes the accounts to disk. The file is replaced at once, so a crash leaves the
+    /// previous version intact.
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(&self.accounts)?)?;
+        fs::rename(temp_path, path)
+    }
+}
+
+fn hash_password(salt: &str, password: &str, rounds: u32) -> String {
+    let mut hash = [0; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt.as_bytes(), rounds, &mut hash);
+    hex(&hash)
+}
+
+/// Compares two hashes in a time that doesn't depend on where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn accounts_and_their_mail_are_saved() {
+        let path =
+            std::env::temp_dir().join(format!("tavern-accounts-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut accounts = Accounts::load(Some(&path)).unwrap();
+        assert!(accounts.register("Alice", PasswordHash::new("hunter2")));
+        assert!(!accounts.register("alice", PasswordHash::new("letmein")));
+        accounts.get_mut("ALICE").unwrap().mailbox.push(Mail {
+            from: "Bob".to_owned(),
+            content: "Meet me at the docks.".to_owned(),
+            timestamp: SystemTime::now(),
+            read: false,
+        });
+        accounts.save().unwrap();
+
+        let accounts = Accounts::load(Some(&path)).unwrap();
+        let alice = accounts.get("alice").unwrap();
+        assert_eq!(alice.name, "Alice");
+        assert!(alice.has_password("hunter2"));
+        assert!(!alice.has_password("letmein"));
+        assert!(!alice.has_legacy_password());
+        assert_eq!(alice.unread(), 1);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn passwords_are_salted_and_legacy_hashes_still_work() {
+        let alice = Account::new("Alice", PasswordHash::new("hunter2"));
+        let other = Account::new("Alice", PasswordHash::new("hunter2"));
+        assert_ne!(alice.salt, other.salt);
+        assert_ne!(alice.password_hash, other.password_hash);
+
+        let mut legacy = Account {
+            salt: "0123456789abcdef".to_owned(),
+            password_hash: hex(&Sha256::digest("0123456789abcdef:hunter2")),
+            rounds: 0,
+            ..alice
+        };
+        assert!(legacy.has_legacy_password());
+        assert!(legacy.has_password("hunter2"));
+        assert!(!legacy.has_password("letmein"));
+        legacy.set_password(PasswordHash::new("hunter2"));
+        assert!(!legacy.has_legacy_password());
+        assert!(legacy.has_password("hunter2"));
+    }
+}
@@ -0,0 +1,429 @@
+//! Contains the client side state of the terminal client.
+//! The server doesn't report a client's context, so it is mirrored from the commands typed.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+pub const MAX_LINES: usize = 1000;
+pub const MAX_HISTORY: usize = 100;
+/// Marks messages the server replays from its history.
+pub const HISTORY_MARKER: &str = "[history] ";
+/// Surround messages mentioning this client.
+pub const MENTION_START: &str = "\x1b[1;33m";
+pub const MENTION_END: &str = "\x1b[0m";
+
+/// Mirrors the server side `ClientContext` of this client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirroredContext {
+    pub target: String,
+    pub tone: String,
+}
+
+impl Default for MirroredContext {
+    fn default() -> Self {
+        Self {
+            target: "The World".to_owned(),
+            tone: "said".to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineKind {
+    System,
+    Message {
+        tone: String,
+        private: bool,
+    },
+    /// A message mentioning this client with `@name`.
+    Mention {
+        tone: String,
+    },
+    /// Something a patron does, e.g. `* Alice leans on the bar`.
+    Action,
+    /// A message replayed from the server's history.
+    History {
+        tone: String,
+    },
+    /// Anything that isn't in the server's message format, including local notices.
+    Plain,
+}
+
+/// A line received from the server, split into its parts for rendering.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatLine {
+    pub time: String,
+    /// The id of the message, like `#2s`, used to edit or delete it.
+    pub id: String,
+    pub from: String,
+    pub content: String,
+    pub kind: LineKind,
+}
+
+impl ChatLine {
+    /// Parses a line in the format produced by `Message::to_output`
+    /// or `SystemNotification::to_output`.
+    pub fn parse(raw: &str) -> Self {
+        if let Some(rest) = raw.strip_prefix(HISTORY_MARKER) {
+            let mut line = Self::parse(rest);
+            if let LineKind::Message { tone, .. } = line.kind {
+                line.kind = LineKind::History { tone };
+            }
+            return line;
+        }
+        if let Some(rest) = raw.strip_prefix(MENTION_START) {
+            let mut line = Self::parse(rest.strip_suffix(MENTION_END).unwrap_or(rest));
+            if let LineKind::Message { tone, .. } = line.kind {
+                line.kind = LineKind::Mention { tone };
+            }
+            return line;
+        }
+
+        let plain = ChatLine {
+            time: String::new(),
+            id: String::new(),
+            from: String::new(),
+            content: raw.to_owned(),
+            kind: LineKind::Plain,
+        };
+
+        // Timestamps look like `2025-01-01 12:00:00.123456789 +00:00`.
+        let mut parts = raw.splitn(4, ' ');
+        let (Some(_date), Some(time), Some(_offset), Some(rest)) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return plain;
+        };
+        let Some(time) = time
+            .get(..8)
+            .filter(|time| time.chars().all(|c| c.is_ascii_digit() || c == ':'))
+        else {
+            return plain;
+        };
+        let time = time.to_owned();
+
+        if let Some(content) = rest.strip_prefix("System: ") {
+            return ChatLine {
+                time,
+                id: String::new(),
+                from: "System".to_owned(),
+                content: content.to_owned(),
+                kind: LineKind::System,
+            };
+        }
+
+        let (id, rest) = match rest.split_once(' ') {
+            Some((id, rest)) if id.starts_with('#') => (id, rest),
+            _ => ("", rest),
+        };
+        if let Some(content) = rest.strip_prefix("* ") {
+            return ChatLine {
+                time,
+                id: id.to_owned(),
+                from: String::new(),
+                content: content.to_owned(),
+                kind: LineKind::Action,
+            };
+        }
+        let Some((head, content)) = rest.split_once(": ") else {
+            return plain;
+        };
+        let head = head.trim_end();
+        let (head, private) = match head.strip_suffix("*privately*") {
+            Some(head) => (head.trim_end(), true),
+            None => (head, false),
+        };
+        let Some((from, tone)) = head.rsplit_once(' ') else {
+            return plain;
+        };
+
+        ChatLine {
+            time,
+            id: id.to_owned(),
+            from: from.to_owned(),
+            content: content.to_owned(),
+            kind: LineKind::Message {
+                tone: tone.to_owned(),
+                private,
+            },
+        }
+    }
+
+    pub fn local(content: &str) -> Self {
+        ChatLine {
+            time: String::new(),
+            id: String::new(),
+            from: String::new(),
+            content: content.to_owned(),
+            kind: LineKind::Plain,
+        }
+    }
+}
+
+/// What the main loop should do after a key press.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    None,
+    Send(String),
+    Quit,
+}
+
+#[derive(Debug, Default)]
+pub struct App {
+    pub lines: Vec<ChatLine>,
+    /// Number of lines scrolled up from the bottom of the message pane.
+    pub scroll: usize,
+    pub input: Vec<char>,
+    pub cursor: usize,
+    pub history: Vec<String>,
+    pub history_index: Option<usize>,
+    pub context: MirroredContext,
+}
+
+impl App {
+    /// Handles a raw line received from the server.
+    pub fn receive_line(&mut self, raw: &str) {
+        // The server ends replies with a `<tone> >` prompt, which the status bar replaces.
+        if let Some(tone) = raw.trim().strip_suffix(" >")
+            && !tone.is_empty()
+            && tone.chars().all(|c| c.is_ascii_alphabetic())
+        {
+            self.context.tone = tone.to_owned();
+            return;
+        }
+        if raw.trim().is_empty() {
+            return;
+        }
+        let line = ChatLine::parse(raw);
+        // Joining or leaving a room changes the target, as told by the server.
+        if line.kind == LineKind::System {
+            if let Some((room, _)) = line
+                .content
+                .strip_prefix("You join ")
+                .and_then(|rest| rest.split_once('.'))
+            {
+                self.context.target = room.to_owned();
+            } else if line.content.starts_with("You leave ") {
+                self.context.target = MirroredContext::default().target;
+            }
+        }
+        self.push_line(line);
+    }
+
+    pub fn push_line(&mut self, line: ChatLine) {
+        self.lines.push(line);
+        if self.lines.len() > MAX_LINES {
+            self.lines.remove(0);
+        }
+        // Keep the view still while the user is reading older messages.
+        if self.scroll > 0 {
+            self.scroll += 1;
+        }
+    }
+
+    pub fn handle_key(&mut self, key: KeyEvent) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Action::Quit,
+            KeyCode::Char('c' | 'd') if ctrl => return Action::Quit,
+            KeyCode::Char('a') if ctrl => self.cursor = 0,
+            KeyCode::Char('e') if ctrl => self.cursor = self.input.len(),
+            KeyCode::Char('u') if ctrl => {
+                self.input.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            KeyCode::Char('w') if ctrl => {
+                let mut start = self.cursor;
+                while start > 0 && self.input[start - 1] == ' ' {
+                    start -= 1;
+                }
+                while start > 0 && self.input[start - 1] != ' ' {
+                    start -= 1;
+                }
+                self.input.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            KeyCode::Char(c) => {
+                self.input.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            KeyCode::Backspace if self.cursor > 0 => {
+                self.cursor -= 1;
+                self.input.remove(self.cursor);
+            }
+            KeyCode::Delete if self.cursor < self.input.len() => {
+                self.input.remove(self.cursor);
+            }
+            KeyCode::Left => self.cursor = self.cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (self.cursor + 1).min(self.input.len()),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.input.len(),
+            KeyCode::Up => self.recall_history(true),
+            KeyCode::Down => self.recall_history(false),
+            KeyCode::PageUp => {
+                self.scroll = (self.scroll + 10).min(self.lines.len().saturating_sub(1))
+            }
+            KeyCode::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            KeyCode::Enter => {
+                let line = self.input.drain(..).collect::<String>();
+                self.cursor = 0;
+                self.history_index = None;
+                self.scroll = 0;
+                if line.trim().is_empty() {
+                    return Action::None;
+                }
+                if self.history.last() != Some(&line) {
+                    self.history.push(line.clone());
+                    if self.history.len() > MAX_HISTORY {
+                        self.history.remove(0);
+                    }
+                }
+                self.mirror_command(&line);
+                return Action::Send(line);
+            }
+            _ => {}
+        }
+        Action::None
+    }
+
+    fn recall_history(&mut self, older: bool) {
+        if self.history.is_empty() {
+            return;
+        }
+        self.history_index = match (self.history_index, older) {
+            (None, true) => Some(self.history.len() - 1),
+            (None, false) => None,
+            (Some(i), true) => Some(i.saturating_sub(1)),
+            (Some(i), false) if i + 1 < self.history.len() => Some(i + 1),
+            (Some(_), false) => None,
+        };
+        self.input = self
+            .history_index
+            .map(|i| self.history[i].chars().collect())
+            .unwrap_or_default();
+        self.cursor = self.input.len();
+    }
+
+    /// Updates the mirrored context the same way the server's parser will.
+    pub fn mirror_command(&mut self, line: &str) {
+        let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let tone = match command.to_ascii_lowercase().as_str() {
+            "/say" | "/s" => Some("said"),
+            "/yell" => Some("yelled"),
+            "/laugh" | "/lol" => Some("laughed"),
+            "/whisper" | "/w" => Some("whispered"),
+            "/to_user" => {
+                if let Ok(id) = arg.trim().parse::<u32>() {
+                    self.context.target = format!("{id}<User>");
+                }
+                None
+            }
+            "/to_npc" => {
+                if let Ok(id) = arg.trim().parse::<u32>() {
+                    self.context.target = format!("{id}<Npc>");
+                }
+                None
+            }
+            "/to_world" | "/to_everyone" | "/global" => {
+                self.context.target = MirroredContext::default().target;
+                None
+            }
+            _ => None,
+        };
+        if let Some(tone) = tone {
+            self.context.tone = tone.to_owned();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn can_parse_server_lines() {
+        assert_eq!(
+            ChatLine::parse("2025-01-01 12:34:56.123456789 +00:00 #2s 3<User> yelled : Ale!"),
+            ChatLine {
+                time: "12:34:56".to_string(),
+                id: "#2s".to_string(),
+                from: "3<User>".to_string(),
+                content: "Ale!".to_string(),
+                kind: LineKind::Message {
+                    tone: "yelled".to_string(),
+                    private: false
+                },
+            }
+        );
+        assert_eq!(
+            ChatLine::parse(
+                "2025-01-01 12:34:56.1 +00:00 The World said *privately*: To 1<User>: hi"
+            )
+            .kind,
+            LineKind::Message {
+                tone: "said".to_string(),
+                private: true
+            }
+        );
+        assert_eq!(
+            ChatLine::parse("2025-01-01 12:34:56.1 +00:00 System: Welcome!").kind,
+            LineKind::System
+        );
+        assert_eq!(
+            ChatLine::parse("[history] 2025-01-01 12:34:56.1 +00:00 3<User> said : Hi").kind,
+            LineKind::History {
+                tone: "said".to_string()
+            }
+        );
+        assert_eq!(
+            ChatLine::parse(
+                "\x1b[1;33m2025-01-01 12:34:56.1 +00:00 3<User> said : @alice, ale?\x1b[0m"
+            ),
+            ChatLine {
+                time: "12:34:56".to_string(),
+                id: String::new(),
+                from: "3<User>".to_string(),
+                content: "@alice, ale?".to_string(),
+                kind: LineKind::Mention {
+                    tone: "said".to_string()
+                },
+            }
+        );
+        assert_eq!(
+            ChatLine::parse("2025-01-01 12:34:56.1 +00:00 #2t * 3<User> leans on the bar"),
+            ChatLine {
+                time: "12:34:56".to_string(),
+                id: "#2t".to_string(),
+                from: String::new(),
+                content: "3<User> leans on the bar".to_string(),
+                kind: LineKind::Action,
+            }
+        );
+        assert_eq!(ChatLine::parse("hello").kind, LineKind::Plain);
+        assert_eq!(ChatLine::parse("a 1234567é9 b c").kind, LineKind::Plain);
+    }
+
+    #[test]
+    fn mirrors_context_from_commands_and_prompts() {
+        let mut app = App::default();
+        app.mirror_command("/yell hello");
+        app.mirror_command("/to_user 4");
+        assert_eq!(
+            app.context,
+            MirroredContext {
+                target: "4<User>".to_string(),
+                tone: "yelled".to_string()
+            }
+        );
+
+        app.receive_line("whispered >");
+        assert_eq!(app.context.tone, "whispered");
+        assert!(app.lines.is_empty());
+
+        app.receive_line(
+            "2025-01-01 12:34:56.1 +00:00 System: You join The Back Room. It's empty.",
+        );
+        assert_eq!(app.context.target, "The Back Room");
+        app.receive_line("2025-01-01 12:34:56.1 +00:00 System: You leave The Back Room.");
+        assert_eq!(app.context.target, "The World");
+    }
+}
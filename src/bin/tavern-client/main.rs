@@ -0,0 +1,94 @@
+////////////////////////////////////////////////////////////////////////////////////////////////
+//! A terminal client for the Tavern chat.
+//! Messages scroll in their own pane, so incoming chatter no longer overwrites what's being
+//! typed. A status bar shows the current chat target and tone.
+//!
+//! Usage: tavern-client [address]
+//!
+
+use crossterm::{
+    event::{Event as TermEvent, EventStream, KeyEventKind},
+    execute,
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use futures::StreamExt;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use crate::app::{Action, App, ChatLine};
+
+mod app;
+mod render;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let address = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_ADDRESS.to_owned());
+    let connection = TcpStream::connect(&address).await?;
+
+    terminal::enable_raw_mode()?;
+    execute!(std::io::stdout(), EnterAlternateScreen)?;
+
+    let result = run(connection, &address).await;
+
+    // Always restore the terminal, even if the connection failed.
+    execute!(std::io::stdout(), LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    result
+}
+
+async fn run(connection: TcpStream, address: &str) -> anyhow::Result<()> {
+    let (read_half, mut write_half) = connection.into_split();
+    let mut lines = BufReader::new(read_half).lines();
+    let mut terminal_events = EventStream::new();
+    let mut stdout = std::io::stdout();
+
+    let mut app = App::default();
+    app.push_line(ChatLine::local(&format!("Connected to {address}")));
+
+    loop {
+        let (width, height) = terminal::size()?;
+        render::draw(&mut stdout, &app, width, height)?;
+
+        tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) => app.receive_line(&line),
+                Ok(None) | Err(_) => {
+                    app.push_line(ChatLine::local("Connection closed by the server. Press Esc to quit."));
+                    let (width, height) = terminal::size()?;
+                    render::draw(&mut stdout, &app, width, height)?;
+                    wait_for_quit(&mut terminal_events, &mut app).await;
+                    return Ok(());
+                }
+            },
+            Some(Ok(event)) = terminal_events.next() => {
+                if let TermEvent::Key(key) = event
+                    && key.kind == KeyEventKind::Press
+                {
+                    match app.handle_key(key) {
+                        Action::Send(line) => {
+                            write_half.write_all(format!("{line}\n").as_bytes()).await?;
+                        }
+                        Action::Quit => return Ok(()),
+                        Action::None => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn wait_for_quit(terminal_events: &mut EventStream, app: &mut App) {
+    while let Some(Ok(event)) = terminal_events.next().await {
+        if let TermEvent::Key(key) = event
+            && app.handle_key(key) == Action::Quit
+        {
+            return;
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Contains the drawing code of the terminal client.
+//! The screen is split into a scrolling message pane, a status bar and an input line.
+
+use crossterm::{
+    cursor::MoveTo,
+    queue,
+    style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor},
+    terminal::{Clear, ClearType},
+};
+use std::io::Write;
+
+use crate::app::{App, ChatLine, LineKind};
+
+const PROMPT: &str = "> ";
+
+/// A piece of a wrapped line, with the style it should be drawn in.
+struct Span {
+    text: String,
+    color: Color,
+    attribute: Attribute,
+}
+
+pub fn draw<W: Write>(out: &mut W, app: &App, width: u16, height: u16) -> std::io::Result<()> {
+    let width = width.max(10) as usize;
+    let pane_height = height.saturating_sub(2) as usize;
+
+    queue!(out, Clear(ClearType::All))?;
+
+    // Message pane, wrapped to the width of the terminal.
+    let rows = app
+        .lines
+        .iter()
+        .flat_map(|line| wrap(line_spans(line), width))
+        .collect::<Vec<_>>();
+    let end = rows.len().saturating_sub(app.scroll);
+    let start = end.saturating_sub(pane_height);
+    for (y, row) in rows[start..end].iter().enumerate() {
+        queue!(out, MoveTo(0, y as u16))?;
+        for span in row {
+            queue!(
+                out,
+                SetForegroundColor(span.color),
+                SetAttribute(span.attribute),
+                Print(&span.text),
+                SetAttribute(Attribute::Reset),
+                ResetColor
+            )?;
+        }
+    }
+
+    // Status bar.
+    let scrolled = if app.scroll > 0 {
+        format!(" | scrolled up {} lines", app.scroll)
+    } else {
+        String::new()
+    };
+    let status = format!(
+        " To: {} | Tone: {}{} | PgUp/PgDn scroll, Esc quit",
+        app.context.target, app.context.tone, scrolled
+    );
+    let status = format!("{:<width$}", status.chars().take(width).collect::<String>());
+    queue!(
+        out,
+        MoveTo(0, height.saturating_sub(2)),
+        SetAttribute(Attribute::Reverse),
+        Print(status),
+        SetAttribute(Attribute::Reset)
+    )?;
+
+    // Input line, scrolled horizontally to keep the cursor visible.
+    let visible = width.saturating_sub(PROMPT.len() + 1);
+    let offset = app.cursor.saturating_sub(visible);
+    let input = app.input[offset..].iter().take(visible).collect::<String>();
+    queue!(
+        out,
+        MoveTo(0, height.saturating_sub(1)),
+        Print(PROMPT),
+        Print(input),
+        MoveTo(
+            (PROMPT.len() + app.cursor - offset) as u16,
+            height.saturating_sub(1)
+        )
+    )?;
+
+    out.flush()
+}
+
+/// Styles a chat line according to its tone and privacy.
+fn line_spans(line: &ChatLine) -> Vec<Span> {
+    let span = |text: String, color, attribute| Span {
+        text,
+        color,
+        attribute,
+    };
+    let time = if line.id.is_empty() {
+        span(format!("{} ", line.time), Color::DarkGrey, Attribute::Reset)
+    } else {
+        span(
+            format!("{} {} ", line.time, line.id),
+            Color::DarkGrey,
+            Attribute::Reset,
+        )
+    };
+
+    match &line.kind {
+        LineKind::Plain => vec![span(line.content.clone(), Color::Grey, Attribute::Reset)],
+        LineKind::System => vec![
+            time,
+            span(
+                format!("* {}", line.content),
+                Color::Cyan,
+                Attribute::Italic,
+            ),
+        ],
+        LineKind::Action => vec![
+            time,
+            span(
+                format!("* {}", line.content),
+                Color::Green,
+                Attribute::Italic,
+            ),
+        ],
+        LineKind::Mention { tone } => vec![
+            time,
+            span(
+                format!("{} {tone}: {}", line.from, line.content),
+                Color::Yellow,
+                Attribute::Reverse,
+            ),
+        ],
+        LineKind::History { tone } => vec![
+            time,
+            span(
+                format!("{} {tone}: {}", line.from, line.content),
+                Color::DarkGrey,
+                Attribute::Italic,
+            ),
+        ],
+        LineKind::Message { tone, private } => {
+            let (color, attribute) = match (tone.as_str(), private) {
+                (_, true) => (Color::Magenta, Attribute::Reset),
+                ("yelled", _) => (Color::Red, Attribute::Bold),
+                ("laughed", _) => (Color::Yellow, Attribute::Reset),
+                ("whispered", _) => (Color::DarkGrey, Attribute::Italic),
+                _ => (Color::Reset, Attribute::Reset),
+            };
+            let privately = if *private { " privately" } else { "" };
+            vec![
+                time,
+                span(
+                    format!("{} {tone}{privately}: ", line.from),
+                    Color::Green,
+                    Attribute::Bold,
+                ),
+                span(line.content.clone(), color, attribute),
+            ]
+        }
+    }
+}
+
+/// Wraps styled spans into rows of at most `width` characters.
+fn wrap(spans: Vec<Span>, width: usize) -> Vec<Vec<Span>> {
+    let mut rows = vec![vec![]];
+    let mut used = 0;
+    for span in spans {
+        let mut chars = span.text.chars().peekable();
+        while chars.peek().is_some() {
+            if used == width {
+                rows.push(vec![]);
+                used = 0;
+            }
+            let text = chars.by_ref().take(width - used).collect::<String>();
+            used += text.chars().count();
+            rows.last_mut().unwrap().push(Span {
+                text,
+                color: span.color,
+                attribute: span.attribute,
+            });
+        }
+    }
+    rows
+}
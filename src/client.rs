@@ -0,0 +1,235 @@
+//! Contains the Bot SDK: an async client for writing tavern bots in Rust.
+//! A `TavernClient` either connects to the server's JSON port over TCP,
+//! or attaches directly to a `TavernServer`'s event channel, which is handy in tests.
+
+use anyhow::{anyhow, bail};
+use futures::Stream;
+use std::collections::BTreeMap;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpStream, tcp::OwnedWriteHalf},
+    sync::mpsc,
+};
+
+use crate::common::*;
+
+pub const CLIENT_BUFFER_LEN: usize = 100;
+
+/// Something that happened in the tavern, as seen by a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientEvent {
+    Message(Message),
+    Notification(SystemNotification),
+    /// A message replayed from the history, on connection or through `/history`.
+    History(Message),
+    /// A message was edited. Replaces the message with the same id.
+    Edit(Message),
+    Delete(MessageId),
+    Reactions {
+        id: MessageId,
+        reactions: BTreeMap<String, Vec<UserId>>,
+    },
+}
+
+#[derive(Debug)]
+enum Connection {
+    Tcp(OwnedWriteHalf),
+    Local(mpsc::Sender<Event>),
+}
+
+#[derive(Debug)]
+pub struct TavernClient {
+    id: UserId,
+    target: ChatTarget,
+    connection: Connection,
+    output_rx: mpsc::Receiver<String>,
+}
+
+impl TavernClient {
+    /// Connects to a server's JSON port (`JSON_PORT` by default) and waits for its welcome.
+    pub async fn connect(address: &str) -> anyhow::Result<Self> {
+        let (read_half, write_half) = TcpStream::connect(address).await?.into_split();
+        let (output_tx, output_rx) = mpsc::channel(CLIENT_BUFFER_LEN);
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(read_half).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if output_tx.send(line).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self::welcomed(Connection::Tcp(write_half), output_rx).await
+    }
+
+    /// Attaches to a server running in the same process, through its event channel.
+    pub async fn in_process(event_tx: mpsc::Sender<Event>) -> anyhow::Result<Self> {
+        let (output_tx, output_rx) = mpsc::channel(CLIENT_BUFFER_LEN);
+        event_tx
+            .send(Event::NewLocalClient { output_tx })
+            .await
+            .map_err(|_| anyhow!("The server has shut down"))?;
+
+        Self::welcomed(Connection::Local(event_tx), output_rx).await
+    }
+
+    /// The server always welcomes a client first, which tells it its own ID.
+    async fn welcomed(
+        connection: Connection,
+        mut output_rx: mpsc::Receiver<String>,
+    ) -> anyhow::Result<Self> {
+        let id = match output_rx
+            .recv()
+            .await
+            .map(|line| serde_json::from_str(&line))
+        {
+            Some(Ok(ServerOutput::Notification(welcome))) => welcome.to,
+            _ => bail!("The server didn't send a welcome notification"),
+        };
+
+        Ok(TavernClient {
+            id,
+            target: ChatTarget::Global,
+            connection,
+            output_rx,
+        })
+    }
+
+    pub fn id(&self) -> UserId {
+        self.id
+    }
+
+    pub fn target(&self) -> ChatTarget {
+        self.target
+    }
+
+    /// Sends a line exactly as if it was typed by a user, commands included.
+    pub async fn send_raw(&mut self, line: &str) -> anyhow::Result<()> {
+        match &mut self.connection {
+            Connection::Tcp(write_half) => {
+                write_half.write_all(format!("{line}\n").as_bytes()).await?
+            }
+            Connection::Local(event_tx) => event_tx
+                .send(Event::ReceiveUserMessage {
+                    from: self.id,
+                    message_raw: line.to_owned(),
+                })
+                .await
+                .map_err(|_| anyhow!("The server has shut down"))?,
+        }
+        Ok(())
+    }
+
+    /// Says something to the current target.
+    pub async fn say(&mut self, text: &str) -> anyhow::Result<()> {
+        self.send_raw(&format!("/say {text}")).await
+    }
+
+    /// Whispers something to a user, without changing the current target.
+    pub async fn whisper(&mut self, to: UserId, text: &str) -> anyhow::Result<()> {
+        self.send_raw(&format!("/tell {} {text}", to.0)).await
+    }
+
+    /// Changes who `say` talks to. Like for users, invalid targets are ignored by the server.
+    /// Targeting a room moves the client into it, and so does targeting a booth it may enter.
+    pub async fn set_target(&mut self, target: ChatTarget) -> anyhow::Result<()> {
+        let command = match target {
+            ChatTarget::Global => "/global".to_owned(),
+            ChatTarget::User(id) => format!("/to_user {}", id.0),
+            ChatTarget::Npc(id) => format!("/to_npc {}", id.0),
+            ChatTarget::Room(id) => format!("/join {}", id.0),
+            ChatTarget::Booth(id) => format!("/booth join {}", id.0),
+        };
+        self.send_raw(&command).await?;
+        self.target = target;
+        Ok(())
+    }
+
+    /// Waits for the next event. Returns None once disconnected from the server.
+    pub async fn next_event(&mut self) -> Option<ClientEvent> {
+        loop {
+            let line = self.output_rx.recv().await?;
+            match serde_json::from_str(&line) {
+                Ok(ServerOutput::Message(message)) => return Some(ClientEvent::Message(message)),
+                Ok(ServerOutput::Notification(notification)) => {
+                    return Some(ClientEvent::Notification(notification));
+                }
+                Ok(ServerOutput::History(message)) => return Some(ClientEvent::History(message)),
+                Ok(ServerOutput::Edit(message)) => return Some(ClientEvent::Edit(message)),
+                Ok(ServerOutput::Delete { id }) => return Some(ClientEvent::Delete(id)),
+                Ok(ServerOutput::Reactions { id, reactions }) => {
+                    return Some(ClientEvent::Reactions { id, reactions });
+                }
+                // Output this client doesn't understand, say from a newer server, is skipped.
+                Err(_) => {}
+            }
+        }
+    }
+
+    /// A stream of events, which ends once disconnected from the server.
+    pub fn events(&mut self) -> impl Stream<Item = ClientEvent> + Unpin + '_ {
+        Box::pin(futures::stream::unfold(self, |client| async move {
+            client.next_event().await.map(|event| (event, client))
+        }))
+    }
+}
+
+impl Drop for TavernClient {
+    fn drop(&mut self) {
+        // TCP connections close on their own, but the server must be told about local clients.
+        if let Connection::Local(event_tx) = &self.connection {
+            let _ = event_tx.try_send(Event::DisconnectClient { id: self.id });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::TavernServer;
+    use futures::StreamExt;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    /// Waits for the next message sent by someone else.
+    async fn next_message(client: &mut TavernClient) -> Message {
+        let me = Some(ChatTarget::User(client.id()));
+        let mut events = client.events();
+        loop {
+            match timeout(Duration::from_secs(1), events.next()).await {
+                Ok(Some(ClientEvent::Message(message)))
+                    if message.from.is_some() && message.from != me =>
+                {
+                    return message;
+                }
+                Ok(Some(_)) => {}
+                Ok(None) | Err(_) => panic!("Expected a message"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn in_process_clients_can_talk() {
+        let (mut server, event_tx) = TavernServer::builder().without_listeners().build();
+        let server = tokio::spawn(async move { server.run().await });
+
+        let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+        let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+        assert_ne!(alice.id(), bob.id());
+
+        alice.say("Evening, all.").await.unwrap();
+        let message = next_message(&mut bob).await;
+        assert_eq!(message.from, Some(ChatTarget::User(alice.id())));
+        assert_eq!(message.to, ChatTarget::Global);
+        assert_eq!(message.content, "Evening, all.");
+
+        bob.whisper(alice.id(), "Psst.").await.unwrap();
+        let message = next_message(&mut alice).await;
+        assert_eq!(message.to, ChatTarget::User(alice.id()));
+        assert_eq!(message.tone, MessageTone::WHISPERED);
+        assert_eq!(bob.target(), ChatTarget::Global);
+
+        event_tx.send(Event::Shutdown).await.unwrap();
+        assert!(server.await.unwrap().is_ok());
+    }
+}
@@ -2,35 +2,194 @@
 //! Structs and enum here should be simple. More complex types,
 //! or types with more complex behavior should have their dedicated file.
 
+use crate::accounts::PasswordHash;
+use crate::dice::{DiceExpression, DiceRoll};
+use crate::games::GameKind;
+use crate::http::ApiRequest;
+use crate::irc::IrcSession;
+use crate::search::SearchQuery;
+use crate::transcript::TranscriptQuery;
 use chrono::{DateTime, Local};
-use std::{fmt::Display, net::SocketAddr, time::SystemTime};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt::Display,
+    net::SocketAddr,
+    str::FromStr,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 use thiserror::Error;
-use tokio::net::{TcpStream, tcp::OwnedWriteHalf};
+use tokio::{
+    net::{TcpStream, tcp::OwnedWriteHalf},
+    sync::mpsc,
+};
 
 pub type ServerResult = Result<(), ServerError>;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+/// Identifies a message. Assigned by the server in increasing order, and shown in base 36
+/// to keep it short, e.g. `#2s`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct MessageId(pub u64);
+impl Display for MessageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut digits = vec![];
+        let mut n = self.0;
+        loop {
+            digits.push(std::char::from_digit((n % 36) as u32, 36).unwrap_or('0'));
+            n /= 36;
+            if n == 0 {
+                break;
+            }
+        }
+        write!(f, "#{}", digits.iter().rev().collect::<String>())
+    }
+}
+impl FromStr for MessageId {
+    type Err = std::num::ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u64::from_str_radix(s.trim().trim_start_matches('#'), 36).map(MessageId)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct UserId(pub u32);
 impl Display for UserId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}<User>", self.0)
     }
 }
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct NpcId(pub u32);
 impl Display for NpcId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}<Npc>", self.0)
     }
 }
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct RoomId(pub u32);
+impl Display for RoomId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}<Room>", self.0)
+    }
+}
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct BoothId(pub u32);
+impl Display for BoothId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}<Booth>", self.0)
+    }
+}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Longest quote of the parent message shown with a reply, in characters.
+pub const QUOTE_LEN: usize = 40;
+/// Shown in place of the quote of a parent message that was deleted.
+pub const DELETED_QUOTE: &str = "(deleted)";
+
+/// Marks the lines of messages replayed from the history, for plain text clients.
+pub const HISTORY_MARKER: &str = "[history]";
+/// Surround messages shown to plain text clients mentioned in them, to highlight them.
+pub const MENTION_START: &str = "\x1b[1;33m";
+pub const MENTION_END: &str = "\x1b[0m";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Message {
+    /// Assigned by the server to messages sent by someone, when they're broadcast.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<MessageId>,
     pub from: Option<ChatTarget>,
     pub to: ChatTarget,
     pub content: String,
+    #[serde(with = "unix_millis")]
     pub timestamp: SystemTime,
     pub tone: MessageTone,
+    #[serde(default)]
+    pub kind: MessageKind,
+    /// Patrons mentioned with `@name`, found by the server when the message is sent.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mentions: Vec<UserId>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub edited: bool,
+    /// Who reacted to the message, by reaction.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reactions: BTreeMap<String, Vec<UserId>>,
+    /// The message this one replies to, filled in by the server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<ReplyTo>,
+    /// The only patrons who heard a message sent to the whole tavern, such as a whisper.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heard_by: Option<Vec<UserId>>,
+    /// Set for emotes, whose content is what everyone but the actor and target read.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emote: Option<EmoteView>,
+    /// Set for dice rolls, which the server makes for their author.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roll: Option<DiceRoll>,
+}
+
+/// How an emote reads to the patron doing it and to its target.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmoteView {
+    pub name: String,
+    pub actor_line: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<UserId>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_line: Option<String>,
+}
+
+/// The message a reply answers, with a short quote of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplyTo {
+    pub id: MessageId,
+    /// Name of the parent's author.
+    pub from: String,
+    pub quote: String,
+}
+
+impl ReplyTo {
+    /// Quotes the start of the parent's first line.
+    pub fn new(id: MessageId, from: &str, content: &str) -> Self {
+        let mut quote = content.lines().next().unwrap_or_default().to_owned();
+        if quote.chars().count() > QUOTE_LEN || content.lines().nth(1).is_some() {
+            quote = quote.chars().take(QUOTE_LEN).collect::<String>() + "…";
+        }
+        Self {
+            id,
+            from: from.to_owned(),
+            quote,
+        }
+    }
+
+    /// Drops the quote of a parent message that was deleted.
+    pub fn forget_quote(&mut self) {
+        self.quote = DELETED_QUOTE.to_owned();
+    }
+}
+
+impl Display for ReplyTo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "↪ {} {}: \"{}\"", self.id, self.from, self.quote)
+    }
+}
+
+/// (De)serializes a `SystemTime` as milliseconds since the unix epoch.
+pub(crate) mod unix_millis {
+    use super::*;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        let millis = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        serializer.serialize_u64(millis)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        Ok(UNIX_EPOCH + Duration::from_millis(u64::deserialize(deserializer)?))
+    }
 }
 
 impl Message {
@@ -41,27 +200,142 @@ impl Message {
         tone: Option<MessageTone>,
     ) -> Self {
         Message {
+            id: None,
             from,
             to,
             content: content.to_owned(),
             timestamp: SystemTime::now(),
             tone: tone.unwrap_or_default(),
+            kind: Default::default(),
+            mentions: vec![],
+            edited: false,
+            reactions: Default::default(),
+            reply_to: None,
+            heard_by: None,
+            emote: None,
+            roll: None,
         }
     }
 
+    /// Returns true if a patron heard the message, unless it was kept to a few patrons.
+    pub fn heard(&self, id: UserId) -> bool {
+        self.heard_by
+            .as_ref()
+            .is_none_or(|heard_by| heard_by.contains(&id))
+    }
+
+    /// The message as a patron reads it. Emotes read differently to their actor and target.
+    pub fn seen_by(&self, id: UserId) -> Cow<'_, Message> {
+        let Some(emote) = &self.emote else {
+            return Cow::Borrowed(self);
+        };
+        let line = if self.from == Some(ChatTarget::User(id)) {
+            &emote.actor_line
+        } else if let (Some(target), Some(line)) = (emote.target, &emote.target_line)
+            && target == id
+        {
+            line
+        } else {
+            return Cow::Borrowed(self);
+        };
+        let mut message = self.clone();
+        message.content = line.clone();
+        Cow::Owned(message)
+    }
+
+    /// Adds a reaction, or takes it back if it was already there.
+    pub fn toggle_reaction(&mut self, by: UserId, reaction: &str) {
+        let reacted = self.reactions.entry(reaction.to_owned()).or_default();
+        match reacted.iter().position(|id| *id == by) {
+            Some(position) => {
+                reacted.remove(position);
+                if reacted.is_empty() {
+                    self.reactions.remove(reaction);
+                }
+            }
+            None => reacted.push(by),
+        }
+    }
+
+    /// Sums up the reactions to a message, e.g. `🍺 3 · ha 1`.
+    pub fn reaction_summary(&self) -> String {
+        self.reactions
+            .iter()
+            .map(|(reaction, by)| format!("{reaction} {}", by.len()))
+            .collect::<Vec<_>>()
+            .join(" · ")
+    }
+
+    /// Renders a message for a plain text client mentioned in it, highlighted.
+    pub fn to_mention_output(&self, is_private: bool) -> String {
+        format!(
+            "{MENTION_START}{}{MENTION_END}\n",
+            self.to_output(is_private).trim_end()
+        )
+    }
+
+    /// Renders a message replayed from the history, marked as such.
+    pub fn to_history_output(&self, is_private: bool) -> String {
+        format!("{HISTORY_MARKER} {}", self.to_output(is_private))
+    }
+
     pub fn to_output(&self, is_private: bool) -> String {
+        let from = self.from.unwrap_or_default();
+        // Actions and emotes read as a sentence, e.g. `* Alice leans on the bar`.
+        let (speaker, privately) = match self.kind {
+            MessageKind::Speech => (
+                format!(
+                    "{from} {} {}: ",
+                    self.tone,
+                    if is_private { "*privately*" } else { "" }
+                ),
+                "",
+            ),
+            MessageKind::Action | MessageKind::Roll => (format!("* {from} "), " *privately*"),
+            MessageKind::Emote => ("* ".to_owned(), " *privately*"),
+        };
         format!(
-            "{} {} {} {}: {}\n",
+            "{} {}{speaker}{}{}{}{}{}\n",
             DateTime::<Local>::from(self.timestamp),
-            self.from.unwrap_or_default(),
-            self.tone.clone(),
-            is_private.then_some("*privately*").unwrap_or(""),
-            self.content
+            self.id.map(|id| format!("{id} ")).unwrap_or_default(),
+            self.reply_to
+                .as_ref()
+                .map(|reply_to| format!("({reply_to}) "))
+                .unwrap_or_default(),
+            self.content,
+            if is_private { privately } else { "" },
+            if self.edited { " (edited)" } else { "" },
+            if self.reactions.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", self.reaction_summary())
+            }
         )
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Something done to keep order in the tavern, such as kicking a patron out of a booth.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModerationAction {
+    pub by: Option<ChatTarget>,
+    pub action: String,
+    pub target: ChatTarget,
+    #[serde(with = "unix_millis")]
+    pub timestamp: SystemTime,
+}
+
+impl ModerationAction {
+    pub fn new(by: Option<ChatTarget>, action: &str, target: ChatTarget) -> Self {
+        Self {
+            by,
+            action: action.to_owned(),
+            target,
+            timestamp: SystemTime::now(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct SystemNotification {
     pub to: UserId,
     pub content: String,
@@ -76,11 +350,44 @@ impl SystemNotification {
     }
 }
 
+/// A single line sent to clients speaking the JSON protocol.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerOutput {
+    Message(Message),
+    Notification(SystemNotification),
+    /// A message sent before the client asked for it, replayed from the history.
+    History(Message),
+    /// A message changed by its author. Replaces the message with the same id.
+    Edit(Message),
+    Delete {
+        id: MessageId,
+    },
+    /// The reactions to a message changed.
+    Reactions {
+        id: MessageId,
+        reactions: BTreeMap<String, Vec<UserId>>,
+    },
+}
+
+impl ServerOutput {
+    pub fn to_json_line(&self) -> String {
+        let mut line = serde_json::to_string(self).unwrap_or_default();
+        line.push('\n');
+        line
+    }
+}
+
 #[derive(Debug)]
 pub enum Event {
     NewClient {
         connection: TcpStream,
         addr: SocketAddr,
+        protocol: ClientProtocol,
+    },
+    /// A client living in the same process, which receives JSON lines over a channel.
+    NewLocalClient {
+        output_tx: mpsc::Sender<String>,
     },
     DisconnectClient {
         id: UserId,
@@ -99,16 +406,246 @@ pub enum Event {
     NotifyClient {
         notification: SystemNotification,
     },
+    ApiRequest {
+        request: ApiRequest,
+        connection: TcpStream,
+        bot: NpcId,
+    },
+    /// A player of the game played there may have taken too long, unless the game moved on.
+    GameTimeout {
+        place: ChatTarget,
+        turn: u64,
+    },
+    /// The trivia question may have timed out, or the next one be due, unless trivia moved on.
+    TriviaTimeout {
+        round: u64,
+    },
+    /// A command that needs the server's state to be answered.
+    Command {
+        from: UserId,
+        command: ServerCommand,
+    },
+    /// The password of a new account was hashed, away from the event loop.
+    PasswordHashed {
+        from: UserId,
+        name: String,
+        password: PasswordHash,
+    },
+    /// The password given to log into an account was checked, away from the event loop.
+    /// Passwords hashed the way they were before they were stretched come back hashed anew.
+    LoginChecked {
+        from: UserId,
+        name: String,
+        valid: bool,
+        rehashed: Option<PasswordHash>,
+    },
     Shutdown,
 }
 
+/// Commands typed by users, which are handled by the server rather than the parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerCommand {
+    /// Moves to a room, given its name or ID.
+    JoinRoom(String),
+    LeaveRoom,
+    ListRooms,
+    Booth(BoothCommand),
+    /// Replays the last messages the patron is allowed to see, or a default number of them.
+    History(Option<usize>),
+    /// Searches the history for messages the patron may see.
+    Search(SearchQuery),
+    /// Gives admin rights to the patron, if the password is right.
+    Admin(Password),
+    /// Exports a transcript of the history to a file. Admins only.
+    Export(TranscriptQuery),
+    /// Creates an account, and logs into it.
+    Register {
+        name: String,
+        password: Password,
+    },
+    Login {
+        name: String,
+        password: Password,
+    },
+    Mail(MailCommand),
+    /// Replays the messages of the history mentioning the patron.
+    Mentions,
+    /// Changes the content of a message. Only its author and admins may do so.
+    Edit {
+        id: MessageId,
+        content: String,
+    },
+    Delete(MessageId),
+    /// Reacts to a message with an emoji or a word, or takes the reaction back.
+    React {
+        id: MessageId,
+        reaction: String,
+    },
+    /// Does an emote, at someone if given their name or ID.
+    Emote {
+        name: String,
+        target: Option<String>,
+    },
+    /// Lists the emotes.
+    Emotes,
+    /// Speaks in a tone from now on, given its name or command. Lists the tones without one.
+    Tone(Option<String>),
+    /// Replies to a message, where it was sent.
+    Reply {
+        id: MessageId,
+        content: String,
+    },
+    /// Replays a message with every reply to it, and every reply to those.
+    Thread(MessageId),
+    Roll(RollCommand),
+    Game(GameCommand),
+    Trivia(TriviaCommand),
+    /// Lists the patrons of the tavern.
+    Who,
+    /// Describes a patron, given their name or ID.
+    Whois(String),
+}
+
+/// A password typed in a command. It's hidden when debugged, so it never ends up in the logs.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Password(pub String);
+
+impl std::fmt::Debug for Password {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Password(***)")
+    }
+}
+
+impl std::ops::Deref for Password {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Commands managing private booths. Invites and kicks apply to the booth currently talked to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BoothCommand {
+    Create {
+        name: String,
+        password: Option<Password>,
+    },
+    /// Joins a booth, given its name or ID. Members just start talking to it again.
+    Join {
+        name: String,
+        password: Option<Password>,
+    },
+    Invite(String),
+    Kick(String),
+    /// Leaves a booth, given its name or ID. Without one, leaves the booth talked to, or the
+    /// only booth the patron is in.
+    Leave(Option<String>),
+    List,
+}
+
+/// Commands rolling the server's dice, or checking them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RollCommand {
+    /// Rolls dice for everyone the patron talks to.
+    Dice(DiceExpression),
+    /// Shows the seal of the dice.
+    Seal,
+    /// Reveals the seed of the dice, so past rolls can be checked, and seals new dice.
+    Reveal,
+}
+
+/// Commands playing the game where the patron is, with the others there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GameCommand {
+    Start(GameKind),
+    Join,
+    Roll,
+    /// Keeps dice when rolling again, by their position from 1.
+    Hold(Vec<usize>),
+    /// Ends a turn early.
+    Stand,
+    /// Describes the game being played.
+    Status,
+}
+
+/// Commands about the trivia run by the quizmaster.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TriviaCommand {
+    /// Repeats the question waiting for an answer.
+    Question,
+    /// Shows the patrons with the most points.
+    Top,
+}
+
+/// Commands managing the mailbox of a registered patron. Mails are numbered from 1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MailCommand {
+    List,
+    Read(usize),
+    Delete(usize),
+    /// Sends a direct message to an account, left in its mailbox if its owner isn't here.
+    Send {
+        to: String,
+        content: String,
+    },
+}
+
 impl PartialEq for Event {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Self::NewClient { addr: l_addr, .. }, Self::NewClient { addr: r_addr, .. }) => {
                 l_addr == r_addr
             }
-            (left, right) => left == right,
+            (Self::DisconnectClient { id: l_id }, Self::DisconnectClient { id: r_id }) => {
+                l_id == r_id
+            }
+            (
+                Self::ReceiveUserMessage {
+                    from: l_from,
+                    message_raw: l_raw,
+                },
+                Self::ReceiveUserMessage {
+                    from: r_from,
+                    message_raw: r_raw,
+                },
+            ) => l_from == r_from && l_raw == r_raw,
+            // Timestamps are assigned when the message is created, so they are not compared.
+            (Self::BroadcastMessage { message: l }, Self::BroadcastMessage { message: r }) => {
+                l.from == r.from && l.to == r.to && l.content == r.content && l.tone == r.tone
+            }
+            (
+                Self::ChangeTarget { id: l_id, to: l_to },
+                Self::ChangeTarget { id: r_id, to: r_to },
+            ) => l_id == r_id && l_to == r_to,
+            (Self::NotifyClient { notification: l }, Self::NotifyClient { notification: r }) => {
+                l == r
+            }
+            (
+                Self::Command {
+                    from: l_from,
+                    command: l_command,
+                },
+                Self::Command {
+                    from: r_from,
+                    command: r_command,
+                },
+            ) => l_from == r_from && l_command == r_command,
+            (
+                Self::GameTimeout {
+                    place: l_place,
+                    turn: l_turn,
+                },
+                Self::GameTimeout {
+                    place: r_place,
+                    turn: r_turn,
+                },
+            ) => l_place == r_place && l_turn == r_turn,
+            (Self::TriviaTimeout { round: l_round }, Self::TriviaTimeout { round: r_round }) => {
+                l_round == r_round
+            }
+            (Self::Shutdown, Self::Shutdown) => true,
+            _ => false,
         }
     }
 }
@@ -134,16 +671,56 @@ impl std::fmt::Display for ServerError {
 
 #[derive(Debug)]
 pub struct Client {
-    pub send_tx: OwnedWriteHalf,
+    pub send_tx: ClientWriter,
     pub context: ClientContext,
+    pub protocol: ClientProtocol,
+    pub connected_at: SystemTime,
+    /// The last time the client sent anything.
+    pub last_active: Instant,
+    /// Set once the client gave the admin password.
+    pub is_admin: bool,
+    /// The account the client logged into, if any.
+    pub account: Option<String>,
+    /// Set while a password the client gave is being hashed or checked.
+    pub checking_password: bool,
+    /// Wrong passwords the client gave to log in.
+    pub failed_logins: u32,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+/// Where output for a client is written to.
+#[derive(Debug)]
+pub enum ClientWriter {
+    Tcp(OwnedWriteHalf),
+    /// An in-process client. Output is dropped and the client disconnected if it falls behind.
+    Channel(mpsc::Sender<String>),
+}
+
+/// The wire protocol a client speaks.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum ClientProtocol {
+    /// Plain text lines, as typed in `nc` or `telnet`.
+    #[default]
+    Plain,
+    /// One `ServerOutput` JSON object per line, for bots and other programs.
+    Json,
+    Irc(IrcSession),
+    /// A Server-Sent Events stream opened through the HTTP API by a bot.
+    EventStream {
+        bot: NpcId,
+    },
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ChatTarget {
     #[default]
     Global,
     User(UserId),
     Npc(NpcId),
+    /// Everyone currently in a room of the tavern.
+    Room(RoomId),
+    /// The members of a private booth.
+    Booth(BoothId),
 }
 
 impl ChatTarget {
@@ -154,6 +731,14 @@ impl ChatTarget {
     pub fn npc(id: u32) -> Self {
         Self::Npc(NpcId(id))
     }
+
+    pub fn room(id: u32) -> Self {
+        Self::Room(RoomId(id))
+    }
+
+    pub fn booth(id: u32) -> Self {
+        Self::Booth(BoothId(id))
+    }
 }
 
 impl Display for ChatTarget {
@@ -162,6 +747,8 @@ impl Display for ChatTarget {
             ChatTarget::Global => write!(f, "The World"),
             ChatTarget::User(id) => write!(f, "{id}"),
             ChatTarget::Npc(id) => write!(f, "{id}"),
+            ChatTarget::Room(id) => write!(f, "{id}"),
+            ChatTarget::Booth(id) => write!(f, "{id}"),
         }
     }
 }
@@ -171,26 +758,54 @@ impl Display for ChatTarget {
 pub struct ClientContext {
     pub current_target: ChatTarget,
     pub tone: MessageTone,
+    pub nickname: Option<String>,
+    /// Set while the client is away, with the reason given, which may be empty.
+    pub away: Option<String>,
 }
 
-/// The emotion that's paired with this message
-#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
-pub enum MessageTone {
+/// How a message reads: said by its author, or done by them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
     #[default]
-    Said,
-    Yelled,
-    Laughed,
-    Whispered,
+    Speech,
+    /// Something the author does, e.g. `/me leans on the bar and sighs`.
+    Action,
+    /// An emote such as `/wave`, whose content already names who does it.
+    Emote,
+    /// Dice rolled by the server for the author, e.g. `rolls 2d6+3: [4, 2] + 3 = 9 (roll 1)`.
+    Roll,
+}
+
+/// The emotion that's paired with this message, named after how it was spoken, e.g. `yelled`.
+/// Tones besides the built-in ones are configured, see `crate::tones`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct MessageTone(pub Cow<'static, str>);
+
+impl MessageTone {
+    pub const SAID: MessageTone = MessageTone(Cow::Borrowed("said"));
+    pub const YELLED: MessageTone = MessageTone(Cow::Borrowed("yelled"));
+    pub const LAUGHED: MessageTone = MessageTone(Cow::Borrowed("laughed"));
+    pub const WHISPERED: MessageTone = MessageTone(Cow::Borrowed("whispered"));
+
+    pub fn new(name: &str) -> Self {
+        Self(Cow::Owned(name.to_lowercase()))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Default for MessageTone {
+    fn default() -> Self {
+        Self::SAID
+    }
 }
 
 impl Display for MessageTone {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let s = match self {
-            MessageTone::Said => "said",
-            MessageTone::Yelled => "yelled",
-            MessageTone::Laughed => "laughed",
-            MessageTone::Whispered => "whispered",
-        };
-        write!(f, "{s}")
+        write!(f, "{}", self.0)
     }
 }
@@ -0,0 +1,328 @@
+//! Contains the configuration of a `TavernServer`, and the builder used to create one.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+
+use crate::accounts::MAILBOX_LEN;
+use crate::common::Event;
+use crate::games::GAME_TURN_TIMEOUT;
+use crate::http::{ApiTokens, HTTP_PORT};
+use crate::irc::IRC_PORT;
+use crate::journal::JOURNAL_MAX_LEN;
+use crate::rooms::DEFAULT_ROOMS;
+use crate::server::{
+    HISTORY_REPLAY_LEN, IDLE_AFTER, JSON_PORT, MESSAGE_HISTORY_LEN, TCP_PORT, TavernServer,
+};
+use crate::trivia::TRIVIA_INTERVAL;
+
+/// The admin password is read from this environment variable.
+pub const ADMIN_PASSWORD_ENV: &str = "TAVERN_ADMIN_PASSWORD";
+pub const EXPORT_DIR: &str = "transcripts";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerConfig {
+    /// Listener addresses. A listener is disabled if it has no address.
+    pub plain_address: Option<String>,
+    pub json_address: Option<String>,
+    pub irc_address: Option<String>,
+    pub http_address: Option<String>,
+    /// Each API token speaks as its own bot NPC. The HTTP API is disabled without tokens.
+    pub api_tokens: ApiTokens,
+    /// Number of messages kept in the server's message log.
+    pub history_len: usize,
+    /// Number of global messages replayed to clients when they connect.
+    pub replay_len: usize,
+    /// Directory where messages are journaled, so history survives restarts.
+    /// Nothing is written to disk without one.
+    pub journal_dir: Option<PathBuf>,
+    /// Journal files are rotated once they grow past this length, in bytes.
+    pub journal_max_len: u64,
+    /// Directory where transcripts exported by admins are written.
+    pub export_dir: PathBuf,
+    /// File where registered accounts and their mailboxes are saved.
+    /// Accounts are forgotten when the server stops without one.
+    pub accounts_file: Option<PathBuf>,
+    /// Number of mails each mailbox holds.
+    pub mailbox_len: usize,
+    /// JSON file defining the emotes. The default emotes are used without one.
+    pub emotes_file: Option<PathBuf>,
+    /// JSON file defining the tones. The default tones are used without one.
+    pub tones_file: Option<PathBuf>,
+    /// Patrons giving this password with `/admin` become admins. Nobody can without one.
+    pub admin_password: Option<String>,
+    /// Patrons are shown as idle after this long without sending anything.
+    pub idle_after: Duration,
+    /// Players of a game who don't play their turn for this long are skipped.
+    pub game_turn_timeout: Duration,
+    /// The quizmaster runs trivia in this room, which is created if it doesn't exist yet.
+    /// There's no trivia without one.
+    pub trivia_room: Option<String>,
+    /// JSON file holding the trivia questions. The default questions are used without one.
+    pub trivia_file: Option<PathBuf>,
+    /// File where the trivia leaderboard, kept by account, is saved. Scores are forgotten
+    /// without one.
+    pub trivia_scores_file: Option<PathBuf>,
+    /// How long patrons have to answer a trivia question, and wait for the next one.
+    pub trivia_interval: Duration,
+    /// Names of the rooms of the tavern.
+    pub rooms: Vec<String>,
+    /// The NPCs present when the tavern opens.
+    pub npcs: Vec<NpcConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NpcConfig {
+    pub name: String,
+    /// The room the NPC sits in. NPCs without a room can be talked to from anywhere.
+    pub room: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            plain_address: Some(TCP_PORT.to_owned()),
+            json_address: Some(JSON_PORT.to_owned()),
+            irc_address: Some(IRC_PORT.to_owned()),
+            http_address: Some(HTTP_PORT.to_owned()),
+            api_tokens: Default::default(),
+            history_len: MESSAGE_HISTORY_LEN,
+            replay_len: HISTORY_REPLAY_LEN,
+            journal_dir: None,
+            journal_max_len: JOURNAL_MAX_LEN,
+            export_dir: PathBuf::from(EXPORT_DIR),
+            accounts_file: None,
+            mailbox_len: MAILBOX_LEN,
+            emotes_file: None,
+            tones_file: None,
+            admin_password: None,
+            idle_after: IDLE_AFTER,
+            game_turn_timeout: GAME_TURN_TIMEOUT,
+            trivia_room: None,
+            trivia_file: None,
+            trivia_scores_file: None,
+            trivia_interval: TRIVIA_INTERVAL,
+            rooms: DEFAULT_ROOMS.map(str::to_owned).to_vec(),
+            npcs: Default::default(),
+        }
+    }
+}
+
+/// Builds a `TavernServer`. Every listener is enabled on its default address unless changed.
+#[derive(Debug, Clone, Default)]
+pub struct TavernServerBuilder {
+    config: ServerConfig,
+}
+
+impl TavernServerBuilder {
+    pub fn plain_address(mut self, address: Option<&str>) -> Self {
+        self.config.plain_address = address.map(str::to_owned);
+        self
+    }
+
+    pub fn json_address(mut self, address: Option<&str>) -> Self {
+        self.config.json_address = address.map(str::to_owned);
+        self
+    }
+
+    pub fn irc_address(mut self, address: Option<&str>) -> Self {
+        self.config.irc_address = address.map(str::to_owned);
+        self
+    }
+
+    pub fn http_address(mut self, address: Option<&str>) -> Self {
+        self.config.http_address = address.map(str::to_owned);
+        self
+    }
+
+    /// Disables every listener, so clients can only attach in-process.
+    pub fn without_listeners(self) -> Self {
+        self.plain_address(None)
+            .json_address(None)
+            .irc_address(None)
+            .http_address(None)
+    }
+
+    pub fn api_tokens(mut self, api_tokens: ApiTokens) -> Self {
+        self.config.api_tokens = api_tokens;
+        self
+    }
+
+    pub fn history_len(mut self, history_len: usize) -> Self {
+        self.config.history_len = history_len;
+        self
+    }
+
+    pub fn replay_len(mut self, replay_len: usize) -> Self {
+        self.config.replay_len = replay_len;
+        self
+    }
+
+    pub fn journal_dir(mut self, dir: Option<&Path>) -> Self {
+        self.config.journal_dir = dir.map(Path::to_owned);
+        self
+    }
+
+    pub fn journal_max_len(mut self, max_len: u64) -> Self {
+        self.config.journal_max_len = max_len;
+        self
+    }
+
+    pub fn export_dir(mut self, dir: &Path) -> Self {
+        self.config.export_dir = dir.to_owned();
+        self
+    }
+
+    pub fn accounts_file(mut self, path: Option<&Path>) -> Self {
+        self.config.accounts_file = path.map(Path::to_owned);
+        self
+    }
+
+    pub fn mailbox_len(mut self, mailbox_len: usize) -> Self {
+        self.config.mailbox_len = mailbox_len;
+        self
+    }
+
+    pub fn emotes_file(mut self, path: Option<&Path>) -> Self {
+        self.config.emotes_file = path.map(Path::to_owned);
+        self
+    }
+
+    pub fn tones_file(mut self, path: Option<&Path>) -> Self {
+        self.config.tones_file = path.map(Path::to_owned);
+        self
+    }
+
+    pub fn admin_password(mut self, password: Option<&str>) -> Self {
+        self.config.admin_password = password.map(str::to_owned);
+        self
+    }
+
+    pub fn idle_after(mut self, idle_after: Duration) -> Self {
+        self.config.idle_after = idle_after;
+        self
+    }
+
+    pub fn game_turn_timeout(mut self, timeout: Duration) -> Self {
+        self.config.game_turn_timeout = timeout;
+        self
+    }
+
+    pub fn trivia_room(mut self, room: Option<&str>) -> Self {
+        self.config.trivia_room = room.map(str::to_owned);
+        self
+    }
+
+    pub fn trivia_file(mut self, path: Option<&Path>) -> Self {
+        self.config.trivia_file = path.map(Path::to_owned);
+        self
+    }
+
+    pub fn trivia_scores_file(mut self, path: Option<&Path>) -> Self {
+        self.config.trivia_scores_file = path.map(Path::to_owned);
+        self
+    }
+
+    pub fn trivia_interval(mut self, interval: Duration) -> Self {
+        self.config.trivia_interval = interval;
+        self
+    }
+
+    /// Adds a room to the tavern, next to the existing ones.
+    pub fn room(mut self, name: &str) -> Self {
+        self.config.rooms.push(name.to_owned());
+        self
+    }
+
+    /// Adds an NPC to the tavern, outside of any room.
+    pub fn npc(mut self, name: &str) -> Self {
+        self.config.npcs.push(NpcConfig {
+            name: name.to_owned(),
+            room: None,
+        });
+        self
+    }
+
+    /// Adds an NPC sitting in a room. The room is created if it doesn't exist yet.
+    pub fn npc_in_room(mut self, name: &str, room: &str) -> Self {
+        self.config.npcs.push(NpcConfig {
+            name: name.to_owned(),
+            room: Some(room.to_owned()),
+        });
+        self
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    pub fn build(self) -> (TavernServer, mpsc::Sender<Event>) {
+        TavernServer::with_config(self.config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn builder_overrides_defaults() {
+        let builder = TavernServerBuilder::default()
+            .without_listeners()
+            .json_address(Some("127.0.0.1:9000"))
+            .history_len(5)
+            .journal_dir(Some(Path::new("journal")))
+            .admin_password(Some("hunter2"))
+            .idle_after(Duration::from_secs(60))
+            .game_turn_timeout(Duration::from_secs(30))
+            .trivia_room(Some("The Cellar"))
+            .room("The Cellar")
+            .npc("Barkeep")
+            .npc_in_room("Bard", "The Fireplace Table");
+
+        assert_eq!(
+            builder.config(),
+            &ServerConfig {
+                plain_address: None,
+                json_address: Some("127.0.0.1:9000".to_string()),
+                irc_address: None,
+                http_address: None,
+                api_tokens: Default::default(),
+                history_len: 5,
+                replay_len: HISTORY_REPLAY_LEN,
+                journal_dir: Some(PathBuf::from("journal")),
+                journal_max_len: JOURNAL_MAX_LEN,
+                export_dir: PathBuf::from(EXPORT_DIR),
+                accounts_file: None,
+                mailbox_len: MAILBOX_LEN,
+                emotes_file: None,
+                tones_file: None,
+                admin_password: Some("hunter2".to_string()),
+                idle_after: Duration::from_secs(60),
+                game_turn_timeout: Duration::from_secs(30),
+                trivia_room: Some("The Cellar".to_string()),
+                trivia_file: None,
+                trivia_scores_file: None,
+                trivia_interval: TRIVIA_INTERVAL,
+                rooms: vec![
+                    "The Common Room".to_string(),
+                    "The Back Room".to_string(),
+                    "The Fireplace Table".to_string(),
+                    "The Cellar".to_string(),
+                ],
+                npcs: vec![
+                    NpcConfig {
+                        name: "Barkeep".to_string(),
+                        room: None,
+                    },
+                    NpcConfig {
+                        name: "Bard".to_string(),
+                        room: Some("The Fireplace Table".to_string()),
+                    },
+                ],
+            }
+        );
+    }
+}
@@ -0,0 +1,424 @@
+//! Contains dice rolls in the usual tabletop notation, e.g. `2d6+3`, `4d6kh3` or `d20 adv`,
+//! and the server's dice. Each die is drawn from a secret seed whose SHA-256 hash, the seal,
+//! is shown beforehand. Once the seed is revealed, anyone can roll the dice again and check
+//! that they weren't loaded.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::hash_map::RandomState,
+    fmt::Display,
+    hash::BuildHasher,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Most dice rolled at once, across terms.
+pub const MAX_DICE: u32 = 100;
+pub const MAX_SIDES: u32 = 1000;
+/// Largest modifier added to or taken from a roll, e.g. the `3` in `2d6+3`.
+pub const MAX_MODIFIER: i64 = 10_000;
+pub const MAX_TERMS: usize = 10;
+
+/// Dice and modifiers added together, e.g. `2d6+3`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiceExpression {
+    pub terms: Vec<Term>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Term {
+    Dice {
+        /// The dice are subtracted, e.g. `-1d4`.
+        negative: bool,
+        count: u32,
+        sides: u32,
+        keep: Option<Keep>,
+    },
+    Modifier(i64),
+}
+
+/// Which dice count towards the total, e.g. `kh3` for the highest three.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    Highest(u32),
+    Lowest(u32),
+}
+
+impl DiceExpression {
+    /// Parses an expression such as `2d6+3`, `4d6kh3` or `d20 adv`. `adv` and `dis` roll a
+    /// single die twice and keep the highest or lowest of the two.
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let lowercase = expression.trim().to_lowercase();
+        let (dice, keep) = match lowercase.rsplit_once(' ') {
+            Some((dice, "adv" | "advantage")) => (dice, Some(Keep::Highest(1))),
+            Some((dice, "dis" | "disadvantage")) => (dice, Some(Keep::Lowest(1))),
+            _ => (lowercase.as_str(), None),
+        };
+        let dice = dice.split_whitespace().collect::<String>();
+        if dice.is_empty() {
+            return Err("Nothing to roll.".to_owned());
+        }
+
+        let mut terms = vec![];
+        let mut rest = dice.as_str();
+        while !rest.is_empty() {
+            let negative = rest.starts_with('-');
+            let unsigned = rest.strip_prefix(['+', '-']).unwrap_or(rest);
+            let end = unsigned.find(['+', '-']).unwrap_or(unsigned.len());
+            if end == 0 {
+                return Err(format!("Invalid dice {dice:?}."));
+            }
+            terms.push(Term::parse(&unsigned[..end], negative)?);
+            rest = &unsigned[end..];
+        }
+        if terms.len() > MAX_TERMS {
+            return Err(format!("Too many terms, at most {MAX_TERMS} are allowed."));
+        }
+        let dice_count = terms
+            .iter()
+            .map(|term| match term {
+                Term::Dice { count, .. } => u64::from(*count),
+                Term::Modifier(_) => 0,
+            })
+            .sum::<u64>();
+        if dice_count > u64::from(MAX_DICE) {
+            return Err(format!("Too many dice, at most {MAX_DICE} are allowed."));
+        }
+
+        if let Some(keep) = keep {
+            match terms.as_mut_slice() {
+                [
+                    Term::Dice {
+                        count: count @ 1,
+                        keep: term_keep @ None,
+                        ..
+                    },
+                    ..,
+                ] => {
+                    *count = 2;
+                    *term_keep = Some(keep);
+                }
+                _ => return Err("Advantage applies to a single die, e.g. d20 adv.".to_owned()),
+            }
+        }
+        Ok(Self { terms })
+    }
+}
+
+impl Term {
+    fn parse(term: &str, negative: bool) -> Result<Self, String> {
+        let invalid = || format!("Invalid dice {term:?}.");
+        let Some((count, sides)) = term.split_once('d') else {
+            let modifier = term
+                .parse::<i64>()
+                .ok()
+                .filter(|modifier| *modifier <= MAX_MODIFIER)
+                .ok_or_else(invalid)?;
+            return Ok(Term::Modifier(if negative { -modifier } else { modifier }));
+        };
+        let count = match count {
+            "" => 1,
+            count => count.parse::<u32>().map_err(|_| invalid())?,
+        };
+        if count > MAX_DICE {
+            return Err(format!("Too many dice, at most {MAX_DICE} are allowed."));
+        }
+        let (sides, keep) = match sides.split_once('k') {
+            Some((sides, keep)) => (sides, Some(keep)),
+            None => (sides, None),
+        };
+        let sides = match sides {
+            "%" => 100,
+            sides => sides.parse::<u32>().map_err(|_| invalid())?,
+        };
+        let keep = match keep {
+            None => None,
+            Some(keep) => {
+                let (keep, kept): (fn(u32) -> Keep, _) = match keep.strip_prefix('l') {
+                    Some(kept) => (Keep::Lowest, kept),
+                    None => (Keep::Highest, keep.strip_prefix('h').unwrap_or(keep)),
+                };
+                match kept.parse::<u32>() {
+                    Ok(kept) if (1..=count).contains(&kept) => Some(keep(kept)),
+                    _ => return Err(format!("Can't keep {kept:?} of {count} dice.")),
+                }
+            }
+        };
+        if count == 0 || !(2..=MAX_SIDES).contains(&sides) {
+            return Err(format!(
+                "Dice need between 2 and {MAX_SIDES} sides, and at least one of them."
+            ));
+        }
+        Ok(Term::Dice {
+            negative,
+            count,
+            sides,
+            keep,
+        })
+    }
+}
+
+impl Display for DiceExpression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, term) in self.terms.iter().enumerate() {
+            let negative = match term {
+                Term::Dice { negative, .. } => *negative,
+                Term::Modifier(modifier) => *modifier < 0,
+            };
+            match (i, negative) {
+                (_, true) => write!(f, "-")?,
+                (0, false) => {}
+                (_, false) => write!(f, "+")?,
+            }
+            match term {
+                Term::Dice {
+                    count, sides, keep, ..
+                } => {
+                    write!(f, "{count}d{sides}")?;
+                    match keep {
+                        Some(Keep::Highest(kept)) => write!(f, "kh{kept}")?,
+                        Some(Keep::Lowest(kept)) => write!(f, "kl{kept}")?,
+                        None => {}
+                    }
+                }
+                Term::Modifier(modifier) => write!(f, "{}", modifier.unsigned_abs())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The outcome of a roll, with enough to check it against the seed once revealed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiceRoll {
+    /// The hash of the seed the dice were drawn from.
+    pub seal: String,
+    /// Counts the rolls made under the same seal, from 1.
+    pub number: u64,
+    pub expression: String,
+    /// Every die and modifier, e.g. `[6, 5, 3, ~1~] + 2`. Dice that weren't kept are struck.
+    pub breakdown: String,
+    pub total: i64,
+}
+
+impl Display for DiceRoll {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "rolls {}: {} = {} (roll {})",
+            self.expression, self.breakdown, self.total, self.number
+        )
+    }
+}
+
+/// The server's dice, drawn from a secret seed.
+#[derive(Debug, Clone)]
+pub struct Dice {
+    seed: String,
+    rolls: u64,
+}
+
+impl Dice {
+    /// Dice with a new random seed.
+    pub fn new() -> Self {
+        // The standard hasher is keyed randomly, which is all the randomness needed here.
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let keys = (0..4)
+            .map(|i| RandomState::new().hash_one((nanos, i)).to_string())
+            .collect::<Vec<_>>();
+        Self::from_seed(&hex(&Sha256::digest(keys.join(":"))))
+    }
+
+    /// Dice drawn from a known seed, to check the rolls made with it.
+    pub fn from_seed(seed: &str) -> Self {
+        Self {
+            seed: seed.to_owned(),
+            rolls: 0,
+        }
+    }
+
+    /// The hash of the seed, which can be shown without giving the rolls away.
+    pub fn seal(&self) -> String {
+        hex(&Sha256::digest(&self.seed))
+    }
+
+    pub fn seed(&self) -> &str {
+        &self.seed
+    }
+
+    /// How many rolls were made under the current seal.
+    pub fn rolls(&self) -> u64 {
+        self.rolls
+    }
+
+    pub fn roll(&mut self, expression: &DiceExpression) -> DiceRoll {
+        self.rolls += 1;
+        self.roll_as(self.rolls, expression)
+    }
+
+    /// Rolls a number of plain dice, as a single roll, e.g. for games.
+    pub fn draw(&mut self, count: u32, sides: u32) -> Vec<u32> {
+        self.rolls += 1;
+        (1..=count)
+            .map(|index| self.die(self.rolls, index, sides))
+            .collect()
+    }
+
+    /// Makes a roll again, given its number.
+    pub fn roll_as(&self, number: u64, expression: &DiceExpression) -> DiceRoll {
+        let mut breakdown = vec![];
+        let mut total = 0i64;
+        // Parsed expressions are too small to overflow, but terms can be built by hand.
+        let add = |total: i64, value: i64| {
+            total
+                .checked_add(value)
+                .unwrap_or(if value < 0 { i64::MIN } else { i64::MAX })
+        };
+        let mut die_index = 0;
+        for term in &expression.terms {
+            match *term {
+                Term::Dice {
+                    negative,
+                    count,
+                    sides,
+                    keep,
+                } => {
+                    let dice = (0..count)
+                        .map(|_| {
+                            die_index += 1;
+                            self.die(number, die_index, sides)
+                        })
+                        .collect::<Vec<_>>();
+                    let mut by_value = (0..dice.len()).collect::<Vec<_>>();
+                    by_value.sort_by_key(|&i| std::cmp::Reverse(dice[i]));
+                    let kept = match keep {
+                        Some(Keep::Highest(kept)) => &by_value[..kept as usize],
+                        Some(Keep::Lowest(kept)) => &by_value[by_value.len() - kept as usize..],
+                        None => &by_value[..],
+                    };
+                    let sum = kept.iter().fold(0, |sum, &i| add(sum, i64::from(dice[i])));
+                    total = add(total, if negative { -sum } else { sum });
+                    let shown = dice
+                        .iter()
+                        .enumerate()
+                        .map(|(i, die)| match kept.contains(&i) {
+                            true => die.to_string(),
+                            false => format!("~{die}~"),
+                        })
+                        .collect::<Vec<_>>();
+                    breakdown.push((negative, format!("[{}]", shown.join(", "))));
+                }
+                Term::Modifier(modifier) => {
+                    total = add(total, modifier);
+                    breakdown.push((modifier < 0, modifier.unsigned_abs().to_string()));
+                }
+            }
+        }
+
+        DiceRoll {
+            seal: self.seal(),
+            number,
+            expression: expression.to_string(),
+            breakdown: breakdown
+                .iter()
+                .enumerate()
+                .map(|(i, (negative, term))| match (i, negative) {
+                    (0, true) => format!("-{term}"),
+                    (0, false) => term.clone(),
+                    (_, true) => format!(" - {term}"),
+                    (_, false) => format!(" + {term}"),
+                })
+                .collect(),
+            total,
+        }
+    }
+
+    /// The `index`th die of a roll, from 1: the first 8 bytes of `sha256("{seed}:{number}:
+    /// {index}:{attempt}")` as a big endian number, modulo the sides, plus one. Attempts start
+    /// at 0, and numbers too large to give every side the same odds are drawn again.
+    fn die(&self, number: u64, index: u32, sides: u32) -> u32 {
+        let sides = u64::from(sides);
+        let fair_limit = u64::MAX - u64::MAX % sides;
+        for attempt in 0.. {
+            let hash = Sha256::digest(format!("{}:{number}:{index}:{attempt}", self.seed));
+            let drawn = u64::from_be_bytes(hash[..8].try_into().expect("SHA-256 is 32 bytes"));
+            if drawn < fair_limit {
+                return (drawn % sides + 1) as u32;
+            }
+        }
+        unreachable!("Some number is eventually drawn")
+    }
+}
+
+impl Default for Dice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dice_expressions_are_parsed() {
+        for (expression, normalized) in [
+            ("2d6+3", "2d6+3"),
+            ("4d6kh3", "4d6kh3"),
+            ("d20 adv", "2d20kh1"),
+            ("D20 DIS", "2d20kl1"),
+            ("d% - 1d4 + 2", "1d100-1d4+2"),
+            ("3d8k1-2", "3d8kh1-2"),
+        ] {
+            assert_eq!(
+                DiceExpression::parse(expression).map(|parsed| parsed.to_string()),
+                Ok(normalized.to_owned())
+            );
+        }
+        for invalid in ["", "2d", "d1", "4d6kh5", "3d6 adv", "1000d6", "2x6", "d20+"] {
+            assert!(DiceExpression::parse(invalid).is_err(), "{invalid:?}");
+        }
+        for (oversized, term) in [
+            ("9223372036854775807+1", "9223372036854775807"),
+            ("1d6+9223372036854775807", "9223372036854775807"),
+            ("1d6-10001", "10001"),
+        ] {
+            assert_eq!(
+                DiceExpression::parse(oversized),
+                Err(format!("Invalid dice {term:?}."))
+            );
+        }
+        assert!(DiceExpression::parse("1d6+10000").is_ok());
+        for too_many in ["4294967295d6+2d6", "101d6", "60d6+50d4"] {
+            assert_eq!(
+                DiceExpression::parse(too_many),
+                Err(format!("Too many dice, at most {MAX_DICE} are allowed."))
+            );
+        }
+    }
+
+    #[test]
+    fn rolls_can_be_checked_once_the_seed_is_revealed() {
+        let mut dice = Dice::new();
+        let seal = dice.seal();
+        let expression = DiceExpression::parse("4d6kh3+2").unwrap();
+        let first = dice.roll(&expression);
+        let second = dice.roll(&expression);
+        assert_eq!((first.number, second.number), (1, 2));
+        assert_eq!(first.seal, seal);
+        assert!((5..=20).contains(&first.total), "{first:?}");
+        assert_eq!(first.breakdown.matches('~').count(), 2, "{first:?}");
+
+        let revealed = Dice::from_seed(dice.seed());
+        assert_eq!(revealed.seal(), seal);
+        assert_eq!(revealed.roll_as(2, &expression), second);
+        assert_ne!(Dice::new().seal(), seal);
+    }
+}
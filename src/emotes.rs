@@ -0,0 +1,152 @@
+//! Contains emotes such as `/wave` or `/poke`. They're read from a JSON file, each with the lines
+//! seen by the patron doing it, by its target, and by everyone else, so adding one needs no code.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use crate::common::MessageTone;
+
+/// The emotes known when the server isn't given a file of its own.
+pub const DEFAULT_EMOTES: &str = include_str!("../emotes.json");
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Emote {
+    /// Typed after a slash, e.g. `wave` for `/wave`.
+    pub name: String,
+    #[serde(default)]
+    pub tone: MessageTone,
+    /// Lines used without a target. The emote needs one if missing.
+    #[serde(default)]
+    pub alone: Option<EmoteTemplates>,
+    /// Lines used with a target. The emote takes none if missing.
+    #[serde(default)]
+    pub at: Option<EmoteTemplates>,
+}
+
+/// The lines of an emote, in which `{actor}` and `{target}` are replaced by names.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmoteTemplates {
+    /// Seen by the patron doing the emote.
+    pub actor: String,
+    /// Seen by the target. They see the same as everyone else without it.
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Seen by everyone else.
+    pub others: String,
+}
+
+/// The lines of an emote once performed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmoteLines {
+    pub actor: String,
+    pub target: Option<String>,
+    pub others: String,
+}
+
+impl Emote {
+    /// Fills in the lines of the emote, or explains why it can't be done that way.
+    pub fn perform(&self, actor: &str, target: Option<&str>) -> Result<EmoteLines, String> {
+        let templates = match target {
+            Some(_) => self
+                .at
+                .as_ref()
+                .ok_or_else(|| format!("/{} doesn't take a target.", self.name))?,
+            None => self.alone.as_ref().ok_or_else(|| {
+                format!(
+                    "/{} needs a target. please use /{} <name>",
+                    self.name, self.name
+                )
+            })?,
+        };
+        let fill = |template: &str| {
+            template
+                .replace("{actor}", actor)
+                .replace("{target}", target.unwrap_or_default())
+        };
+        Ok(EmoteLines {
+            actor: fill(&templates.actor),
+            target: templates.target.as_deref().map(fill),
+            others: fill(&templates.others),
+        })
+    }
+
+    /// How to use the emote, e.g. `/wave [name]`.
+    pub fn usage(&self) -> String {
+        match (&self.alone, &self.at) {
+            (Some(_), Some(_)) => format!("/{} [name]", self.name),
+            (None, Some(_)) => format!("/{} <name>", self.name),
+            _ => format!("/{}", self.name),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Emotes {
+    /// Emotes by lowercase name.
+    emotes: BTreeMap<String, Emote>,
+}
+
+impl Emotes {
+    /// Reads emotes from a JSON list.
+    pub fn parse(json: &str) -> io::Result<Self> {
+        let mut emotes = BTreeMap::new();
+        for emote in serde_json::from_str::<Vec<Emote>>(json)? {
+            if emote.alone.is_none() && emote.at.is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Emote {:?} has no lines", emote.name),
+                ));
+            }
+            emotes.insert(emote.name.to_lowercase(), emote);
+        }
+        Ok(Self { emotes })
+    }
+
+    /// Loads the emotes in `path`, or the default ones without it.
+    pub fn load(path: Option<&Path>) -> io::Result<Self> {
+        match path {
+            Some(path) => Self::parse(&fs::read_to_string(path)?),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Emote> {
+        self.emotes.get(&name.to_lowercase())
+    }
+
+    /// Every emote, by name.
+    pub fn iter(&self) -> impl Iterator<Item = &Emote> {
+        self.emotes.values()
+    }
+}
+
+impl Default for Emotes {
+    fn default() -> Self {
+        Self::parse(DEFAULT_EMOTES).expect("The default emotes are valid")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn emotes_have_lines_for_the_actor_target_and_others() {
+        let emotes = Emotes::default();
+        let wave = emotes.get("WAVE").unwrap();
+        assert_eq!(
+            wave.perform("Alice", Some("Bob")),
+            Ok(EmoteLines {
+                actor: "You wave at Bob. Wassup?".to_owned(),
+                target: Some("Alice waves at you. Wassup?".to_owned()),
+                others: "Alice waves at Bob. Wassup?".to_owned(),
+            })
+        );
+        assert_eq!(wave.usage(), "/wave [name]");
+
+        let poke = emotes.get("poke").unwrap();
+        assert_eq!(poke.usage(), "/poke <name>");
+        assert!(poke.perform("Alice", None).is_err());
+        assert!(Emotes::parse(r#"[{"name": "sulk"}]"#).is_err());
+    }
+}
@@ -0,0 +1,616 @@
+//! Contains the mini-games played in the tavern: dice poker and arm wrestling. Each game is a
+//! session owned by the server, played with `/game` by the patrons where it was started.
+//! Games only decide what happens, with the server's dice, and the server announces it.
+
+use std::{fmt::Display, time::Duration};
+
+use crate::common::UserId;
+use crate::dice::Dice;
+
+/// How long a player has to play their turn, or a game to find players.
+pub const GAME_TURN_TIMEOUT: Duration = Duration::from_secs(60);
+pub const DICE_POKER_MAX_PLAYERS: usize = 6;
+pub const DICE_POKER_DICE: usize = 5;
+/// Rolls each dice poker player gets, the first one included.
+pub const DICE_POKER_ROLLS: u32 = 3;
+/// Rounds an arm wrestler must be ahead by to win.
+pub const ARM_WRESTLING_LEAD: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameKind {
+    DicePoker,
+    ArmWrestling,
+}
+
+impl GameKind {
+    /// Finds a game by name, e.g. `dice-poker` or `arm wrestling`.
+    pub fn parse(name: &str) -> Option<Self> {
+        let name = name
+            .trim()
+            .to_lowercase()
+            .replace(|c: char| !c.is_alphanumeric(), "");
+        match name.as_str() {
+            "dicepoker" | "poker" => Some(GameKind::DicePoker),
+            "armwrestling" | "armwrestle" => Some(GameKind::ArmWrestling),
+            _ => None,
+        }
+    }
+
+    fn max_players(self) -> usize {
+        match self {
+            GameKind::DicePoker => DICE_POKER_MAX_PLAYERS,
+            GameKind::ArmWrestling => 2,
+        }
+    }
+}
+
+impl Display for GameKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameKind::DicePoker => write!(f, "dice poker"),
+            GameKind::ArmWrestling => write!(f, "arm wrestling"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Player {
+    id: UserId,
+    name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    /// Waiting for players. Arm wrestling starts once the opponent joins, and dice poker once
+    /// its first player rolls, or when the lobby times out.
+    Lobby,
+    DicePoker {
+        /// Index of the player whose turn it is.
+        current: usize,
+        dice: Vec<u32>,
+        held: Vec<bool>,
+        rolls: u32,
+        /// The dice each player ended their turn with, if they played it.
+        hands: Vec<Option<Vec<u32>>>,
+    },
+    ArmWrestling {
+        /// Positive when the first player is ahead.
+        lead: i32,
+        pushes: [Option<u32>; 2],
+    },
+    Over,
+}
+
+/// A game being played. Every change returns the lines to announce to everyone there, or why
+/// it can't be done.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Game {
+    pub kind: GameKind,
+    players: Vec<Player>,
+    state: State,
+    /// Counts turns, including rounds and the lobby, so stale timeouts can be told apart.
+    turn: u64,
+}
+
+impl Game {
+    /// A game waiting for players, with the patron who started it.
+    pub fn new(kind: GameKind, id: UserId, name: &str) -> Self {
+        Self {
+            kind,
+            players: vec![Player {
+                id,
+                name: name.to_owned(),
+            }],
+            state: State::Lobby,
+            turn: 0,
+        }
+    }
+
+    pub fn turn(&self) -> u64 {
+        self.turn
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.state == State::Over
+    }
+
+    pub fn join(&mut self, id: UserId, name: &str) -> Result<Vec<String>, String> {
+        if self.state != State::Lobby {
+            return Err(format!("This game of {} has already begun.", self.kind));
+        }
+        if self.players.iter().any(|player| player.id == id) {
+            return Err("You're already playing.".to_owned());
+        }
+        if self.players.len() >= self.kind.max_players() {
+            return Err(format!("This game of {} is full.", self.kind));
+        }
+        self.players.push(Player {
+            id,
+            name: name.to_owned(),
+        });
+        match self.kind {
+            GameKind::DicePoker => Ok(vec![format!(
+                "{name} joins the game of dice poker. {} may /game roll to begin.",
+                self.players[0].name
+            )]),
+            GameKind::ArmWrestling => Ok(self.begin()),
+        }
+    }
+
+    pub fn roll(&mut self, id: UserId, dice: &mut Dice) -> Result<Vec<String>, String> {
+        let player = self.player(id)?;
+        let mut lines = vec![];
+        if self.state == State::Lobby {
+            match self.kind {
+                GameKind::DicePoker if self.players.len() < 2 => {
+                    return Err(
+                        "Dice poker needs another player. Others may /game join.".to_owned()
+                    );
+                }
+                GameKind::DicePoker if player != 0 => {
+                    return Err(format!("{} rolls first.", self.players[0].name));
+                }
+                GameKind::DicePoker => lines = self.begin(),
+                GameKind::ArmWrestling => {
+                    return Err(
+                        "Arm wrestling needs an opponent. Others may /game join.".to_owned()
+                    );
+                }
+            }
+        }
+
+        let name = self.players[player].name.clone();
+        match &mut self.state {
+            State::DicePoker {
+                current,
+                dice: hand,
+                held,
+                rolls,
+                ..
+            } => {
+                if *current != player {
+                    return Err(format!("It's {}'s turn.", self.players[*current].name));
+                }
+                if *rolls == 0 {
+                    *hand = dice.draw(DICE_POKER_DICE as u32, 6);
+                } else {
+                    let rerolled = held.iter().filter(|held| !**held).count();
+                    let mut drawn = dice.draw(rerolled as u32, 6).into_iter();
+                    for (die, held) in hand.iter_mut().zip(held.iter()) {
+                        if !held {
+                            *die = drawn.next().unwrap_or(*die);
+                        }
+                    }
+                }
+                *rolls += 1;
+                lines.push(format!(
+                    "{name} rolls {}: {}.",
+                    show_dice(hand),
+                    Hand::of(hand)
+                ));
+                if *rolls >= DICE_POKER_ROLLS {
+                    lines.extend(self.end_turn());
+                }
+            }
+            State::ArmWrestling { pushes, .. } => {
+                if pushes[player].is_some() {
+                    return Err("You already pushed this round.".to_owned());
+                }
+                let push = dice.draw(1, 6)[0];
+                pushes[player] = Some(push);
+                lines.push(format!("{name} pushes with a {push}."));
+                if pushes.iter().all(Option::is_some) {
+                    lines.extend(self.end_round());
+                }
+            }
+            State::Lobby | State::Over => {}
+        }
+        Ok(lines)
+    }
+
+    /// Keeps the dice at `positions`, from 1, when rolling again. The others are rolled.
+    pub fn hold(&mut self, id: UserId, positions: &[usize]) -> Result<Vec<String>, String> {
+        let player = self.player(id)?;
+        let State::DicePoker {
+            current,
+            dice,
+            held,
+            rolls,
+            ..
+        } = &mut self.state
+        else {
+            return Err("There are no dice to hold.".to_owned());
+        };
+        if *current != player {
+            return Err(format!("It's {}'s turn.", self.players[*current].name));
+        }
+        if *rolls == 0 {
+            return Err("Roll your dice first.".to_owned());
+        }
+        if let Some(position) = positions
+            .iter()
+            .find(|position| !(1..=DICE_POKER_DICE).contains(position))
+        {
+            return Err(format!(
+                "There's no die {position}, they're numbered 1 to {DICE_POKER_DICE}."
+            ));
+        }
+        for (i, held) in held.iter_mut().enumerate() {
+            *held = positions.contains(&(i + 1));
+        }
+        let kept = dice
+            .iter()
+            .zip(held.iter())
+            .filter(|(_, held)| **held)
+            .map(|(die, _)| *die)
+            .collect::<Vec<_>>();
+        Ok(vec![match kept.is_empty() {
+            true => format!("{} will roll every die.", self.players[player].name),
+            false => format!("{} holds {}.", self.players[player].name, show_dice(&kept)),
+        }])
+    }
+
+    /// Ends a dice poker turn early, keeping the dice as they are.
+    pub fn stand(&mut self, id: UserId) -> Result<Vec<String>, String> {
+        let player = self.player(id)?;
+        let State::DicePoker {
+            current,
+            dice,
+            rolls,
+            ..
+        } = &self.state
+        else {
+            return Err("There's nothing to stand on.".to_owned());
+        };
+        if *current != player {
+            return Err(format!("It's {}'s turn.", self.players[*current].name));
+        }
+        if *rolls == 0 {
+            return Err("Roll your dice first.".to_owned());
+        }
+        let mut lines = vec![format!(
+            "{} stands with {}.",
+            self.players[player].name,
+            Hand::of(dice)
+        )];
+        lines.extend(self.end_turn());
+        Ok(lines)
+    }
+
+    /// Moves the game on when a player took too long, or nobody joined.
+    pub fn timeout(&mut self) -> Vec<String> {
+        let starter = self.players[0].name.clone();
+        match &mut self.state {
+            State::Lobby if self.kind == GameKind::DicePoker && self.players.len() >= 2 => {
+                self.begin()
+            }
+            State::Lobby => {
+                self.state = State::Over;
+                vec![format!("Nobody took up {starter}'s game of {}.", self.kind)]
+            }
+            State::DicePoker {
+                current,
+                dice,
+                rolls,
+                ..
+            } => {
+                let name = &self.players[*current].name;
+                let mut lines = vec![match rolls {
+                    0 => format!("{name} took too long, and sits this game out."),
+                    _ => format!("{name} took too long, and stands with {}.", Hand::of(dice)),
+                }];
+                lines.extend(self.end_turn());
+                lines
+            }
+            State::ArmWrestling { pushes, .. } => {
+                if pushes.iter().all(Option::is_none) {
+                    self.state = State::Over;
+                    return vec![format!(
+                        "{} and {} let go of each other. Nobody wins.",
+                        self.players[0].name, self.players[1].name
+                    )];
+                }
+                let mut lines = vec![];
+                for (player, push) in pushes.iter_mut().enumerate() {
+                    if push.is_none() {
+                        lines.push(format!("{} hesitates.", self.players[player].name));
+                        *push = Some(0);
+                    }
+                }
+                lines.extend(self.end_round());
+                lines
+            }
+            State::Over => vec![],
+        }
+    }
+
+    /// Describes the game, for `/game`.
+    pub fn status(&self) -> String {
+        let players = self
+            .players
+            .iter()
+            .map(|player| player.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        match &self.state {
+            State::Lobby => format!(
+                "{players} {} waiting to play {}. /game join to play too.",
+                if self.players.len() == 1 { "is" } else { "are" },
+                self.kind
+            ),
+            State::DicePoker {
+                current,
+                dice,
+                rolls,
+                ..
+            } => format!(
+                "Dice poker between {players}. It's {}'s turn, with {} after {rolls} of \
+                 {DICE_POKER_ROLLS} rolls.",
+                self.players[*current].name,
+                show_dice(dice)
+            ),
+            State::ArmWrestling { lead, .. } => {
+                format!("Arm wrestling between {players}. {}", self.standing(*lead))
+            }
+            State::Over => format!("The game of {} is over.", self.kind),
+        }
+    }
+
+    fn player(&self, id: UserId) -> Result<usize, String> {
+        self.players
+            .iter()
+            .position(|player| player.id == id)
+            .ok_or_else(|| format!("You're not playing {}. /game join to play.", self.kind))
+    }
+
+    fn begin(&mut self) -> Vec<String> {
+        self.turn += 1;
+        let names = self
+            .players
+            .iter()
+            .map(|player| player.name.as_str())
+            .collect::<Vec<_>>();
+        match self.kind {
+            GameKind::DicePoker => {
+                self.state = State::DicePoker {
+                    current: 0,
+                    dice: vec![],
+                    held: vec![false; DICE_POKER_DICE],
+                    rolls: 0,
+                    hands: vec![None; self.players.len()],
+                };
+                vec![format!(
+                    "Dice poker begins between {}. {} goes first: /game roll, /game hold 1 3 to \
+                     keep dice when rolling again, and /game stand.",
+                    names.join(", "),
+                    names[0]
+                )]
+            }
+            GameKind::ArmWrestling => {
+                self.state = State::ArmWrestling {
+                    lead: 0,
+                    pushes: [None, None],
+                };
+                vec![format!(
+                    "{} and {} lock hands over the table. /game roll to push!",
+                    names[0], names[1]
+                )]
+            }
+        }
+    }
+
+    /// Keeps the dice poker hand of the current player, and passes the dice on.
+    fn end_turn(&mut self) -> Vec<String> {
+        let State::DicePoker {
+            current,
+            dice,
+            held,
+            rolls,
+            hands,
+        } = &mut self.state
+        else {
+            return vec![];
+        };
+        if *rolls > 0 {
+            hands[*current] = Some(std::mem::take(dice));
+        }
+        *current += 1;
+        *held = vec![false; DICE_POKER_DICE];
+        *rolls = 0;
+        self.turn += 1;
+        if let Some(next) = self.players.get(*current) {
+            return vec![format!("It's {}'s turn to /game roll.", next.name)];
+        }
+
+        let hands = std::mem::take(hands);
+        self.state = State::Over;
+        let best = hands.iter().flatten().map(|dice| Hand::of(dice)).max();
+        let Some(best) = best else {
+            return vec!["Nobody rolled, so nobody wins the game of dice poker.".to_owned()];
+        };
+        let winners = self
+            .players
+            .iter()
+            .zip(&hands)
+            .filter(|(_, hand)| hand.as_ref().is_some_and(|dice| Hand::of(dice) == best))
+            .map(|(player, _)| player.name.as_str())
+            .collect::<Vec<_>>();
+        vec![match winners.as_slice() {
+            [winner] => format!("{winner} wins the game of dice poker with {best}!"),
+            winners => format!(
+                "{} tie the game of dice poker with {best}!",
+                winners.join(" and ")
+            ),
+        }]
+    }
+
+    /// Moves the arm wrestlers' hands towards whoever pushed hardest.
+    fn end_round(&mut self) -> Vec<String> {
+        let State::ArmWrestling { lead, pushes } = &mut self.state else {
+            return vec![];
+        };
+        let [Some(first), Some(second)] = *pushes else {
+            return vec![];
+        };
+        *pushes = [None, None];
+        self.turn += 1;
+        let lead = match first.cmp(&second) {
+            std::cmp::Ordering::Greater => *lead + 1,
+            std::cmp::Ordering::Less => *lead - 1,
+            std::cmp::Ordering::Equal => return vec!["Neither gives way.".to_owned()],
+        };
+        if lead.abs() >= ARM_WRESTLING_LEAD {
+            let (winner, loser) = match lead > 0 {
+                true => (&self.players[0].name, &self.players[1].name),
+                false => (&self.players[1].name, &self.players[0].name),
+            };
+            let line = format!("{winner} slams {loser}'s arm onto the table, and wins!");
+            self.state = State::Over;
+            return vec![line];
+        }
+        self.state = State::ArmWrestling {
+            lead,
+            pushes: [None, None],
+        };
+        vec![self.standing(lead)]
+    }
+
+    fn standing(&self, lead: i32) -> String {
+        match lead {
+            0 => "Their arms are upright.".to_owned(),
+            lead => format!(
+                "{} is ahead, {} of {ARM_WRESTLING_LEAD} rounds from winning.",
+                self.players[if lead > 0 { 0 } else { 1 }].name,
+                ARM_WRESTLING_LEAD - lead.abs()
+            ),
+        }
+    }
+}
+
+/// How good a dice poker hand is. Hands compare by rank, then by the dice making them.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hand {
+    pub rank: Rank,
+    /// The values of the dice, the most common first, then the highest.
+    pub values: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rank {
+    Nothing,
+    Pair,
+    TwoPair,
+    ThreeOfAKind,
+    Straight,
+    FullHouse,
+    FourOfAKind,
+    FiveOfAKind,
+}
+
+impl Hand {
+    pub fn of(dice: &[u32]) -> Self {
+        let mut counts = (1..=6)
+            .map(|value| (dice.iter().filter(|die| **die == value).count(), value))
+            .filter(|(count, _)| *count > 0)
+            .collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.cmp(a));
+        let values = counts.iter().map(|(_, value)| *value).collect();
+        let rank = match counts.iter().map(|(count, _)| *count).collect::<Vec<_>>()[..] {
+            [5] => Rank::FiveOfAKind,
+            [4, ..] => Rank::FourOfAKind,
+            [3, 2] => Rank::FullHouse,
+            [3, ..] => Rank::ThreeOfAKind,
+            [2, 2, ..] => Rank::TwoPair,
+            [2, ..] => Rank::Pair,
+            // Five different dice are a straight, unless they skip a value.
+            [1, 1, 1, 1, 1] if !(dice.contains(&1) && dice.contains(&6)) => Rank::Straight,
+            _ => Rank::Nothing,
+        };
+        Self { rank, values }
+    }
+}
+
+impl Display for Hand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let value = |i: usize| self.values.get(i).copied().unwrap_or_default();
+        match self.rank {
+            Rank::FiveOfAKind => write!(f, "five {}s", value(0)),
+            Rank::FourOfAKind => write!(f, "four {}s", value(0)),
+            Rank::FullHouse => write!(f, "a full house, {}s over {}s", value(0), value(1)),
+            Rank::Straight => write!(f, "a straight to {}", value(0)),
+            Rank::ThreeOfAKind => write!(f, "three {}s", value(0)),
+            Rank::TwoPair => write!(f, "two pair, {}s and {}s", value(0), value(1)),
+            Rank::Pair => write!(f, "a pair of {}s", value(0)),
+            Rank::Nothing => write!(f, "nothing, {} high", value(0)),
+        }
+    }
+}
+
+fn show_dice(dice: &[u32]) -> String {
+    format!(
+        "[{}]",
+        dice.iter()
+            .map(|die| die.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dice_poker_hands_are_ranked() {
+        let hands = [
+            [2, 3, 4, 5, 6],
+            [1, 2, 4, 5, 6],
+            [3, 3, 5, 5, 5],
+            [6, 6, 1, 2, 3],
+            [5, 5, 5, 5, 5],
+            [4, 4, 2, 2, 6],
+        ]
+        .map(|dice| Hand::of(&dice));
+        assert_eq!(
+            hands.iter().map(Hand::to_string).collect::<Vec<_>>(),
+            [
+                "a straight to 6",
+                "nothing, 6 high",
+                "a full house, 5s over 3s",
+                "a pair of 6s",
+                "five 5s",
+                "two pair, 4s and 2s",
+            ]
+        );
+        assert!(hands[4] > hands[2] && hands[2] > hands[0] && hands[0] > hands[5]);
+        assert!(hands[5] > hands[3] && hands[3] > hands[1]);
+        assert!(Hand::of(&[6, 6, 1, 2, 3]) > Hand::of(&[5, 5, 4, 3, 2]));
+    }
+
+    #[test]
+    fn dice_poker_is_played_in_turns() {
+        let (alice, bob) = (UserId(1), UserId(2));
+        let mut dice = Dice::new();
+        let mut game = Game::new(GameKind::DicePoker, alice, "Alice");
+        assert!(game.roll(alice, &mut dice).is_err());
+        game.join(bob, "Bob").unwrap();
+        assert!(game.join(bob, "Bob").is_err());
+        assert!(game.roll(bob, &mut dice).is_err());
+        assert!(game.hold(alice, &[1]).is_err());
+
+        game.roll(alice, &mut dice).unwrap();
+        let turn = game.turn();
+        game.hold(alice, &[1, 3]).unwrap();
+        assert!(game.hold(alice, &[6]).is_err());
+        game.roll(alice, &mut dice).unwrap();
+        assert!(game.roll(bob, &mut dice).is_err());
+        let stood = game.stand(alice).unwrap();
+        assert_eq!(stood.last().unwrap(), "It's Bob's turn to /game roll.");
+        assert!(game.turn() > turn);
+
+        let lines = game.timeout();
+        assert_eq!(lines[0], "Bob took too long, and sits this game out.");
+        assert!(lines[1].starts_with("Alice wins the game of dice poker with"));
+        assert!(game.is_over());
+    }
+}
@@ -0,0 +1,238 @@
+//! Contains the local HTTP API used by dashboards and bots.
+//! Requests are authenticated with API tokens and forwarded to the server as events.
+//! Each token speaks as its own bot, which is registered in the tavern as an NPC.
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+
+use crate::common::*;
+
+pub const HTTP_PORT: &str = "127.0.0.1:8088";
+/// Comma separated list of `<bot name>:<token>` pairs.
+pub const API_TOKENS_ENV: &str = "TAVERN_API_TOKENS";
+pub const MAX_BODY_LEN: usize = 64 * 1024;
+
+/// Maps API tokens to the name of the bot they speak as.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ApiTokens(pub HashMap<String, String>);
+
+impl ApiTokens {
+    pub fn from_env() -> Self {
+        std::env::var(API_TOKENS_ENV)
+            .map(|tokens| Self::parse(&tokens))
+            .unwrap_or_default()
+    }
+
+    pub fn parse(tokens: &str) -> Self {
+        ApiTokens(
+            tokens
+                .split(',')
+                .filter_map(|pair| pair.trim().split_once(':'))
+                .filter(|(name, token)| !name.is_empty() && !token.is_empty())
+                .map(|(name, token)| (token.to_owned(), name.to_owned()))
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiRequest {
+    pub method: String,
+    pub path: String,
+    pub query: HashMap<String, String>,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl ApiRequest {
+    /// Returns the bearer token sent with the request, if any.
+    pub fn token(&self) -> Option<&str> {
+        self.headers
+            .get("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .map(str::trim)
+    }
+}
+
+/// Body of `POST /messages`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct PostMessage {
+    pub content: String,
+    #[serde(default)]
+    pub to: ChatTarget,
+    #[serde(default)]
+    pub tone: Option<MessageTone>,
+    #[serde(default, rename = "as")]
+    pub sender: MessageSender,
+}
+
+/// Who an injected message appears to come from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageSender {
+    #[default]
+    Bot,
+    System,
+}
+
+/// Reads a single HTTP/1.1 request. Returns None if the request is malformed.
+pub async fn read_request<R: AsyncBufRead + Unpin>(reader: &mut R) -> Option<ApiRequest> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok()?;
+    let mut request_line = line.split_whitespace();
+    let method = request_line.next()?.to_ascii_uppercase();
+    let target = request_line.next()?;
+
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let path = path.to_owned();
+    let query = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect();
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await.ok()? == 0 {
+            return None;
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':')?;
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_owned());
+    }
+
+    let body_len = headers
+        .get("content-length")
+        .map(|len| len.parse::<usize>())
+        .transpose()
+        .ok()?
+        .unwrap_or(0);
+    if body_len > MAX_BODY_LEN {
+        return None;
+    }
+    let mut body = vec![0u8; body_len];
+    reader.read_exact(&mut body).await.ok()?;
+
+    Some(ApiRequest {
+        method,
+        path,
+        query,
+        headers,
+        body: String::from_utf8(body).ok()?,
+    })
+}
+
+/// Formats a complete HTTP response. The connection is closed after every response.
+pub fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+pub fn json_response(status: &str, body: &serde_json::Value) -> String {
+    response(status, "application/json", &body.to_string())
+}
+
+pub fn error_response(status: &str, error: &str) -> String {
+    json_response(status, &serde_json::json!({ "error": error }))
+}
+
+/// Headers that open a Server-Sent Events stream.
+pub fn event_stream_headers() -> String {
+    "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n"
+        .to_owned()
+}
+
+/// Formats a single Server-Sent Event.
+pub fn sse_event(event: &str, data: &serde_json::Value) -> String {
+    format!("event: {event}\ndata: {data}\n\n")
+}
+
+/// Accepts HTTP connections, authenticates them and forwards them to the server.
+pub async fn manage_http_connections(
+    address: String,
+    tokens: HashMap<String, NpcId>,
+    event_dispatch: mpsc::Sender<Event>,
+    mut shutdown: watch::Receiver<()>,
+) -> anyhow::Result<JoinHandle<()>> {
+    let listener = TcpListener::bind(&address).await?;
+
+    Ok(tokio::spawn(async move {
+        println!("☎️ Rust Tavern HTTP API awaiting requests on {:?}", address);
+
+        loop {
+            tokio::select! {
+                Ok((mut socket, addr)) = listener.accept() => {
+                    let tokens = tokens.clone();
+                    let event_dispatch = event_dispatch.clone();
+                    // Read requests off the main loop, so slow clients can't stall the server.
+                    tokio::spawn(async move {
+                        let request = read_request(&mut BufReader::new(&mut socket)).await;
+                        let error = match request {
+                            None => error_response("400 Bad Request", "Malformed request"),
+                            Some(request) => match request.token().and_then(|token| tokens.get(token)) {
+                                Some(bot) => {
+                                    println!("🌐 API request from {addr}: {} {}", request.method, request.path);
+                                    let _ = event_dispatch
+                                        .send(Event::ApiRequest { request, connection: socket, bot: *bot })
+                                        .await;
+                                    return;
+                                }
+                                None => error_response("401 Unauthorized", "Missing or invalid API token"),
+                            },
+                        };
+                        let _ = socket.write_all(error.as_bytes()).await;
+                    });
+                }
+                Ok(()) = shutdown.changed() => {
+                    break;
+                }
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn can_read_request_with_body() {
+        let raw = "POST /messages?limit=5 HTTP/1.1\r\nAuthorization: Bearer abc\r\nContent-Length: 17\r\n\r\n{\"content\":\"hi\"}\n";
+        let request = read_request(&mut raw.as_bytes()).await.unwrap();
+
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/messages");
+        assert_eq!(request.query.get("limit").map(String::as_str), Some("5"));
+        assert_eq!(request.token(), Some("abc"));
+
+        let body: PostMessage = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(
+            body,
+            PostMessage {
+                content: "hi".to_string(),
+                to: ChatTarget::Global,
+                tone: None,
+                sender: MessageSender::Bot,
+            }
+        );
+    }
+
+    #[test]
+    fn can_parse_api_tokens() {
+        let tokens = ApiTokens::parse("dashboard:s3cret, dicebot:xyz,broken");
+        assert_eq!(tokens.0.len(), 2);
+        assert_eq!(tokens.0.get("xyz").map(String::as_str), Some("dicebot"));
+    }
+}
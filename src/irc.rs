@@ -0,0 +1,187 @@
+//! Contains the IRC gateway protocol helpers.
+//! IRC clients connect to their own listener, and their lines are translated to and from
+//! tavern `Event`s by the server. `ChatTarget::Global` is exposed as a single channel,
+//! `ChatTarget::User` as private messages and NPCs as pseudo-users.
+
+use crate::common::{Message, MessageKind, MessageTone};
+
+pub const IRC_PORT: &str = "127.0.0.1:6667";
+pub const IRC_CHANNEL: &str = "#tavern";
+pub const IRC_SERVER_NAME: &str = "tavern";
+pub const IRC_TOPIC: &str = "Welcome to the Tavern! Pull up a chair.";
+
+/// Registration and channel state of a connected IRC client.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IrcSession {
+    pub username: Option<String>,
+    pub registered: bool,
+    pub joined: bool,
+}
+
+/// A single parsed line sent by an IRC client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrcCommand {
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl IrcCommand {
+    /// Parses a raw IRC line. The optional prefix is discarded, since clients are identified
+    /// by their connection rather than by what they claim to be.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(['\r', '\n']).trim_start();
+        if rest.starts_with(':') {
+            rest = rest
+                .split_once(' ')
+                .map(|(_, r)| r.trim_start())
+                .unwrap_or("");
+        }
+
+        let (middle, trailing) = match rest.split_once(" :") {
+            Some((middle, trailing)) => (middle, Some(trailing)),
+            None => (rest, None),
+        };
+        let mut words = middle.split_whitespace();
+        let command = words.next()?.to_ascii_uppercase();
+        let mut params = words.map(str::to_owned).collect::<Vec<_>>();
+        if let Some(trailing) = trailing {
+            params.push(trailing.to_owned());
+        }
+
+        Some(IrcCommand { command, params })
+    }
+
+    pub fn param(&self, index: usize) -> Option<&str> {
+        self.params.get(index).map(String::as_str)
+    }
+}
+
+/// Formats an outgoing IRC line. The last parameter is always sent as a trailing parameter.
+pub fn format_line(prefix: &str, command: &str, params: &[&str]) -> String {
+    let mut line = format!(":{prefix} {command}");
+    if let Some((last, middle)) = params.split_last() {
+        for param in middle {
+            line.push(' ');
+            line.push_str(param);
+        }
+        line.push_str(" :");
+        line.push_str(last);
+    }
+    line.push_str("\r\n");
+    line
+}
+
+/// Formats a numeric reply sent by the server to `nick`.
+pub fn numeric(code: &str, nick: &str, params: &[&str]) -> String {
+    let mut all = vec![nick];
+    all.extend_from_slice(params);
+    format_line(IRC_SERVER_NAME, code, &all)
+}
+
+/// Formats a tavern message sent to `target`, which is either the channel or a nickname.
+/// Messages without a sender are relayed as server notices, one line per line of content.
+pub fn message_line(from: Option<&str>, target: &str, message: &Message) -> String {
+    let text = match (message.kind, &message.tone) {
+        // Actions are sent as CTCP ACTIONs, which clients show as `* nick does something`.
+        (MessageKind::Action | MessageKind::Roll, _) => message
+            .content
+            .lines()
+            .map(|line| format!("\x01ACTION {line}\x01\n"))
+            .collect(),
+        (MessageKind::Speech, tone) if *tone == MessageTone::SAID => message.content.clone(),
+        (MessageKind::Emote, _) => message.content.clone(),
+        (MessageKind::Speech, tone) => format!("*{tone}* {}", message.content),
+    };
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| match from {
+            Some(prefix) => format_line(prefix, "PRIVMSG", &[target, line]),
+            None => format_line(IRC_SERVER_NAME, "NOTICE", &[target, line]),
+        })
+        .collect()
+}
+
+/// The `nick!user@host` prefix used for messages originating from a tavern patron.
+pub fn user_prefix(nick: &str) -> String {
+    format!("{nick}!{nick}@{IRC_SERVER_NAME}")
+}
+
+/// Returns true if the nickname can be used by an IRC client.
+/// `user<N>` and `npc<N>` are reserved for the generated names of other patrons.
+pub fn is_valid_nick(nick: &str) -> bool {
+    let reserved = ["user", "npc"].iter().any(|prefix| {
+        nick.get(..prefix.len())
+            .is_some_and(|p| p.eq_ignore_ascii_case(prefix))
+            && nick[prefix.len()..].parse::<u32>().is_ok()
+    });
+
+    !reserved
+        && nick.len() <= 30
+        && nick.chars().next().is_some_and(|c| c.is_ascii_alphabetic())
+        && nick
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_[]\\`^{}|".contains(c))
+}
+
+/// Turns an arbitrary display name into something usable as an IRC nickname.
+pub fn sanitize_nick(name: &str) -> String {
+    name.chars()
+        .filter_map(|c| match c {
+            c if c.is_ascii_alphanumeric() || "-_[]\\`^{}|".contains(c) => Some(c),
+            ' ' => Some('_'),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn can_parse_commands_with_prefix_and_trailing() {
+        assert_eq!(
+            IrcCommand::parse(":alice!a@host PRIVMSG #tavern :hello there\r\n"),
+            Some(IrcCommand {
+                command: "PRIVMSG".to_string(),
+                params: vec!["#tavern".to_string(), "hello there".to_string()],
+            })
+        );
+        assert_eq!(
+            IrcCommand::parse("nick bob"),
+            Some(IrcCommand {
+                command: "NICK".to_string(),
+                params: vec!["bob".to_string()],
+            })
+        );
+        assert_eq!(IrcCommand::parse("   "), None);
+    }
+
+    #[test]
+    fn can_format_lines() {
+        assert_eq!(
+            format_line("bob!bob@tavern", "PRIVMSG", &["#tavern", "hi all"]),
+            ":bob!bob@tavern PRIVMSG #tavern :hi all\r\n"
+        );
+        assert_eq!(
+            numeric("001", "bob", &["Welcome"]),
+            ":tavern 001 bob :Welcome\r\n"
+        );
+        let mut action = Message::new(None, Default::default(), "sighs", None);
+        action.kind = MessageKind::Action;
+        assert_eq!(
+            message_line(Some("bob!bob@tavern"), "#tavern", &action),
+            ":bob!bob@tavern PRIVMSG #tavern :\x01ACTION sighs\x01\r\n"
+        );
+    }
+
+    #[test]
+    fn reserved_nicks_are_rejected() {
+        assert!(is_valid_nick("alice"));
+        assert!(!is_valid_nick("user3"));
+        assert!(!is_valid_nick("NPC12"));
+        assert!(is_valid_nick("username"));
+        assert!(!is_valid_nick("9lives"));
+        assert_eq!(sanitize_nick("Old Tom!"), "Old_Tom");
+    }
+}
@@ -0,0 +1,363 @@
+//! Contains the journal: an append-only log of every message and moderation action, on disk.
+//! Entries are written as JSON lines and synced one by one, so a crash loses at most the entry
+//! being written. Files are rotated daily, or once they grow past a maximum length. Deleted
+//! messages are the one thing rewritten: their content is scrubbed from the files.
+
+use chrono::{Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::common::{Message, MessageId, ModerationAction, UserId, unix_millis};
+
+/// Journal files are rotated once they grow past this length, in bytes.
+pub const JOURNAL_MAX_LEN: u64 = 16 * 1024 * 1024;
+const JOURNAL_EXTENSION: &str = "jsonl";
+
+/// A single line of the journal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JournalEntry {
+    Message(Box<Message>),
+    Moderation(ModerationAction),
+    Edit {
+        id: MessageId,
+        content: String,
+        #[serde(with = "unix_millis")]
+        timestamp: SystemTime,
+    },
+    Delete {
+        id: MessageId,
+        #[serde(with = "unix_millis")]
+        timestamp: SystemTime,
+    },
+    React {
+        id: MessageId,
+        by: UserId,
+        reaction: String,
+        #[serde(with = "unix_millis")]
+        timestamp: SystemTime,
+    },
+}
+
+#[derive(Debug)]
+pub struct Journal {
+    dir: PathBuf,
+    max_len: u64,
+    file: File,
+    day: NaiveDate,
+    sequence: u32,
+    len: u64,
+}
+
+impl Journal {
+    /// Opens the journal in `dir`, creating it if needed, and carries on with today's last file.
+    pub fn open(dir: &Path, max_len: u64) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let day = Local::now().date_naive();
+        let sequence = journal_files(dir)?
+            .iter()
+            .filter_map(|path| parse_file_name(path))
+            .filter(|(file_day, _)| *file_day == day)
+            .map(|(_, sequence)| sequence)
+            .max()
+            .unwrap_or_default();
+        let (file, len) = open_file(&file_path(dir, day, sequence))?;
+        let mut journal = Self {
+            dir: dir.to_owned(),
+            max_len,
+            file,
+            day,
+            sequence,
+            len,
+        };
+        if journal.len >= journal.max_len {
+            journal.rotate(day)?;
+        }
+        Ok(journal)
+    }
+
+    /// Reads every entry of the journal, oldest first.
+    pub fn replay(&self) -> io::Result<Vec<JournalEntry>> {
+        read_journal(&self.dir)
+    }
+
+    /// Appends an entry, rotating the file first if it's a new day or the file is full.
+    /// Returns once the entry is safely on disk.
+    pub fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        let today = Local::now().date_naive();
+        if today != self.day || self.len >= self.max_len {
+            self.rotate(today)?;
+        }
+
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+        self.len += line.len() as u64;
+        Ok(())
+    }
+
+    /// Blanks out the content of a deleted message, of its edits and of its quote in replies,
+    /// in every file of the journal. Files holding it are rewritten at once, so a crash leaves them intact. This
+    /// reads the whole journal, which is fine for something as rare as a deletion.
+    pub fn scrub(&mut self, id: MessageId) -> io::Result<()> {
+        for path in journal_files(&self.dir)? {
+            let mut scrubbed = false;
+            let mut lines = vec![];
+            for line in BufReader::new(File::open(&path)?).lines() {
+                let line = line?;
+                let entry = match serde_json::from_str(&line) {
+                    Ok(JournalEntry::Message(mut message)) if message.id == Some(id) => {
+                        message.content.clear();
+                        JournalEntry::Message(message)
+                    }
+                    Ok(JournalEntry::Message(mut message))
+                        if message.reply_to.as_ref().is_some_and(|r| r.id == id) =>
+                    {
+                        if let Some(reply_to) = &mut message.reply_to {
+                            reply_to.forget_quote();
+                        }
+                        JournalEntry::Message(message)
+                    }
+                    Ok(JournalEntry::Edit {
+                        id: edited,
+                        timestamp,
+                        ..
+                    }) if edited == id => JournalEntry::Edit {
+                        id,
+                        content: String::new(),
+                        timestamp,
+                    },
+                    _ => {
+                        lines.push(line);
+                        continue;
+                    }
+                };
+                scrubbed = true;
+                lines.push(serde_json::to_string(&entry)?);
+            }
+            if !scrubbed {
+                continue;
+            }
+
+            let temp_path = path.with_extension("tmp");
+            let mut file = File::create(&temp_path)?;
+            for line in &lines {
+                writeln!(file, "{line}")?;
+            }
+            file.sync_data()?;
+            fs::rename(temp_path, &path)?;
+            if path == file_path(&self.dir, self.day, self.sequence) {
+                (self.file, self.len) = open_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self, day: NaiveDate) -> io::Result<()> {
+        self.sequence = if day == self.day {
+            self.sequence + 1
+        } else {
+            0
+        };
+        self.day = day;
+        (self.file, self.len) = open_file(&file_path(&self.dir, day, self.sequence))?;
+        Ok(())
+    }
+}
+
+/// Reads every entry of the journal in `dir`, oldest first, without writing to it.
+/// Lines that can't be read, such as one cut short by a crash, are skipped.
+pub fn read_journal(dir: &Path) -> io::Result<Vec<JournalEntry>> {
+    let mut entries = vec![];
+    for path in journal_files(dir)? {
+        for line in BufReader::new(File::open(&path)?).lines() {
+            match serde_json::from_str(&line?) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => println!("📜 Skipping unreadable journal line in {path:?}: {e}"),
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Opens a journal file for appending. Returns it with its current length.
+fn open_file(path: &Path) -> io::Result<(File, u64)> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+    let mut len = file.metadata()?.len();
+
+    // A crash may have cut the last line short. Start on a fresh line, so only that one is lost.
+    if len > 0 {
+        let mut last = [0u8];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            file.write_all(b"\n")?;
+            file.sync_data()?;
+            len += 1;
+        }
+    }
+    Ok((file, len))
+}
+
+/// Journal files are named after their day and sequence number, so they sort in order.
+fn file_path(dir: &Path, day: NaiveDate, sequence: u32) -> PathBuf {
+    dir.join(format!(
+        "{}-{sequence:04}.{JOURNAL_EXTENSION}",
+        day.format("%Y-%m-%d")
+    ))
+}
+
+fn parse_file_name(path: &Path) -> Option<(NaiveDate, u32)> {
+    let stem = path.file_stem()?.to_str()?;
+    let (day, sequence) = stem.rsplit_once('-')?;
+    Some((
+        NaiveDate::parse_from_str(day, "%Y-%m-%d").ok()?,
+        sequence.parse().ok()?,
+    ))
+}
+
+/// Every journal file in `dir`, oldest first.
+fn journal_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension().is_some_and(|ext| ext == JOURNAL_EXTENSION)
+                && parse_file_name(path).is_some()
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::{ChatTarget, DELETED_QUOTE, ReplyTo};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("tavern-journal-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// Timestamps are journaled in milliseconds, so entries are compared by content.
+    fn contents(entries: Vec<JournalEntry>) -> Vec<String> {
+        entries
+            .into_iter()
+            .map(|entry| match entry {
+                JournalEntry::Message(message) => message.content,
+                JournalEntry::Moderation(action) => action.action,
+                JournalEntry::Edit { content, .. } => content,
+                JournalEntry::Delete { id, .. } => id.to_string(),
+                JournalEntry::React { reaction, .. } => reaction,
+            })
+            .collect()
+    }
+
+    fn message(content: &str) -> JournalEntry {
+        JournalEntry::Message(Box::new(Message::new(
+            Some(ChatTarget::user(1)),
+            ChatTarget::Global,
+            content,
+            None,
+        )))
+    }
+
+    #[test]
+    fn entries_survive_reopening_and_rotation() {
+        let dir = temp_dir("rotation");
+        let mut journal = Journal::open(&dir, 100).unwrap();
+        for i in 0..3 {
+            journal.append(&message(&format!("Round {i}"))).unwrap();
+        }
+        // Each entry is longer than the maximum length, so each got its own file.
+        assert_eq!(journal_files(&dir).unwrap().len(), 3);
+        drop(journal);
+
+        let journal = Journal::open(&dir, 100).unwrap();
+        assert_eq!(
+            contents(journal.replay().unwrap()),
+            vec!["Round 0", "Round 1", "Round 2"]
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn deleted_messages_are_scrubbed() {
+        let dir = temp_dir("scrub");
+        let mut journal = Journal::open(&dir, JOURNAL_MAX_LEN).unwrap();
+        let mut secret = Message::new(
+            Some(ChatTarget::user(1)),
+            ChatTarget::Global,
+            "The key is under the mat.",
+            None,
+        );
+        secret.id = Some(MessageId(7));
+        journal.append(&message("Kept")).unwrap();
+        journal
+            .append(&JournalEntry::Message(Box::new(secret)))
+            .unwrap();
+        journal
+            .append(&JournalEntry::Edit {
+                id: MessageId(7),
+                content: "The key is in the well.".to_owned(),
+                timestamp: SystemTime::now(),
+            })
+            .unwrap();
+        let mut reply = Message::new(
+            Some(ChatTarget::user(2)),
+            ChatTarget::Global,
+            "Thanks!",
+            None,
+        );
+        reply.reply_to = Some(ReplyTo::new(
+            MessageId(7),
+            "user1",
+            "The key is under the mat.",
+        ));
+        journal
+            .append(&JournalEntry::Message(Box::new(reply)))
+            .unwrap();
+        journal.scrub(MessageId(7)).unwrap();
+        journal.append(&message("After")).unwrap();
+
+        let entries = journal.replay().unwrap();
+        let Some(JournalEntry::Message(reply)) = entries.get(3) else {
+            panic!("The reply is gone: {entries:?}");
+        };
+        assert_eq!(reply.reply_to.as_ref().unwrap().quote, DELETED_QUOTE);
+        assert_eq!(contents(entries), vec!["Kept", "", "", "Thanks!", "After"]);
+        let files = journal_files(&dir).unwrap();
+        assert!(
+            files
+                .iter()
+                .all(|path| { !fs::read_to_string(path).unwrap().contains("under the mat") })
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn lines_cut_short_by_a_crash_are_skipped() {
+        let dir = temp_dir("crash");
+        let mut journal = Journal::open(&dir, JOURNAL_MAX_LEN).unwrap();
+        journal.append(&message("Before")).unwrap();
+        journal.file.write_all(b"{\"type\":\"mess").unwrap();
+        drop(journal);
+
+        let mut journal = Journal::open(&dir, JOURNAL_MAX_LEN).unwrap();
+        journal.append(&message("After")).unwrap();
+        assert_eq!(contents(journal.replay().unwrap()), vec!["Before", "After"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,31 @@
+////////////////////////////////////////////////////////////////////////////////////////////////
+//! This project simulates a Fantasy style Tavern.
+//! A main server can be connected via TCP connections. Connected client can interact with
+//! other clients and NPCs.
+//!
+//! The library exposes the server and its builder, the types shared between server and
+//! clients, the parser and NPC APIs, and a client for writing bots in Rust.
+//!
+
+pub mod accounts;
+pub mod client;
+pub mod common;
+pub mod config;
+pub mod dice;
+pub mod emotes;
+pub mod games;
+pub mod http;
+pub mod irc;
+pub mod journal;
+pub mod npcs;
+pub mod parser;
+pub mod rooms;
+pub mod search;
+pub mod server;
+pub mod tones;
+pub mod transcript;
+pub mod trivia;
+
+pub use client::TavernClient;
+pub use config::{ServerConfig, TavernServerBuilder};
+pub use server::TavernServer;
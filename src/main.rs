@@ -8,18 +8,185 @@
 //! Roy Sirui Yang 2025
 //!
 
-use crate::server::TavernServer;
+use anyhow::{Context, bail};
+use std::{path::Path, time::Duration};
+use tavern_chat::{
+    TavernServer, TavernServerBuilder,
+    config::ADMIN_PASSWORD_ENV,
+    http::ApiTokens,
+    transcript::{self, TranscriptQuery},
+};
 
-mod common;
-mod npcs;
-mod parser;
-mod server;
+const USAGE: &str = "Usage: tavern-chat [OPTIONS]
+       tavern-chat export [EXPORT OPTIONS] [OPTIONS]
+
+Options:
+  --plain <address|off>   Listener for plain text clients (default 127.0.0.1:8080)
+  --json <address|off>    Listener for JSON clients and bots (default 127.0.0.1:8081)
+  --irc <address|off>     Listener for IRC clients (default 127.0.0.1:6667)
+  --http <address|off>    Listener for the HTTP API (default 127.0.0.1:8088)
+  --history <n>           Number of messages kept in history (default 100)
+  --replay <n>            Number of messages replayed to new clients (default 20)
+  --journal <dir|off>     Directory where messages are journaled to survive restarts (default off)
+  --accounts <file|off>   File where registered accounts and their mail are saved (default off)
+  --mailbox <n>           Number of mails each mailbox holds (default 50)
+  --export-dir <dir>      Directory where admins export transcripts (default transcripts)
+  --emotes <file>         JSON file defining the emotes (default emotes built in)
+  --tones <file>          JSON file defining the tones (default tones built in)
+  --idle <minutes>        Minutes without input before a patron is shown as idle (default 10)
+  --turn <seconds>        Seconds players of a game have to play their turn (default 60)
+  --trivia <room|off>     Room where the quizmaster runs trivia (default off)
+  --questions <file>      JSON file holding the trivia questions (default questions built in)
+  --scores <file|off>     File where the trivia leaderboard is saved (default off)
+  --trivia-time <secs>    Seconds to answer a trivia question, and until the next (default 45)
+  --room <name>           Adds a room to the tavern. Can be repeated
+  --npc <name>[@<room>]   Adds an NPC to the tavern, sitting in a room if given. Can be repeated
+  -h, --help              Prints this message
+
+Export options, to write a transcript of the journal given with --journal:
+  --format <json|text|html>  Format of the transcript (default text)
+  --in <room>                Only exports messages sent to this room
+  --since <time>             Only exports messages sent from this time on, as YYYY-MM-DD[THH:MM]
+  --until <time>             Only exports messages sent until this time
+  --out <file>               File to write the transcript to (default standard output)
+
+API tokens are read from TAVERN_API_TOKENS, as comma separated <bot name>:<token> pairs.
+The admin password is read from TAVERN_ADMIN_PASSWORD.";
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let (mut server, _event_tx) = TavernServer::new();
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if(|arg| arg == "export").is_some() {
+        return export(args);
+    }
+    let Some(builder) = parse_args(args)? else {
+        println!("{USAGE}");
+        return Ok(());
+    };
+    let (mut server, _event_tx) = builder
+        .api_tokens(ApiTokens::from_env())
+        .admin_password(std::env::var(ADMIN_PASSWORD_ENV).ok().as_deref())
+        .build();
     let handle = server.run();
 
     // Run until server exits.
     handle.await
 }
+
+/// Writes a transcript of the journal, without starting the server.
+/// The server options are used to know the rooms of the tavern.
+fn export(mut args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let mut query = TranscriptQuery::default();
+    let mut out = None;
+    let mut server_args = vec![];
+    while let Some(arg) = args.next() {
+        let value = match arg.as_str() {
+            "--format" | "--in" | "--since" | "--until" | "--out" => args
+                .next()
+                .with_context(|| format!("Missing value for {arg}\n\n{USAGE}"))?,
+            _ => {
+                server_args.push(arg);
+                continue;
+            }
+        };
+        match arg.as_str() {
+            "--format" => query.format = value.parse().map_err(anyhow::Error::msg)?,
+            "--in" => query.room = Some(value),
+            "--since" => {
+                query.since =
+                    Some(transcript::parse_time(&value, false).map_err(anyhow::Error::msg)?)
+            }
+            "--until" => {
+                query.until =
+                    Some(transcript::parse_time(&value, true).map_err(anyhow::Error::msg)?)
+            }
+            _ => out = Some(value),
+        }
+    }
+    let Some(builder) = parse_args(server_args.into_iter())? else {
+        println!("{USAGE}");
+        return Ok(());
+    };
+    if builder.config().journal_dir.is_none() {
+        bail!("Nothing to export without a journal. Use --journal <dir>\n\n{USAGE}");
+    }
+
+    let (mut server, _event_tx) = builder.build();
+    server.restore_history()?;
+    let transcript = server.transcript(&query).map_err(anyhow::Error::msg)?;
+    match out {
+        Some(path) => std::fs::write(&path, transcript)
+            .with_context(|| format!("Failed to write the transcript to {path:?}"))?,
+        None => print!("{transcript}"),
+    }
+    Ok(())
+}
+
+/// Parses command line arguments into a server builder. Returns None if help was requested.
+fn parse_args(
+    mut args: impl Iterator<Item = String>,
+) -> anyhow::Result<Option<TavernServerBuilder>> {
+    let mut builder = TavernServer::builder();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(None);
+        }
+        let value = args
+            .next()
+            .with_context(|| format!("Missing value for {arg}\n\n{USAGE}"))?;
+        let address = (value != "off").then_some(value.as_str());
+        builder = match arg.as_str() {
+            "--plain" => builder.plain_address(address),
+            "--json" => builder.json_address(address),
+            "--irc" => builder.irc_address(address),
+            "--http" => builder.http_address(address),
+            "--history" => builder.history_len(
+                value
+                    .parse()
+                    .with_context(|| format!("Invalid history length {value:?}"))?,
+            ),
+            "--replay" => builder.replay_len(
+                value
+                    .parse()
+                    .with_context(|| format!("Invalid replay length {value:?}"))?,
+            ),
+            "--journal" => builder.journal_dir(address.map(Path::new)),
+            "--accounts" => builder.accounts_file(address.map(Path::new)),
+            "--mailbox" => builder.mailbox_len(
+                value
+                    .parse()
+                    .with_context(|| format!("Invalid mailbox length {value:?}"))?,
+            ),
+            "--export-dir" => builder.export_dir(Path::new(&value)),
+            "--emotes" => builder.emotes_file(Some(Path::new(&value))),
+            "--tones" => builder.tones_file(Some(Path::new(&value))),
+            "--idle" => builder.idle_after(Duration::from_secs(
+                value
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|minutes| minutes.checked_mul(60))
+                    .with_context(|| format!("Invalid idle time {value:?}"))?,
+            )),
+            "--turn" => builder.game_turn_timeout(Duration::from_secs(
+                value
+                    .parse()
+                    .with_context(|| format!("Invalid turn time {value:?}"))?,
+            )),
+            "--trivia" => builder.trivia_room(address),
+            "--questions" => builder.trivia_file(Some(Path::new(&value))),
+            "--scores" => builder.trivia_scores_file(address.map(Path::new)),
+            "--trivia-time" => builder.trivia_interval(Duration::from_secs(
+                value
+                    .parse()
+                    .with_context(|| format!("Invalid trivia interval {value:?}"))?,
+            )),
+            "--room" => builder.room(&value),
+            "--npc" => match value.split_once('@') {
+                Some((name, room)) => builder.npc_in_room(name, room),
+                None => builder.npc(&value),
+            },
+            _ => bail!("Unknown option {arg}\n\n{USAGE}"),
+        };
+    }
+    Ok(Some(builder))
+}
@@ -1,13 +1,36 @@
 //! Contains code for NPC info, state and behavior
+use serde::Serialize;
 use std::time::Instant;
 
 #[derive(Debug)]
 pub struct Npc {
     name: String,
     state: NpcState,
+    #[allow(dead_code)]
     last_active: Instant,
 }
 
+impl Npc {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn state(&self) -> &NpcState {
+        &self.state
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        matches!(self.state, NpcState::Disabled)
+    }
+}
+
 impl Default for Npc {
     fn default() -> Self {
         Self {
@@ -18,9 +41,11 @@ impl Default for Npc {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum NpcState {
     #[default]
     Idle,
+    #[allow(dead_code)]
     Disabled,
 }
@@ -2,6 +2,10 @@
 //! We only need to parse incoming messages from a user.
 
 use crate::common::*;
+use crate::dice::DiceExpression;
+use crate::games::GameKind;
+use crate::search::SearchQuery;
+use crate::transcript::TranscriptQuery;
 use tokio::sync::mpsc::Sender;
 
 pub async fn parse_incoming_message(
@@ -10,7 +14,7 @@ pub async fn parse_incoming_message(
     event_tx: Sender<Event>,
     client_ctx: &mut ClientContext,
 ) -> ServerResult {
-    println!("{:?}: {:?}", from, message_raw);
+    println!("{:?}: {:?}", from, redact_passwords(&message_raw));
     if message_raw.is_empty() {
         return Ok(());
     }
@@ -18,30 +22,36 @@ pub async fn parse_incoming_message(
     let mut reply = None;
 
     if message_raw.starts_with('/') {
-        let split = message_raw.splitn(2, " ").into_iter().collect::<Vec<_>>();
-        let (command, msg) = (*split.get(0).unwrap(), *split.get(1).unwrap_or(&""));
+        let split = message_raw.splitn(2, " ").collect::<Vec<_>>();
+        let (command, msg) = (*split.first().unwrap(), *split.get(1).unwrap_or(&""));
 
         match command.to_ascii_lowercase().as_str() {
             // Command related to Saying something
             "/say" | "/s" => {
                 reply =
-                    say_something(from, msg, client_ctx, &event_tx, Some(MessageTone::Said)).await
+                    say_something(from, msg, client_ctx, &event_tx, Some(MessageTone::SAID)).await
             }
             "/yell" => {
                 reply =
-                    say_something(from, msg, client_ctx, &event_tx, Some(MessageTone::Yelled)).await
+                    say_something(from, msg, client_ctx, &event_tx, Some(MessageTone::YELLED)).await
             }
             "/laugh" => {
-                reply = say_something(from, msg, client_ctx, &event_tx, Some(MessageTone::Laughed))
+                reply = say_something(from, msg, client_ctx, &event_tx, Some(MessageTone::LAUGHED))
                     .await
             }
+            "/me" => {
+                reply = match msg.trim() {
+                    "" => Some("Invalid action. please use /me <action>".to_string()),
+                    action => act(from, action, client_ctx, &event_tx).await,
+                }
+            }
             "/whisper" | "/w" => {
                 reply = say_something(
                     from,
                     msg,
                     client_ctx,
                     &event_tx,
-                    Some(MessageTone::Whispered),
+                    Some(MessageTone::WHISPERED),
                 )
                 .await
             }
@@ -71,6 +81,28 @@ pub async fn parse_incoming_message(
                     reply = Some("Invalid target. please use /to_npc <id>".to_string());
                 }
             }
+            // Whisper to a user without changing the chat target
+            "/tell" => {
+                let (target, msg) = msg.split_once(' ').unwrap_or((msg, ""));
+                match target.parse::<u32>() {
+                    Ok(target_id) if !msg.is_empty() => {
+                        let _ = event_tx
+                            .send(Event::BroadcastMessage {
+                                message: Message::new(
+                                    Some(ChatTarget::User(from)),
+                                    ChatTarget::user(target_id),
+                                    msg,
+                                    Some(MessageTone::WHISPERED),
+                                ),
+                            })
+                            .await;
+                        reply = Some(format!("To {}: {}", UserId(target_id), msg));
+                    }
+                    _ => {
+                        reply = Some("Invalid target. please use /tell <id> <message>".to_string())
+                    }
+                }
+            }
             "/to_world" | "/to_everyone" | "/global" => {
                 let _ = event_tx
                     .send(Event::ChangeTarget {
@@ -79,60 +111,398 @@ pub async fn parse_incoming_message(
                     })
                     .await;
             }
-            // Emote
-            "/wave" => {
-                say_something(
-                    from,
-                    format!("You waved at {}. Wassup?", client_ctx.current_target).as_str(),
-                    client_ctx,
-                    &event_tx,
-                    None,
-                )
-                .await;
+            // Move between the rooms of the tavern
+            "/join" => {
+                if msg.trim().is_empty() {
+                    reply = Some("Invalid room. please use /join <room>".to_string());
+                } else {
+                    let _ = event_tx
+                        .send(Event::Command {
+                            from,
+                            command: ServerCommand::JoinRoom(msg.trim().to_owned()),
+                        })
+                        .await;
+                }
             }
-            "/poke" => {
-                say_something(
-                    from,
-                    format!("You poked {}. Hey!", client_ctx.current_target).as_str(),
-                    client_ctx,
-                    &event_tx,
-                    None,
-                )
-                .await;
+            "/leave" => {
+                let _ = event_tx
+                    .send(Event::Command {
+                        from,
+                        command: ServerCommand::LeaveRoom,
+                    })
+                    .await;
             }
-            "/lol" => {
-                say_something(
-                    from,
-                    "You laughed out loud. A ha HA!",
-                    client_ctx,
-                    &event_tx,
-                    Some(MessageTone::Laughed),
-                )
-                .await;
+            "/rooms" => {
+                let _ = event_tx
+                    .send(Event::Command {
+                        from,
+                        command: ServerCommand::ListRooms,
+                    })
+                    .await;
             }
-            "/cry" => {
-                say_something(
-                    from,
-                    format!(
-                        "You cried on {}'s shoulder. There there.",
-                        client_ctx.current_target
-                    )
-                    .as_str(),
-                    client_ctx,
-                    &event_tx,
-                    None,
-                )
-                .await;
+            "/history" => match msg.trim() {
+                "" => {
+                    let _ = event_tx
+                        .send(Event::Command {
+                            from,
+                            command: ServerCommand::History(None),
+                        })
+                        .await;
+                }
+                n => match n.parse::<usize>() {
+                    Ok(n) => {
+                        let _ = event_tx
+                            .send(Event::Command {
+                                from,
+                                command: ServerCommand::History(Some(n)),
+                            })
+                            .await;
+                    }
+                    Err(_) => reply = Some("Invalid count. please use /history [n]".to_string()),
+                },
+            },
+            "/edit" => {
+                let (id, content) = msg.trim().split_once(' ').unwrap_or((msg.trim(), ""));
+                match id.parse::<MessageId>() {
+                    Ok(id) if !content.trim().is_empty() => {
+                        let _ = event_tx
+                            .send(Event::Command {
+                                from,
+                                command: ServerCommand::Edit {
+                                    id,
+                                    content: content.trim().to_owned(),
+                                },
+                            })
+                            .await;
+                    }
+                    _ => reply = Some("Invalid edit. please use /edit <id> <message>".to_string()),
+                }
             }
-            "/dance" => {
-                say_something(
-                    from,
-                    "You danced on top of a table! What a jolly time!",
-                    client_ctx,
-                    &event_tx,
-                    None,
-                )
-                .await;
+            "/delete" => match msg.trim().parse::<MessageId>() {
+                Ok(id) => {
+                    let _ = event_tx
+                        .send(Event::Command {
+                            from,
+                            command: ServerCommand::Delete(id),
+                        })
+                        .await;
+                }
+                Err(_) => reply = Some("Invalid message. please use /delete <id>".to_string()),
+            },
+            "/react" => {
+                let (id, reaction) = msg.trim().split_once(' ').unwrap_or((msg.trim(), ""));
+                match id.parse::<MessageId>() {
+                    // Reactions are a single emoji or word.
+                    Ok(id) if !reaction.trim().is_empty() && !reaction.trim().contains(' ') => {
+                        let _ = event_tx
+                            .send(Event::Command {
+                                from,
+                                command: ServerCommand::React {
+                                    id,
+                                    reaction: reaction.trim().to_owned(),
+                                },
+                            })
+                            .await;
+                    }
+                    _ => {
+                        reply = Some(
+                            "Invalid reaction. please use /react <id> <emoji or word>".to_string(),
+                        )
+                    }
+                }
+            }
+            "/reply" => {
+                let (id, content) = msg.trim().split_once(' ').unwrap_or((msg.trim(), ""));
+                match id.parse::<MessageId>() {
+                    Ok(id) if !content.trim().is_empty() => {
+                        let _ = event_tx
+                            .send(Event::Command {
+                                from,
+                                command: ServerCommand::Reply {
+                                    id,
+                                    content: content.trim().to_owned(),
+                                },
+                            })
+                            .await;
+                    }
+                    _ => {
+                        reply = Some("Invalid reply. please use /reply <id> <message>".to_string())
+                    }
+                }
+            }
+            "/thread" => match msg.trim().parse::<MessageId>() {
+                Ok(id) => {
+                    let _ = event_tx
+                        .send(Event::Command {
+                            from,
+                            command: ServerCommand::Thread(id),
+                        })
+                        .await;
+                }
+                Err(_) => reply = Some("Invalid message. please use /thread <id>".to_string()),
+            },
+            "/mentions" => {
+                let _ = event_tx
+                    .send(Event::Command {
+                        from,
+                        command: ServerCommand::Mentions,
+                    })
+                    .await;
+            }
+            "/search" => match SearchQuery::parse(msg) {
+                Ok(query) => {
+                    let _ = event_tx
+                        .send(Event::Command {
+                            from,
+                            command: ServerCommand::Search(query),
+                        })
+                        .await;
+                }
+                Err(e) => {
+                    reply = Some(format!(
+                        "{e} Please use /search <terms> [from:<user>] [since:<YYYY-MM-DD>]"
+                    ))
+                }
+            },
+            "/admin" => {
+                let _ = event_tx
+                    .send(Event::Command {
+                        from,
+                        command: ServerCommand::Admin(Password(msg.trim().to_owned())),
+                    })
+                    .await;
+            }
+            "/export" => match TranscriptQuery::parse(msg) {
+                Ok(query) => {
+                    let _ = event_tx
+                        .send(Event::Command {
+                            from,
+                            command: ServerCommand::Export(query),
+                        })
+                        .await;
+                }
+                Err(e) => {
+                    reply = Some(format!(
+                        "{e} Please use /export <json|text|html> [room:<name>] [since:<time>] [until:<time>]"
+                    ))
+                }
+            },
+            // Accounts
+            "/register" | "/login" => match msg.trim().split_once(' ') {
+                Some((name, password)) if !password.trim().is_empty() => {
+                    let (name, password) = (name.to_owned(), Password(password.trim().to_owned()));
+                    let command = if command.eq_ignore_ascii_case("/register") {
+                        ServerCommand::Register { name, password }
+                    } else {
+                        ServerCommand::Login { name, password }
+                    };
+                    let _ = event_tx.send(Event::Command { from, command }).await;
+                }
+                _ => {
+                    reply = Some(format!(
+                        "Invalid account. please use {command} <name> <password>"
+                    ))
+                }
+            },
+            "/mail" => {
+                let (action, args) = msg.trim().split_once(' ').unwrap_or((msg.trim(), ""));
+                let command = match (action.to_ascii_lowercase().as_str(), args.trim()) {
+                    ("list" | "", _) => Some(MailCommand::List),
+                    ("read", n) => n.parse().ok().map(MailCommand::Read),
+                    ("delete", n) => n.parse().ok().map(MailCommand::Delete),
+                    ("send", args) => match args.split_once(' ') {
+                        Some((to, content)) if !content.trim().is_empty() => {
+                            Some(MailCommand::Send {
+                                to: to.to_owned(),
+                                content: content.trim().to_owned(),
+                            })
+                        }
+                        _ => None,
+                    },
+                    _ => None,
+                };
+                if let Some(command) = command {
+                    let _ = event_tx
+                        .send(Event::Command {
+                            from,
+                            command: ServerCommand::Mail(command),
+                        })
+                        .await;
+                } else {
+                    reply = Some(
+                        "Invalid mail command. please use /mail list, /mail read <n>, \
+                         /mail delete <n> or /mail send <name> <message>"
+                            .to_string(),
+                    );
+                }
+            }
+            // Presence
+            "/away" => {
+                let reason = msg.trim();
+                client_ctx.away = Some(reason.to_owned());
+                reply = Some(if reason.is_empty() {
+                    "You are now away. Use /back when you return.".to_string()
+                } else {
+                    format!("You are now away: {reason}. Use /back when you return.")
+                });
+            }
+            "/back" => {
+                reply = Some(if client_ctx.away.take().is_some() {
+                    "Welcome back!".to_string()
+                } else {
+                    "You weren't away.".to_string()
+                });
+            }
+            "/who" => {
+                let _ = event_tx
+                    .send(Event::Command {
+                        from,
+                        command: ServerCommand::Who,
+                    })
+                    .await;
+            }
+            "/whois" => {
+                if msg.trim().is_empty() {
+                    reply = Some("Invalid user. please use /whois <user>".to_string());
+                } else {
+                    let _ = event_tx
+                        .send(Event::Command {
+                            from,
+                            command: ServerCommand::Whois(msg.trim().to_owned()),
+                        })
+                        .await;
+                }
+            }
+            "/booth" => {
+                let (action, args) = msg.split_once(' ').unwrap_or((msg, ""));
+                // Booth names are a single word, optionally followed by a password.
+                let (name, password) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+                let password =
+                    (!password.trim().is_empty()).then(|| Password(password.trim().to_owned()));
+                let command = match (action.to_ascii_lowercase().as_str(), name) {
+                    ("create", name) if !name.is_empty() => Some(BoothCommand::Create {
+                        name: name.to_owned(),
+                        password,
+                    }),
+                    ("join", name) if !name.is_empty() => Some(BoothCommand::Join {
+                        name: name.to_owned(),
+                        password,
+                    }),
+                    ("invite", name) if !name.is_empty() => {
+                        Some(BoothCommand::Invite(name.to_owned()))
+                    }
+                    ("kick", name) if !name.is_empty() => Some(BoothCommand::Kick(name.to_owned())),
+                    ("leave", "") => Some(BoothCommand::Leave(None)),
+                    ("leave", name) => Some(BoothCommand::Leave(Some(name.to_owned()))),
+                    ("list" | "", _) => Some(BoothCommand::List),
+                    _ => None,
+                };
+                if let Some(command) = command {
+                    let _ = event_tx
+                        .send(Event::Command {
+                            from,
+                            command: ServerCommand::Booth(command),
+                        })
+                        .await;
+                } else {
+                    reply = Some(
+                        "Invalid booth command. please use /booth create <name> [password], \
+                         /booth join <name> [password], /booth invite <user>, /booth kick <user>, \
+                         /booth leave [name] or /booth list"
+                            .to_string(),
+                    );
+                }
+            }
+            "/tone" => {
+                let tone = msg.trim();
+                let _ = event_tx
+                    .send(Event::Command {
+                        from,
+                        command: ServerCommand::Tone((!tone.is_empty()).then(|| tone.to_owned())),
+                    })
+                    .await;
+            }
+            "/roll" => {
+                let command = match msg.trim() {
+                    "seal" => Ok(RollCommand::Seal),
+                    "reveal" => Ok(RollCommand::Reveal),
+                    dice => DiceExpression::parse(dice).map(RollCommand::Dice),
+                };
+                match command {
+                    Ok(command) => {
+                        let _ = event_tx
+                            .send(Event::Command {
+                                from,
+                                command: ServerCommand::Roll(command),
+                            })
+                            .await;
+                    }
+                    Err(e) => {
+                        reply = Some(format!(
+                            "{e} please use /roll <dice>, e.g. /roll 2d6+3, /roll 4d6kh3 or \
+                             /roll d20 adv, /roll seal or /roll reveal"
+                        ))
+                    }
+                }
+            }
+            "/game" => {
+                let mut words = msg.split_whitespace();
+                let command = match words.next() {
+                    None => Some(GameCommand::Status),
+                    Some("start") => GameKind::parse(&words.collect::<Vec<_>>().join(" "))
+                        .map(GameCommand::Start),
+                    Some("join") => Some(GameCommand::Join),
+                    Some("roll") => Some(GameCommand::Roll),
+                    Some("hold") => words
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()
+                        .ok()
+                        .map(GameCommand::Hold),
+                    Some("stand") => Some(GameCommand::Stand),
+                    Some(_) => None,
+                };
+                if let Some(command) = command {
+                    let _ = event_tx
+                        .send(Event::Command {
+                            from,
+                            command: ServerCommand::Game(command),
+                        })
+                        .await;
+                } else {
+                    reply = Some(
+                        "Invalid game command. please use /game start <dice-poker|arm-wrestling>, \
+                         /game join, /game roll, /game hold <dice>, /game stand or /game"
+                            .to_string(),
+                    );
+                }
+            }
+            "/trivia" => {
+                let command = match msg.trim() {
+                    "" => Some(TriviaCommand::Question),
+                    "top" => Some(TriviaCommand::Top),
+                    _ => None,
+                };
+                if let Some(command) = command {
+                    let _ = event_tx
+                        .send(Event::Command {
+                            from,
+                            command: ServerCommand::Trivia(command),
+                        })
+                        .await;
+                } else {
+                    reply = Some(
+                        "Invalid trivia command. please use /trivia or /trivia top".to_string(),
+                    );
+                }
+            }
+            // Emotes are defined by the server. Any other command may be one, see below.
+            "/emotes" => {
+                let _ = event_tx
+                    .send(Event::Command {
+                        from,
+                        command: ServerCommand::Emotes,
+                    })
+                    .await;
             }
 
             // System commands
@@ -140,8 +510,17 @@ pub async fn parse_incoming_message(
                 let _ = event_tx.send(Event::Shutdown).await;
             }
             _ => {
-                // Unknown command.
-                reply = Some("Unknown command.".to_string());
+                // Anything else may be an emote or a tone, which the server knows about.
+                let target = msg.trim();
+                let _ = event_tx
+                    .send(Event::Command {
+                        from,
+                        command: ServerCommand::Emote {
+                            name: command.trim_start_matches('/').to_owned(),
+                            target: (!target.is_empty()).then(|| target.to_owned()),
+                        },
+                    })
+                    .await;
             }
         }
     } else {
@@ -173,10 +552,10 @@ async fn say_something(
     new_tone: Option<MessageTone>,
 ) -> Option<String> {
     let tone = if let Some(tone) = new_tone {
-        client_ctx.tone = tone;
+        client_ctx.tone = tone.clone();
         tone
     } else {
-        client_ctx.tone
+        client_ctx.tone.clone()
     };
 
     // Send message if content is non-empty
@@ -200,6 +579,53 @@ async fn say_something(
     }
 }
 
+/// The line as it's logged, with the passwords given to `/admin`, `/register`, `/login` and
+/// `/booth` hidden.
+pub fn redact_passwords(message_raw: &str) -> String {
+    let words = message_raw.split_whitespace().collect::<Vec<_>>();
+    let command = words.first().map(|word| word.to_ascii_lowercase());
+    let shown = match command.as_deref() {
+        Some("/admin") => 1,
+        Some("/register" | "/login") => 2,
+        Some("/booth")
+            if words.get(1).is_some_and(|action| {
+                action.eq_ignore_ascii_case("create") || action.eq_ignore_ascii_case("join")
+            }) =>
+        {
+            3
+        }
+        _ => return message_raw.to_owned(),
+    };
+    match words.len() > shown {
+        true => format!("{} ***", words[..shown].join(" ")),
+        false => message_raw.to_owned(),
+    }
+}
+
+/// Does something where the client is talking, e.g. `/me leans on the bar and sighs`.
+async fn act(
+    from: UserId,
+    action: &str,
+    client_ctx: &mut ClientContext,
+    event_tx: &Sender<Event>,
+) -> Option<String> {
+    let mut message = Message::new(
+        Some(ChatTarget::User(from)),
+        client_ctx.current_target,
+        action,
+        Some(client_ctx.tone.clone()),
+    );
+    message.kind = MessageKind::Action;
+    let _ = event_tx.send(Event::BroadcastMessage { message }).await;
+
+    // Reply back if the action is not seen by everyone around.
+    match client_ctx.current_target {
+        ChatTarget::User(id) => Some(format!("To {}: * {}", id, action)),
+        ChatTarget::Npc(id) => Some(format!("To {}: * {}", id, action)),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -220,12 +646,10 @@ mod test {
             let actual_event = match timeout(Duration::from_secs(1), rx.recv()).await {
                 Ok(Some(event)) => event,
                 Ok(None) | Err(_) => {
-                    assert!(
-                        false,
+                    panic!(
                         "Failed to receive Event from the channel. \ninput: {:?}, expected_event: {:?}",
                         input, expected_event
                     );
-                    return;
                 }
             };
 
@@ -244,7 +668,117 @@ mod test {
                     to: ChatTarget::Global,
                     content: "hello world!".to_string(),
                     timestamp: SystemTime::now(),
-                    tone: MessageTone::Yelled,
+                    tone: MessageTone::YELLED,
+                    kind: MessageKind::Speech,
+                    mentions: vec![],
+                    id: None,
+                    edited: false,
+                    reactions: Default::default(),
+                    reply_to: None,
+                    heard_by: None,
+                    roll: None,
+                    emote: None,
+                },
+            },
+            &mut ctx,
+        )])
+        .await;
+    }
+
+    #[tokio::test]
+    async fn can_parse_booth_commands() {
+        let mut ctx = ClientContext::default();
+        let mut other_ctx = ClientContext::default();
+        assert_parse_event(vec![
+            (
+                "/booth create snug mead",
+                Event::Command {
+                    from: SENDER,
+                    command: ServerCommand::Booth(BoothCommand::Create {
+                        name: "snug".to_string(),
+                        password: Some(Password("mead".to_string())),
+                    }),
+                },
+                &mut ctx,
+            ),
+            (
+                "/booth invite 4",
+                Event::Command {
+                    from: SENDER,
+                    command: ServerCommand::Booth(BoothCommand::Invite("4".to_string())),
+                },
+                &mut other_ctx,
+            ),
+        ])
+        .await;
+    }
+
+    #[test]
+    fn passwords_are_hidden_from_logs() {
+        for (line, logged) in [
+            ("/login Alice hunter2", "/login Alice ***"),
+            ("/REGISTER Alice correct horse", "/REGISTER Alice ***"),
+            ("/admin hunter2", "/admin ***"),
+            ("/booth join snug mead", "/booth join snug ***"),
+            ("/booth join snug", "/booth join snug"),
+            ("/say my password is hunter2", "/say my password is hunter2"),
+        ] {
+            assert_eq!(redact_passwords(line), logged);
+        }
+        let command = ServerCommand::Login {
+            name: "Alice".to_string(),
+            password: Password("hunter2".to_string()),
+        };
+        assert!(!format!("{command:?}").contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn away_status_is_kept_in_the_context() {
+        let mut ctx = ClientContext::default();
+        assert_parse_event(vec![(
+            "/away fetching more ale",
+            Event::BroadcastMessage {
+                message: Message::new(
+                    None,
+                    ChatTarget::User(SENDER),
+                    "You are now away: fetching more ale. Use /back when you return.\nsaid >",
+                    None,
+                ),
+            },
+            &mut ctx,
+        )])
+        .await;
+        assert_eq!(ctx.away.as_deref(), Some("fetching more ale"));
+    }
+
+    #[tokio::test]
+    async fn message_ids_are_parsed_in_base_36() {
+        let mut ctx = ClientContext::default();
+        assert_eq!(MessageId(100).to_string(), "#2s");
+        assert_parse_event(vec![(
+            "/edit #2s Two ales, not three.",
+            Event::Command {
+                from: SENDER,
+                command: ServerCommand::Edit {
+                    id: MessageId(100),
+                    content: "Two ales, not three.".to_string(),
+                },
+            },
+            &mut ctx,
+        )])
+        .await;
+    }
+
+    #[tokio::test]
+    async fn reactions_are_parsed_with_their_message_id() {
+        let mut ctx = ClientContext::default();
+        assert_parse_event(vec![(
+            "/react #2s 🍺",
+            Event::Command {
+                from: SENDER,
+                command: ServerCommand::React {
+                    id: MessageId(100),
+                    reaction: "🍺".to_string(),
                 },
             },
             &mut ctx,
@@ -0,0 +1,170 @@
+//! Contains the rooms of the tavern. Each room has its own patrons and NPCs,
+//! and messages sent to a room only reach the patrons sitting in it.
+//! Private booths are rooms created by patrons, which can only be entered when invited,
+//! or with the booth's password.
+
+use std::collections::HashSet;
+
+use crate::common::{NpcId, UserId};
+
+/// The rooms of a tavern with the default configuration.
+pub const DEFAULT_ROOMS: [&str; 3] = ["The Common Room", "The Back Room", "The Fireplace Table"];
+
+#[derive(Debug)]
+pub struct Room {
+    name: String,
+    pub members: HashSet<UserId>,
+    /// NPCs sitting in this room can only be talked to from within it.
+    pub npcs: HashSet<NpcId>,
+}
+
+impl Room {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            members: Default::default(),
+            npcs: Default::default(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// A short name for the room, used to refer to it in commands.
+    pub fn key(&self) -> String {
+        room_key(&self.name)
+    }
+
+    /// The IRC channel the room is available as.
+    pub fn irc_channel(&self) -> String {
+        format!("#{}", self.key())
+    }
+
+    /// Returns true if `name` refers to this room, e.g. "the back room", "back-room" or "#back-room".
+    pub fn is_called(&self, name: &str) -> bool {
+        let key = room_key(name);
+        !key.is_empty() && key == self.key()
+    }
+}
+
+#[derive(Debug)]
+pub struct Booth {
+    name: String,
+    owner: UserId,
+    pub members: HashSet<UserId>,
+    /// Patrons allowed to join without the password.
+    pub invited: HashSet<UserId>,
+    password: Option<String>,
+}
+
+impl Booth {
+    pub fn new(name: &str, owner: UserId, password: Option<&str>) -> Self {
+        Self {
+            name: name.to_owned(),
+            owner,
+            members: HashSet::from([owner]),
+            invited: Default::default(),
+            password: password.map(str::to_owned),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn owner(&self) -> UserId {
+        self.owner
+    }
+
+    pub fn key(&self) -> String {
+        room_key(&self.name)
+    }
+
+    /// Booths are available as local IRC channels, which start with `&`.
+    pub fn irc_channel(&self) -> String {
+        format!("&{}", self.key())
+    }
+
+    pub fn is_called(&self, name: &str) -> bool {
+        let key = room_key(name.trim_start_matches('&'));
+        !key.is_empty() && key == self.key()
+    }
+
+    /// Returns true if a patron may join the booth, either invited or with the right password.
+    pub fn can_enter(&self, id: UserId, password: Option<&str>) -> bool {
+        self.members.contains(&id)
+            || self.invited.contains(&id)
+            || (self.password.is_some() && self.password.as_deref() == password)
+    }
+
+    /// Removes a member. If they owned the booth, the member who came to the tavern first
+    /// becomes the owner.
+    /// Returns the new owner, if ownership changed.
+    pub fn remove_member(&mut self, id: UserId) -> Option<UserId> {
+        self.members.remove(&id);
+        self.invited.remove(&id);
+        if self.owner != id {
+            return None;
+        }
+        let owner = self.members.iter().min().copied()?;
+        self.owner = owner;
+        Some(owner)
+    }
+}
+
+/// Normalizes a room name: lowercase words joined by dashes, without a leading "the".
+pub fn room_key(name: &str) -> String {
+    let words = name
+        .trim_start_matches('#')
+        .split(|c: char| c.is_whitespace() || c == '-' || c == '_')
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    let words = match words.split_first() {
+        Some((first, rest)) if first == "the" && !rest.is_empty() => rest,
+        _ => &words[..],
+    };
+    words.join("-")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rooms_can_be_named_loosely() {
+        let room = Room::new("The Fireplace Table");
+        assert_eq!(room.key(), "fireplace-table");
+        assert_eq!(room.irc_channel(), "#fireplace-table");
+        for name in [
+            "the fireplace table",
+            "Fireplace  Table",
+            "fireplace_table",
+            "#fireplace-table",
+        ] {
+            assert!(room.is_called(name), "{name}");
+        }
+        assert!(!room.is_called("fireplace"));
+        assert!(!room.is_called(""));
+    }
+
+    #[test]
+    fn booths_are_private_and_change_owner() {
+        let (alice, bob, carol) = (UserId(1), UserId(2), UserId(3));
+        let mut booth = Booth::new("Snug", alice, Some("mead"));
+        assert!(booth.is_called("&snug"));
+        assert!(!booth.can_enter(bob, None));
+        assert!(!booth.can_enter(bob, Some("ale")));
+        assert!(booth.can_enter(bob, Some("mead")));
+        booth.invited.insert(carol);
+        assert!(booth.can_enter(carol, None));
+
+        booth.members.extend([carol, bob]);
+        assert_eq!(booth.remove_member(carol), None);
+        assert_eq!(booth.remove_member(alice), Some(bob));
+        assert_eq!(booth.owner(), bob);
+        assert_eq!(booth.remove_member(bob), None);
+        assert!(booth.members.is_empty());
+    }
+}
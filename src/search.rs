@@ -0,0 +1,238 @@
+//! Contains the search over chat history: an inverted index from words to the messages using
+//! them, kept up to date as messages are logged, and the queries typed with `/search`.
+
+use chrono::{Local, NaiveDate, TimeZone};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    time::SystemTime,
+};
+
+use crate::common::{Message, MessageId};
+
+/// Most results shown for a single search. The most recent ones are kept.
+pub const SEARCH_RESULTS_LEN: usize = 20;
+
+/// What to look for, e.g. `dragon gold from:Barkeep since:2025-06-01`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// Words every result contains, or starts a word with. Lowercase.
+    pub terms: Vec<String>,
+    /// Name or ID of the speaker.
+    pub from: Option<String>,
+    pub since: Option<NaiveDate>,
+}
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        for word in query.split_whitespace() {
+            match word.split_once(':') {
+                Some((key, value)) if key.eq_ignore_ascii_case("from") && !value.is_empty() => {
+                    parsed.from = Some(value.to_owned());
+                }
+                Some((key, value)) if key.eq_ignore_ascii_case("since") => {
+                    let since = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                        .map_err(|_| format!("Invalid date {value:?}."))?;
+                    parsed.since = Some(since);
+                }
+                _ => parsed.terms.extend(words(word)),
+            }
+        }
+        if parsed.terms.is_empty() && parsed.from.is_none() && parsed.since.is_none() {
+            return Err("Nothing to search for.".to_owned());
+        }
+        Ok(parsed)
+    }
+
+    /// The time the query starts from, at midnight local time.
+    pub fn since_time(&self) -> Option<SystemTime> {
+        let midnight = self.since?.and_hms_opt(0, 0, 0)?;
+        Local
+            .from_local_datetime(&midnight)
+            .earliest()
+            .map(SystemTime::from)
+    }
+}
+
+/// Indexes every message spoken in the tavern by the words it contains.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    /// Deleted messages leave a gap, so positions stay valid.
+    messages: Vec<Option<Message>>,
+    positions: HashMap<MessageId, usize>,
+    /// Maps each word to the positions of the messages containing it, in order.
+    words: BTreeMap<String, BTreeSet<usize>>,
+}
+
+impl SearchIndex {
+    pub fn insert(&mut self, message: Message) {
+        let position = self.messages.len();
+        self.index_words(&message.content, position);
+        if let Some(id) = message.id {
+            self.positions.insert(id, position);
+        }
+        self.messages.push(Some(message));
+    }
+
+    pub fn get(&self, id: MessageId) -> Option<&Message> {
+        self.messages.get(*self.positions.get(&id)?)?.as_ref()
+    }
+
+    /// Gives access to a message, to change anything but its content.
+    pub fn get_mut(&mut self, id: MessageId) -> Option<&mut Message> {
+        self.messages.get_mut(*self.positions.get(&id)?)?.as_mut()
+    }
+
+    /// Changes the content of a message. Returns the edited message.
+    pub fn edit(&mut self, id: MessageId, content: &str) -> Option<&Message> {
+        let position = *self.positions.get(&id)?;
+        let old_content = self.messages.get(position)?.as_ref()?.content.clone();
+        self.unindex_words(&old_content, position);
+        self.index_words(content, position);
+        let message = self.messages[position].as_mut()?;
+        message.content = content.to_owned();
+        message.edited = true;
+        Some(message)
+    }
+
+    /// Removes a message. Returns it, if it was there.
+    pub fn remove(&mut self, id: MessageId) -> Option<Message> {
+        let position = self.positions.remove(&id)?;
+        let message = self.messages.get_mut(position)?.take()?;
+        self.unindex_words(&message.content, position);
+        Some(message)
+    }
+
+    /// Every indexed message, oldest first.
+    pub fn messages(&self) -> impl Iterator<Item = &Message> {
+        self.messages.iter().flatten()
+    }
+
+    /// Gives access to every message, to change anything but their content.
+    pub fn messages_mut(&mut self) -> impl Iterator<Item = &mut Message> {
+        self.messages.iter_mut().flatten()
+    }
+
+    fn index_words(&mut self, content: &str, position: usize) {
+        for word in words(content) {
+            self.words.entry(word).or_default().insert(position);
+        }
+    }
+
+    fn unindex_words(&mut self, content: &str, position: usize) {
+        for word in words(content) {
+            if let Some(positions) = self.words.get_mut(&word) {
+                positions.remove(&position);
+                if positions.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// Finds the messages containing every term of the query, oldest first.
+    /// Only messages for which `matches` returns true are kept.
+    pub fn search(&self, terms: &[String], matches: impl Fn(&Message) -> bool) -> Vec<&Message> {
+        let mut positions: Option<BTreeSet<usize>> = None;
+        for term in terms {
+            // Terms match any word they start, so "drag" finds "dragons".
+            let found = self
+                .words
+                .range(term.clone()..)
+                .take_while(|(word, _)| word.starts_with(term.as_str()))
+                .flat_map(|(_, positions)| positions.iter().copied())
+                .collect::<BTreeSet<_>>();
+            positions = Some(match positions {
+                Some(positions) => positions.intersection(&found).copied().collect(),
+                None => found,
+            });
+        }
+
+        match positions {
+            Some(positions) => positions
+                .into_iter()
+                .filter_map(|position| self.messages[position].as_ref())
+                .filter(|message| matches(message))
+                .collect(),
+            None => self.messages().filter(|message| matches(message)).collect(),
+        }
+    }
+}
+
+/// Splits text into lowercase words, ignoring punctuation.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::ChatTarget;
+
+    #[test]
+    fn queries_are_parsed_into_terms_and_filters() {
+        assert_eq!(
+            SearchQuery::parse("The Dragon's gold from:Barkeep since:2025-06-01"),
+            Ok(SearchQuery {
+                terms: vec!["the", "dragon", "s", "gold"]
+                    .into_iter()
+                    .map(str::to_owned)
+                    .collect(),
+                from: Some("Barkeep".to_owned()),
+                since: NaiveDate::from_ymd_opt(2025, 6, 1),
+            })
+        );
+        assert!(SearchQuery::parse("since:yesterday").is_err());
+        assert!(SearchQuery::parse("  ").is_err());
+    }
+
+    #[test]
+    fn every_term_must_match_the_start_of_a_word() {
+        let mut index = SearchIndex::default();
+        for content in [
+            "The dragon sleeps on its gold.",
+            "Dragons don't exist!",
+            "Gold for ale, please.",
+        ] {
+            index.insert(Message::new(None, ChatTarget::Global, content, None));
+        }
+        let search = |query: &str| {
+            let query = SearchQuery::parse(query).unwrap();
+            index
+                .search(&query.terms, |_| true)
+                .into_iter()
+                .map(|message| message.content.as_str())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            search("drag"),
+            vec!["The dragon sleeps on its gold.", "Dragons don't exist!"]
+        );
+        assert_eq!(
+            search("GOLD dragon"),
+            vec!["The dragon sleeps on its gold."]
+        );
+        assert!(search("wyvern").is_empty());
+    }
+
+    #[test]
+    fn edited_and_removed_messages_are_reindexed() {
+        let mut index = SearchIndex::default();
+        let mut message = Message::new(None, ChatTarget::Global, "A dragon!", None);
+        message.id = Some(MessageId(7));
+        index.insert(message);
+
+        let edited = index.edit(MessageId(7), "Only a lizard.").unwrap();
+        assert!(edited.edited);
+        assert!(index.search(&["dragon".to_owned()], |_| true).is_empty());
+        assert_eq!(index.search(&["lizard".to_owned()], |_| true).len(), 1);
+
+        assert!(index.remove(MessageId(7)).is_some());
+        assert!(index.get(MessageId(7)).is_none());
+        assert!(index.search(&["lizard".to_owned()], |_| true).is_empty());
+        assert_eq!(index.messages().count(), 0);
+    }
+}
@@ -1,134 +1,366 @@
 //! Contains the Server struct for the tavern.
 //! Stores all essential information in this centralized, global instance.
 
+use anyhow::Context;
+use chrono::{DateTime, Local};
 use futures::future::join_all;
-use std::collections::{HashMap, VecDeque};
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::{
-        TcpListener,
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-    },
+    net::{TcpListener, TcpStream, tcp::OwnedReadHalf},
     sync::{mpsc, watch},
     task::JoinHandle,
 };
 
+use crate::accounts::{Accounts, MAX_FAILED_LOGINS, Mail, PasswordHash};
 use crate::common::*;
+use crate::config::{ServerConfig, TavernServerBuilder};
+use crate::dice::{Dice, DiceExpression};
+use crate::emotes::Emotes;
+use crate::games::Game;
+use crate::http::{self, ApiRequest, MessageSender, PostMessage, manage_http_connections};
+use crate::irc::{self, IRC_CHANNEL, IRC_SERVER_NAME, IrcCommand};
+use crate::journal::{Journal, JournalEntry, read_journal};
 use crate::npcs::Npc;
+use crate::rooms::{Booth, Room, room_key};
+use crate::search::{SEARCH_RESULTS_LEN, SearchIndex, SearchQuery};
+use crate::tones::{Reach, Tones};
+use crate::transcript::{self, TranscriptQuery};
+use crate::trivia::{LEADERBOARD_LEN, Leaderboard, QUIZMASTER_NAME, QuestionBank, Trivia};
 
 pub const MESSAGE_HISTORY_LEN: usize = 100usize;
 pub const TCP_PORT: &str = "127.0.0.1:8080";
+pub const JSON_PORT: &str = "127.0.0.1:8081";
+pub const HISTORY_REPLAY_LEN: usize = 20;
+pub const IDLE_AFTER: Duration = Duration::from_secs(10 * 60);
+/// Longer reactions are cut short.
+const MAX_REACTION_LEN: usize = 16;
+/// Answers game commands where nobody plays.
+const NO_GAME: &str = "Nobody is playing here. /game start <dice-poker|arm-wrestling> to play.";
 
 #[derive(Debug)]
 pub struct TavernServer {
+    config: ServerConfig,
     message_log: VecDeque<Message>,
+    /// Opened when the server starts running, if configured.
+    journal: Option<Journal>,
+    /// Indexes every message spoken since the journal began, or since the server started.
+    search_index: SearchIndex,
+    accounts: Accounts,
+    emotes: Emotes,
+    tones: Tones,
+    dice: Dice,
+    /// The game played in each room, booth, or in the whole tavern.
+    games: HashMap<ChatTarget, Game>,
+    /// Set if the quizmaster runs trivia.
+    trivia: Option<Trivia>,
+    /// The account each patron logged into, kept after they leave so direct messages
+    /// to them can be left in their mailbox.
+    account_ids: HashMap<UserId, String>,
+    /// Ids of restored messages are skipped, so ids stay unique across restarts.
+    next_message_id: u64,
+    /// The patrons each message was sent or replayed to, so its edits, deletion and reactions
+    /// reach them alone.
+    audiences: HashMap<MessageId, HashSet<UserId>>,
     npcs: HashMap<NpcId, Npc>,
     clients: HashMap<UserId, Client>,
+    rooms: BTreeMap<RoomId, Room>,
+    booths: BTreeMap<BoothId, Booth>,
+    /// Maps each API token to the bot NPC it speaks as.
+    api_tokens: HashMap<String, NpcId>,
     next_entity_id: u32,
+    next_room_id: u32,
+    next_booth_id: u32,
     event_tx: mpsc::Sender<Event>,
     event_rx: mpsc::Receiver<Event>,
 }
 
 impl TavernServer {
+    /// Creates a server with the default configuration.
     pub fn new() -> (Self, mpsc::Sender<Event>) {
+        Self::builder().build()
+    }
+
+    pub fn builder() -> TavernServerBuilder {
+        Default::default()
+    }
+
+    pub fn with_config(config: ServerConfig) -> (Self, mpsc::Sender<Event>) {
         let (event_tx, event_rx) = mpsc::channel::<Event>(100);
-        (
-            TavernServer {
-                message_log: Default::default(),
-                npcs: Default::default(),
-                clients: Default::default(),
-                next_entity_id: Default::default(),
-                event_tx: event_tx.clone(),
-                event_rx,
-            },
-            event_tx,
-        )
+        let mut server = TavernServer {
+            config,
+            message_log: Default::default(),
+            journal: None,
+            search_index: Default::default(),
+            accounts: Default::default(),
+            emotes: Default::default(),
+            tones: Default::default(),
+            dice: Default::default(),
+            games: Default::default(),
+            trivia: None,
+            account_ids: Default::default(),
+            next_message_id: Default::default(),
+            audiences: Default::default(),
+            npcs: Default::default(),
+            clients: Default::default(),
+            rooms: Default::default(),
+            booths: Default::default(),
+            api_tokens: Default::default(),
+            next_entity_id: Default::default(),
+            next_room_id: Default::default(),
+            next_booth_id: Default::default(),
+            event_tx: event_tx.clone(),
+            event_rx,
+        };
+
+        for name in server.config.rooms.clone() {
+            server.add_room(&name);
+        }
+        for npc in server.config.npcs.clone() {
+            let id = server.add_npc(&npc.name);
+            if let Some(room) = npc.room {
+                let room = server
+                    .find_room(&room)
+                    .unwrap_or_else(|| server.add_room(&room));
+                server.seat_npc(id, room);
+            }
+        }
+        // The quizmaster sits in the room it runs trivia in.
+        if let Some(room) = server.config.trivia_room.clone() {
+            let npc = server.add_npc(QUIZMASTER_NAME);
+            let room = server
+                .find_room(&room)
+                .unwrap_or_else(|| server.add_room(&room));
+            server.seat_npc(npc, room);
+            server.trivia = Some(Trivia::new(npc, room));
+        }
+        // Register a bot NPC for every API token.
+        for (token, name) in server.config.api_tokens.0.clone() {
+            let id = server.add_npc(&name);
+            server.api_tokens.insert(token, id);
+        }
+
+        (server, event_tx)
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// Adds an NPC to the tavern, assigning it a new ID.
+    pub fn add_npc(&mut self, name: &str) -> NpcId {
+        let id = NpcId(self.next_entity_id);
+        self.next_entity_id += 1;
+        self.npcs.insert(id, Npc::new(name));
+        id
+    }
+
+    /// Adds a room to the tavern, assigning it a new ID.
+    pub fn add_room(&mut self, name: &str) -> RoomId {
+        let id = RoomId(self.next_room_id);
+        self.next_room_id += 1;
+        self.rooms.insert(id, Room::new(name));
+        id
+    }
+
+    /// Moves an NPC into a room, out of any room it was sitting in.
+    pub fn seat_npc(&mut self, npc: NpcId, room: RoomId) {
+        for room in self.rooms.values_mut() {
+            room.npcs.remove(&npc);
+        }
+        if let Some(room) = self.rooms.get_mut(&room) {
+            room.npcs.insert(npc);
+        }
     }
 
     /// Runs the main loop
     pub async fn run(&mut self) -> anyhow::Result<()> {
         println!("☀️ Starting Tavern Chat server! Welcome!");
 
+        self.open_journal()?;
+        self.accounts = Accounts::load(self.config.accounts_file.as_deref())
+            .context("Failed to load accounts")?;
+        self.emotes =
+            Emotes::load(self.config.emotes_file.as_deref()).context("Failed to load emotes")?;
+        self.tones =
+            Tones::load(self.config.tones_file.as_deref()).context("Failed to load tones")?;
+        if let Some(trivia) = &mut self.trivia {
+            trivia.bank = QuestionBank::load(self.config.trivia_file.as_deref())
+                .context("Failed to load trivia questions")?;
+            trivia.leaderboard = Leaderboard::load(self.config.trivia_scores_file.as_deref())
+                .context("Failed to load the trivia leaderboard")?;
+            self.schedule_trivia();
+        }
+
         // Create event channel
         let (shutdown_tx, shutdown_rx) = watch::channel(());
 
-        // Initiate TCP connection loop
-        let mut client_handles =
-            vec![manage_tcp_connections(self.event_tx.clone(), shutdown_rx.clone()).await?];
+        // Initiate TCP connection loops for plain text, JSON and IRC clients
+        let mut client_handles = vec![];
+        for (address, protocol) in [
+            (&self.config.plain_address, ClientProtocol::Plain),
+            (&self.config.json_address, ClientProtocol::Json),
+            (
+                &self.config.irc_address,
+                ClientProtocol::Irc(Default::default()),
+            ),
+        ] {
+            if let Some(address) = address {
+                client_handles.push(
+                    manage_tcp_connections(
+                        address.clone(),
+                        protocol,
+                        self.event_tx.clone(),
+                        shutdown_rx.clone(),
+                    )
+                    .await?,
+                );
+            }
+        }
+        match &self.config.http_address {
+            Some(_) if self.api_tokens.is_empty() => {
+                println!("☎️ HTTP API disabled: no API tokens configured");
+            }
+            Some(address) => {
+                client_handles.push(
+                    manage_http_connections(
+                        address.clone(),
+                        self.api_tokens.clone(),
+                        self.event_tx.clone(),
+                        shutdown_rx.clone(),
+                    )
+                    .await?,
+                );
+            }
+            None => {}
+        }
 
         while let Some(event) = self.event_rx.recv().await {
-            println!("New event: {:?}", event);
+            match &event {
+                // Lines are logged by the parser, without passwords. IRC lines aren't logged, as
+                // they can carry channel keys.
+                Event::ReceiveUserMessage { from, .. } => {
+                    println!("New event: ReceiveUserMessage from {from:?}")
+                }
+                event => println!("New event: {event:?}"),
+            }
             match event {
-                Event::NewClient { connection, .. } => {
-                    // Assign a new ID to a new client.
-                    let id = UserId(self.next_entity_id);
-                    self.next_entity_id += 1;
-
+                Event::NewClient {
+                    connection,
+                    protocol,
+                    ..
+                } => {
                     let (read_half, write_half) = connection.into_split();
-                    self.clients.insert(
-                        id,
-                        Client {
-                            send_tx: write_half,
-                            context: Default::default(),
-                        },
-                    );
+                    let id = self.add_client(ClientWriter::Tcp(write_half), protocol);
                     client_handles.push(watch_client(
                         id,
                         read_half,
                         self.event_tx.clone(),
                         shutdown_rx.clone(),
                     ));
-                    let _ = self
-                        .event_tx
-                        .send(Event::NotifyClient {
-                            notification: SystemNotification {
-                                to: id,
-                                content: "Welcome to Tavern chat!".to_string(),
-                            },
-                        })
-                        .await;
+                    self.welcome_client(id).await;
                 }
-                Event::DisconnectClient { id } => self.remove_clients(id),
+                Event::NewLocalClient { output_tx } => {
+                    let id =
+                        self.add_client(ClientWriter::Channel(output_tx), ClientProtocol::Json);
+                    self.welcome_client(id).await;
+                }
+                Event::DisconnectClient { id } => self.remove_clients(id).await,
                 Event::ReceiveUserMessage { from, message_raw } => {
                     if let Some(client) = self.clients.get_mut(&from) {
-                        let _ = crate::parser::parse_incoming_message(
-                            from,
-                            message_raw,
-                            self.event_tx.clone(),
-                            &mut client.context,
-                        )
-                        .await;
+                        client.last_active = Instant::now();
+                    }
+                    match self.clients.get_mut(&from) {
+                        Some(Client {
+                            protocol: ClientProtocol::Irc(_),
+                            ..
+                        }) => self.handle_irc_message(from, message_raw).await,
+                        // Event streams are read only.
+                        Some(Client {
+                            protocol: ClientProtocol::EventStream { .. },
+                            ..
+                        }) => {}
+                        Some(client) => {
+                            let _ = crate::parser::parse_incoming_message(
+                                from,
+                                message_raw,
+                                self.event_tx.clone(),
+                                &mut client.context,
+                            )
+                            .await;
+                        }
+                        None => {}
                     }
                 }
                 Event::BroadcastMessage { message } => self.broadcast_message(message).await,
                 Event::ChangeTarget { id, to } => {
-                    if match to {
-                        ChatTarget::Global => true,
-                        ChatTarget::User(to) => self.clients.contains_key(&to),
-                        ChatTarget::Npc(to) => self.npcs.contains_key(&to),
-                    } && let Some(client) = self.clients.get_mut(&id)
+                    if self.can_target(id, to)
+                        && let Some(client) = self.clients.get_mut(&id)
                     {
                         client.context.current_target = to;
                     }
                 }
-                Event::NotifyClient { notification } => {
-                    if let Some(client) = self.clients.get_mut(&notification.to) {
-                        if to_client(
-                            &mut client.send_tx,
-                            notification.to,
-                            notification.to_output(),
-                        )
-                        .await
-                        .is_err()
-                        {
-                            // Disconnect client if message can't be sent
-                            let _ = self
-                                .event_tx
-                                .send(Event::DisconnectClient {
-                                    id: notification.to,
-                                })
-                                .await;
-                        }
+                Event::NotifyClient { notification } => self.notify_client(notification).await,
+                Event::ApiRequest {
+                    request,
+                    connection,
+                    bot,
+                } => {
+                    if let Some(connection) =
+                        self.handle_api_request(request, connection, bot).await
+                    {
+                        // The request opened an event stream, which is watched like any client.
+                        let (read_half, write_half) = connection.into_split();
+                        let id = self.add_client(
+                            ClientWriter::Tcp(write_half),
+                            ClientProtocol::EventStream { bot },
+                        );
+                        client_handles.push(watch_client(
+                            id,
+                            read_half,
+                            self.event_tx.clone(),
+                            shutdown_rx.clone(),
+                        ));
+                    }
+                }
+                Event::Command { from, command } => self.handle_command(from, command).await,
+                Event::PasswordHashed {
+                    from,
+                    name,
+                    password,
+                } => {
+                    let reply = self.finish_register(from, &name, password);
+                    self.notify_client(SystemNotification {
+                        to: from,
+                        content: reply,
+                    })
+                    .await;
+                }
+                Event::LoginChecked {
+                    from,
+                    name,
+                    valid,
+                    rehashed,
+                } => {
+                    let reply = self.finish_login(from, &name, valid, rehashed);
+                    self.notify_client(SystemNotification {
+                        to: from,
+                        content: reply,
+                    })
+                    .await;
+                }
+                Event::TriviaTimeout { round } => self.run_trivia(round).await,
+                Event::GameTimeout { place, turn } => {
+                    if self
+                        .games
+                        .get(&place)
+                        .is_some_and(|game| game.turn() == turn)
+                    {
+                        self.play_game(place, |game, _| Ok(game.timeout())).await;
                     }
                 }
                 Event::Shutdown => {
@@ -150,7 +382,7 @@ impl TavernServer {
         }
 
         // Wait for all threads to shutdown
-        let _ = join_all(client_handles);
+        join_all(client_handles).await;
 
         println!("🌙 Tavern Chat server shutdown! So long!");
         Ok(())
@@ -163,29 +395,368 @@ impl TavernServer {
         self.clients.clear();
     }
 
+    /// Registers a newly connected client, assigning it a new ID.
+    fn add_client(&mut self, send_tx: ClientWriter, protocol: ClientProtocol) -> UserId {
+        let id = UserId(self.next_entity_id);
+        self.next_entity_id += 1;
+
+        self.clients.insert(
+            id,
+            Client {
+                send_tx,
+                context: Default::default(),
+                protocol,
+                connected_at: SystemTime::now(),
+                last_active: Instant::now(),
+                is_admin: false,
+                account: None,
+                checking_password: false,
+                failed_logins: 0,
+            },
+        );
+        id
+    }
+
+    /// Greets a new client and lets everyone else know they arrived.
+    async fn welcome_client(&mut self, id: UserId) {
+        // IRC clients are welcomed once they complete registration.
+        let is_irc = matches!(
+            self.clients.get(&id).map(|client| &client.protocol),
+            Some(ClientProtocol::Irc(_))
+        );
+        if !is_irc {
+            // Sent right away, so the welcome is always the first thing a client receives.
+            self.notify_client(SystemNotification {
+                to: id,
+                content: "Welcome to Tavern chat!".to_string(),
+            })
+            .await;
+            let prefix = irc::user_prefix(&self.display_name(ChatTarget::User(id)));
+            self.irc_announce(None, irc::format_line(&prefix, "JOIN", &[IRC_CHANNEL]))
+                .await;
+            self.announce_presence(id, "walks into the tavern").await;
+            self.replay_history(id, self.config.replay_len, true).await;
+        }
+        self.stream_announce("join", id).await;
+    }
+
+    /// Tells every other patron that someone came or went. IRC clients see JOIN and QUIT
+    /// lines instead, and bots get join and leave events.
+    async fn announce_presence(&mut self, id: UserId, action: &str) {
+        let content = format!("{} {action}.", self.display_name(ChatTarget::User(id)));
+        let patrons = self
+            .clients
+            .iter()
+            .filter(|(patron, client)| {
+                **patron != id
+                    && matches!(
+                        client.protocol,
+                        ClientProtocol::Plain | ClientProtocol::Json
+                    )
+            })
+            .map(|(patron, _)| *patron)
+            .collect::<Vec<_>>();
+        for patron in patrons {
+            self.notify_client(SystemNotification {
+                to: patron,
+                content: content.clone(),
+            })
+            .await;
+        }
+    }
+
     /// Close a Client's Tcp connection.
-    pub fn remove_clients(&mut self, id: UserId) {
+    pub async fn remove_clients(&mut self, id: UserId) {
+        let prefix = irc::user_prefix(&self.display_name(ChatTarget::User(id)));
+        let is_patron = self
+            .clients
+            .get(&id)
+            .is_some_and(|client| !matches!(client.protocol, ClientProtocol::EventStream { .. }));
+        if is_patron {
+            self.stream_announce("leave", id).await;
+        }
+        // IRC clients only walked in once registered.
+        let walked_in = self
+            .clients
+            .get(&id)
+            .is_some_and(|client| match &client.protocol {
+                ClientProtocol::Irc(session) => session.registered,
+                ClientProtocol::EventStream { .. } => false,
+                _ => true,
+            });
+        if walked_in {
+            self.announce_presence(id, "leaves the tavern").await;
+        }
+        self.leave_room(id).await;
+        let booths = self
+            .booths
+            .iter()
+            .filter(|(_, booth)| booth.members.contains(&id) || booth.invited.contains(&id))
+            .map(|(booth, _)| *booth)
+            .collect::<Vec<_>>();
+        for booth in booths {
+            self.leave_booth(id, booth).await;
+        }
+
         // Dropping the write half closes the connection.
-        self.clients.remove(&id);
+        if let Some(client) = self.clients.remove(&id)
+            && in_irc_channel(&client)
+        {
+            self.irc_announce(
+                None,
+                irc::format_line(&prefix, "QUIT", &["Left the tavern"]),
+            )
+            .await;
+        }
     }
 
-    /// Broadcast a new message to listeners of the server.
-    pub async fn broadcast_message(&mut self, message: Message) {
-        // Insert the new message into the log.
-        self.message_log.push_back(message.clone());
-        if self.message_log.len() > MESSAGE_HISTORY_LEN {
+    /// Sends a system notification to a client, in the protocol it speaks.
+    async fn notify_client(&mut self, notification: SystemNotification) {
+        let Some(client) = self.clients.get(&notification.to) else {
+            return;
+        };
+        let output = match &client.protocol {
+            ClientProtocol::Plain => Some(notification.to_output()),
+            ClientProtocol::Json => {
+                Some(ServerOutput::Notification(notification.clone()).to_json_line())
+            }
+            ClientProtocol::Irc(_) => Some(irc::format_line(
+                IRC_SERVER_NAME,
+                "NOTICE",
+                &[
+                    &self.display_name(ChatTarget::User(notification.to)),
+                    &notification.content,
+                ],
+            )),
+            ClientProtocol::EventStream { .. } => None,
+        };
+        if let Some(output) = output {
+            self.send_to_client(notification.to, output).await;
+        }
+    }
+
+    /// Writes raw output to a client, disconnecting it if the write fails.
+    async fn send_to_client(&mut self, id: UserId, output: String) {
+        if let Some(client) = self.clients.get_mut(&id)
+            && to_client(&mut client.send_tx, id, output).await.is_err()
+        {
+            // Disconnect client if message can't be sent
+            let _ = self.event_tx.send(Event::DisconnectClient { id }).await;
+        }
+    }
+
+    /// Opens the journal, if configured, and fills the history with the messages it holds.
+    fn open_journal(&mut self) -> anyhow::Result<()> {
+        let Some(dir) = self.config.journal_dir.clone() else {
+            return Ok(());
+        };
+        let journal = Journal::open(&dir, self.config.journal_max_len)
+            .with_context(|| format!("Failed to open the journal in {dir:?}"))?;
+        let restored = self.restore_history()?;
+        println!("📜 Journaling to {dir:?}, {restored} messages restored");
+        self.journal = Some(journal);
+        Ok(())
+    }
+
+    /// Fills the history with the messages of the configured journal, without writing to it.
+    /// Returns the number of messages restored.
+    pub fn restore_history(&mut self) -> anyhow::Result<usize> {
+        let Some(dir) = self.config.journal_dir.clone() else {
+            return Ok(0);
+        };
+        let entries =
+            read_journal(&dir).with_context(|| format!("Failed to read the journal in {dir:?}"))?;
+        let mut restored = 0;
+        for entry in entries {
+            match entry {
+                JournalEntry::Message(message) => {
+                    if let Some(id) = message.id {
+                        self.next_message_id = self.next_message_id.max(id.0 + 1);
+                    }
+                    self.skip_restored_ids(&message);
+                    self.log_message(*message);
+                    restored += 1;
+                }
+                JournalEntry::Edit { id, content, .. } => {
+                    self.apply_edit(id, &content);
+                }
+                JournalEntry::Delete { id, .. } => {
+                    self.apply_delete(id);
+                }
+                JournalEntry::React {
+                    id, by, reaction, ..
+                } => {
+                    self.next_entity_id = self.next_entity_id.max(by.0 + 1);
+                    self.apply_reaction(id, by, &reaction);
+                }
+                JournalEntry::Moderation(_) => {}
+            }
+        }
+        Ok(restored)
+    }
+
+    /// Skips the ids of the patrons and booths a restored message was addressed with, so new
+    /// ones can't read or change messages that were never meant for them.
+    fn skip_restored_ids(&mut self, message: &Message) {
+        for target in message.from.iter().chain([&message.to]) {
+            match *target {
+                ChatTarget::User(UserId(id)) | ChatTarget::Npc(NpcId(id)) => {
+                    self.next_entity_id = self.next_entity_id.max(id + 1)
+                }
+                ChatTarget::Booth(BoothId(id)) => {
+                    self.next_booth_id = self.next_booth_id.max(id + 1)
+                }
+                ChatTarget::Global | ChatTarget::Room(_) => {}
+            }
+        }
+        let users = (message.heard_by.iter().flatten())
+            .chain(&message.mentions)
+            .chain(message.reactions.values().flatten());
+        for UserId(id) in users {
+            self.next_entity_id = self.next_entity_id.max(id + 1);
+        }
+    }
+
+    /// Renders the messages matching a query as a transcript.
+    pub fn transcript(&self, query: &TranscriptQuery) -> Result<String, String> {
+        Ok(transcript::render(
+            &self.transcript_messages(query)?,
+            query.format,
+        ))
+    }
+
+    fn transcript_messages(&self, query: &TranscriptQuery) -> Result<Vec<&Message>, String> {
+        let room = match &query.room {
+            Some(name) => Some(
+                self.find_room(name)
+                    .ok_or_else(|| format!("There is no room called {name:?}."))?,
+            ),
+            None => None,
+        };
+        Ok(self
+            .search_index
+            .messages()
+            .filter(|message| room.is_none_or(|room| message.to == ChatTarget::Room(room)))
+            .filter(|message| query.contains(message.timestamp))
+            .collect())
+    }
+
+    /// Writes a transcript to the export directory. Only admins may do so.
+    fn export_transcript(&self, id: UserId, query: &TranscriptQuery) -> String {
+        if !self.clients.get(&id).is_some_and(|client| client.is_admin) {
+            return "Only admins can export transcripts. Use /admin <password> first.".to_owned();
+        }
+        let messages = match self.transcript_messages(query) {
+            Ok(messages) => messages,
+            Err(e) => return e,
+        };
+        let path = self.config.export_dir.join(format!(
+            "transcript-{}.{}",
+            Local::now().format("%Y%m%d-%H%M%S"),
+            query.format.extension()
+        ));
+        let written = std::fs::create_dir_all(&self.config.export_dir)
+            .and_then(|_| std::fs::write(&path, transcript::render(&messages, query.format)));
+        match written {
+            Ok(()) => format!(
+                "Exported {} message{} to {}.",
+                messages.len(),
+                if messages.len() == 1 { "" } else { "s" },
+                path.display()
+            ),
+            Err(e) => format!("Failed to export the transcript: {e}"),
+        }
+    }
+
+    /// Writes an entry to the journal, if there is one. Failing to do so doesn't stop the tavern.
+    fn journal(&mut self, entry: JournalEntry) {
+        if let Some(journal) = &mut self.journal
+            && let Err(e) = journal.append(&entry)
+        {
+            println!("📜 Failed to write to the journal: {e}");
+        }
+    }
+
+    /// Removes the content of a deleted message from the journal.
+    fn scrub_journal(&mut self, id: MessageId) {
+        if let Some(journal) = &mut self.journal
+            && let Err(e) = journal.scrub(id)
+        {
+            println!("📜 Failed to scrub {id} from the journal: {e}");
+        }
+    }
+
+    /// Keeps a record of a moderation action in the journal.
+    fn log_moderation(&mut self, action: ModerationAction) {
+        println!("Moderation: {action:?}");
+        self.journal(JournalEntry::Moderation(action));
+    }
+
+    /// Inserts a message into the log, dropping the oldest one once it's full.
+    /// Messages spoken by someone are also indexed for searching.
+    fn log_message(&mut self, message: Message) {
+        if message.from.is_some() {
+            self.search_index.insert(message.clone());
+        }
+        self.message_log.push_back(message);
+        if self.message_log.len() > self.config.history_len {
             let _ = self.message_log.pop_front();
         }
+    }
+
+    /// Broadcast a new message to listeners of the server.
+    pub async fn broadcast_message(&mut self, mut message: Message) {
+        if message.from.is_some() {
+            message.id = Some(MessageId(self.next_message_id));
+            self.next_message_id += 1;
+            if matches!(message.to, ChatTarget::Global | ChatTarget::Room(_)) {
+                message.mentions = self.find_mentions(&message.content);
+            }
+            if message.kind == MessageKind::Speech
+                && let Some(tone) = self.tones.get(&message.tone)
+            {
+                message.content = tone.render(&message.content);
+            }
+            // Quiet tones only reach the patrons sitting nearby, and stay between them.
+            if let (Some(ChatTarget::User(speaker)), Reach::Nearby) =
+                (message.from, self.reach_of(&message))
+            {
+                let room = self.room_of(speaker);
+                let mut heard_by = self
+                    .clients
+                    .keys()
+                    .copied()
+                    .filter(|id| self.room_of(*id) == room)
+                    .collect::<Vec<_>>();
+                heard_by.sort();
+                message.mentions.retain(|id| heard_by.contains(id));
+                if message.to == ChatTarget::Global {
+                    message.heard_by = Some(heard_by);
+                }
+            }
+        }
+        if let Some(id) = message.id {
+            self.audiences.insert(id, self.audience_of(&message));
+        }
+        // Insert the new message into the log.
+        self.log_message(message.clone());
+        self.journal(JournalEntry::Message(Box::new(message.clone())));
 
         let mut failed_client = vec![];
+        let irc_from = message
+            .from
+            .map(|from| irc::user_prefix(&self.display_name(from)));
+        let irc_to = self.irc_name(message.to);
 
         if let Err(e) = match message.to {
             ChatTarget::Global => {
-                // Broadcast the message to all clients
+                // Broadcast the message to all clients who can hear it.
                 println!("Global: {:?}", message.content.clone());
                 for (id, client) in self.clients.iter_mut() {
-                    if let Err(_) =
-                        to_client(&mut client.send_tx, *id, message.to_output(false)).await
+                    if message.heard(*id)
+                        && let Some(output) =
+                            client_output(*id, client, &message, irc_from.as_deref(), &irc_to)
+                        && to_client(&mut client.send_tx, *id, output).await.is_err()
                     {
                         failed_client.push(*id);
                     }
@@ -194,16 +765,56 @@ impl TavernServer {
             }
             ChatTarget::User(id) => {
                 if let Some(client) = self.clients.get_mut(&id) {
-                    to_client(&mut client.send_tx, id, message.to_output(true))
-                        .await
-                        .inspect_err(|_| {
-                            failed_client.push(id);
+                    match client_output(id, client, &message, irc_from.as_deref(), &irc_to) {
+                        Some(output) => to_client(&mut client.send_tx, id, output)
+                            .await
+                            .inspect_err(|_| {
+                                failed_client.push(id);
+                            }),
+                        None => Ok(()),
+                    }
+                } else if let Some(name) = self.account_ids.get(&id).cloned() {
+                    // Patrons with an account find their direct messages in their mailbox.
+                    let reply = self.leave_mail(message.from, &name, &message.content).await;
+                    if let Some(ChatTarget::User(sender)) = message.from {
+                        self.notify_client(SystemNotification {
+                            to: sender,
+                            content: reply,
                         })
+                        .await;
+                    }
+                    Ok(())
+                } else {
+                    Err(ServerError::InvalidMessageTarget(message.to))
+                }
+            }
+            ChatTarget::Room(_) | ChatTarget::Booth(_) => {
+                if let Some(members) = self.members_of(message.to) {
+                    // Only the patrons sitting in the room or booth hear what's said in it.
+                    for id in members {
+                        if let Some(client) = self.clients.get_mut(&id)
+                            && let Some(output) =
+                                client_output(id, client, &message, irc_from.as_deref(), &irc_to)
+                            && to_client(&mut client.send_tx, id, output).await.is_err()
+                        {
+                            failed_client.push(id);
+                        }
+                    }
+                    self.carry_to_adjacent_rooms(&message, irc_from.as_deref())
+                        .await;
+                    Ok(())
+                } else {
+                    Err(ServerError::InvalidMessageTarget(message.to))
+                }
+            }
+            // NPCs have no behavior yet, but bots following an event stream hear them.
+            ChatTarget::Npc(id) => {
+                if self.npcs.contains_key(&id) {
+                    Ok(())
                 } else {
                     Err(ServerError::InvalidMessageTarget(message.to))
                 }
             }
-            ChatTarget::Npc(_id) => todo!("NPC behavior to be implemented later"),
         } {
             // Send reply to Client user.
             if let Some(ChatTarget::User(sender)) = message.from {
@@ -219,79 +830,2929 @@ impl TavernServer {
             }
         }
 
+        // Bots following an event stream also see room messages and the private messages
+        // they're part of.
+        if message.to != ChatTarget::Global {
+            for (id, client) in self.clients.iter_mut() {
+                if let ClientProtocol::EventStream { .. } = client.protocol
+                    && let Some(output) = client_output(*id, client, &message, None, &irc_to)
+                    && to_client(&mut client.send_tx, *id, output).await.is_err()
+                {
+                    failed_client.push(*id);
+                }
+            }
+        }
+
+        // Let the sender of a direct message know if its recipient isn't around.
+        if let (Some(ChatTarget::User(sender)), ChatTarget::User(recipient)) =
+            (message.from, message.to)
+            && sender != recipient
+            && let Some(status) = self.status_of(recipient)
+        {
+            let content = format!(
+                "{} is {status}.",
+                self.display_name(ChatTarget::User(recipient))
+            );
+            self.notify_client(SystemNotification {
+                to: sender,
+                content,
+            })
+            .await;
+        }
+        self.notify_mentions(&message).await;
+        self.react_to_tone(&message).await;
+        self.check_trivia_answer(&message).await;
+
         // Remove bad connections
         for id in failed_client.into_iter() {
             let _ = self.event_tx.send(Event::DisconnectClient { id }).await;
         }
     }
-}
-
-async fn manage_tcp_connections(
-    event_dispatch: mpsc::Sender<Event>,
-    mut shutdown: watch::Receiver<()>,
-) -> anyhow::Result<JoinHandle<()>> {
-    let listener = TcpListener::bind(TCP_PORT).await?;
-
-    Ok(tokio::spawn(async move {
-        println!(
-            "☎️ Rust Tavern server awaiting connections on {:?}",
-            TCP_PORT
-        );
 
-        loop {
-            tokio::select! {
-                Ok((socket, addr)) = listener.accept() => {
-                    println!("🍺 New client connected: {addr}");
-                    let _ = event_dispatch.send(Event::NewClient { connection: socket, addr}).await;
+    /// Answers a request made through the HTTP API on behalf of `bot`.
+    /// Returns the connection back if it should be kept open as an event stream.
+    async fn handle_api_request(
+        &mut self,
+        request: ApiRequest,
+        mut connection: TcpStream,
+        bot: NpcId,
+    ) -> Option<TcpStream> {
+        let output = match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/users") => {
+                let users = self
+                    .clients
+                    .iter()
+                    .filter(|(_, client)| {
+                        !matches!(client.protocol, ClientProtocol::EventStream { .. })
+                    })
+                    .map(|(id, _)| self.user_json(*id))
+                    .collect::<Vec<_>>();
+                http::json_response("200 OK", &json!(users))
+            }
+            ("GET", "/npcs") => {
+                let npcs = self
+                    .npcs
+                    .iter()
+                    .map(|(id, npc)| {
+                        json!({
+                            "id": id,
+                            "name": self.display_name(ChatTarget::Npc(*id)),
+                            "state": npc.state(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                http::json_response("200 OK", &json!(npcs))
+            }
+            ("GET", "/rooms") => {
+                let rooms = self
+                    .rooms
+                    .iter()
+                    .map(|(id, room)| {
+                        json!({
+                            "id": id,
+                            "name": room.name(),
+                            "members": room.members,
+                            "npcs": room.npcs,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                http::json_response("200 OK", &json!(rooms))
+            }
+            ("GET", "/history") => {
+                let limit = request
+                    .query
+                    .get("limit")
+                    .and_then(|limit| limit.parse::<usize>().ok())
+                    .unwrap_or(self.config.history_len);
+                let visible = self
+                    .message_log
+                    .iter()
+                    .filter(|message| visible_to_bot(message, bot))
+                    .collect::<Vec<_>>();
+                let history = &visible[visible.len().saturating_sub(limit)..];
+                http::json_response("200 OK", &json!(history))
+            }
+            ("POST", "/messages") => match serde_json::from_str::<PostMessage>(&request.body) {
+                Ok(post) if post.content.trim().is_empty() => {
+                    http::error_response("400 Bad Request", "Message content is empty")
                 }
-                Ok(()) = shutdown.changed() => {
-                    break;
+                Ok(post)
+                    if post
+                        .tone
+                        .as_ref()
+                        .is_some_and(|t| self.tones.get(t).is_none()) =>
+                {
+                    http::error_response("400 Bad Request", "No such tone")
                 }
+                Ok(post) => {
+                    let exists = match post.to {
+                        ChatTarget::Global => true,
+                        ChatTarget::User(id) => self.clients.contains_key(&id),
+                        ChatTarget::Npc(id) => self.npcs.contains_key(&id),
+                        ChatTarget::Room(id) => self.rooms.contains_key(&id),
+                        // Booths are for their members only.
+                        ChatTarget::Booth(_) => false,
+                    };
+                    if exists {
+                        let from = match post.sender {
+                            MessageSender::Bot => Some(ChatTarget::Npc(bot)),
+                            MessageSender::System => None,
+                        };
+                        let message = Message::new(from, post.to, &post.content, post.tone);
+                        let body = json!(message);
+                        self.broadcast_message(message).await;
+                        http::json_response("202 Accepted", &body)
+                    } else {
+                        http::error_response("404 Not Found", "No such message target")
+                    }
+                }
+                Err(e) => http::error_response("400 Bad Request", &e.to_string()),
+            },
+            ("GET", "/events") => {
+                // The headers are the first thing written to the connection, so they fit in its
+                // buffer and are written at once.
+                let headers = http::event_stream_headers();
+                return connection
+                    .write_all(headers.as_bytes())
+                    .await
+                    .is_ok()
+                    .then_some(connection);
             }
-        }
-    }))
-}
+            (_, "/users" | "/npcs" | "/rooms" | "/history" | "/messages" | "/events") => {
+                http::error_response("405 Method Not Allowed", "Method not allowed")
+            }
+            _ => http::error_response("404 Not Found", "Not found"),
+        };
 
-async fn to_client(send_tx: &mut OwnedWriteHalf, id: UserId, message: String) -> ServerResult {
-    // Ignore error when broadcasting.
-    send_tx
-        .write_all(message.as_bytes())
-        .await
-        .map_err(|_| ServerError::TcpConnectionFailed(id))?;
-    send_tx
-        .flush()
-        .await
-        .map_err(|_| ServerError::TcpConnectionFailed(id))?;
-    Ok(())
-}
+        // Responses can be long, so they're written by a task of their own, and a client slow
+        // to read them never holds up the tavern.
+        tokio::spawn(async move {
+            let _ = connection.write_all(output.as_bytes()).await;
+        });
+        None
+    }
 
-/// A new TCP client has been connected to the server.
-fn watch_client(
-    id: UserId,
-    read_half: OwnedReadHalf,
-    event_tx: mpsc::Sender<Event>,
-    mut shutdown_rx: watch::Receiver<()>,
-) -> JoinHandle<()> {
-    tokio::spawn(async move {
-        let mut lines = BufReader::new(read_half).lines();
-        loop {
-            tokio::select! {
-                res  = lines.next_line() => {
-                    match res {
-                        Ok(Some(incoming)) => {
-                            let _ = event_tx.send(Event::ReceiveUserMessage{from: id, message_raw: incoming}).await;
-                        },
-                        Ok(None) | Err(_) => {
-                            println!("❌ Error in connecting to user: {:?}", id);
-                            let _ = event_tx.send(Event::DisconnectClient { id }).await;
-                            break;
-                        },
+    /// Describes a connected patron for the HTTP API.
+    fn user_json(&self, id: UserId) -> serde_json::Value {
+        let client = self.clients.get(&id);
+        json!({
+            "id": id,
+            "name": self.display_name(ChatTarget::User(id)),
+            "protocol": client.map(|client| protocol_name(&client.protocol)),
+            "target": client.map(|client| client.context.current_target),
+            "room": self.room_of(id),
+            "idle_secs": client.map(|client| client.last_active.elapsed().as_secs()),
+            "status": self.status_of(id),
+            "tone": client.map(|client| &client.context.tone),
+        })
+    }
+
+    /// Sends a join or leave event to every open event stream.
+    async fn stream_announce(&mut self, event: &str, id: UserId) {
+        let output = http::sse_event(event, &self.user_json(id));
+        let streams = self
+            .clients
+            .iter()
+            .filter(|(_, client)| matches!(client.protocol, ClientProtocol::EventStream { .. }))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for stream in streams {
+            self.send_to_client(stream, output.clone()).await;
+        }
+    }
+
+    /// Answers a command that needs the server's state, with a notification.
+    async fn handle_command(&mut self, from: UserId, command: ServerCommand) {
+        let reply = match command {
+            ServerCommand::History(n) => {
+                let n = n.unwrap_or(self.config.replay_len);
+                return self.replay_history(from, n, false).await;
+            }
+            ServerCommand::Search(query) => return self.search_history(from, query).await,
+            ServerCommand::Edit { id, content } => self.edit_message(from, id, &content).await,
+            ServerCommand::Delete(id) => self.delete_message(from, id).await,
+            ServerCommand::React { id, reaction } => {
+                self.react_to_message(from, id, &reaction).await
+            }
+            ServerCommand::Emote { name, target }
+                if self.emotes.get(&name).is_none() && self.tones.find(&name).is_some() =>
+            {
+                match self.speak_in_tone(from, &name, target.as_deref()).await {
+                    Some(reply) => reply,
+                    None => return,
+                }
+            }
+            ServerCommand::Emote { name, target } => {
+                match self.perform_emote(from, &name, target.as_deref()).await {
+                    Some(reply) => reply,
+                    None => return,
+                }
+            }
+            ServerCommand::Emotes => format!(
+                "Emotes: {}",
+                self.emotes
+                    .iter()
+                    .map(|emote| emote.usage())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ServerCommand::Tone(None) => format!(
+                "You speak in a {} tone. Tones: {}",
+                self.clients
+                    .get(&from)
+                    .map(|client| client.context.tone.clone())
+                    .unwrap_or_default(),
+                self.tones
+                    .iter()
+                    .map(|(tone, config)| match &config.command {
+                        Some(command) => format!("{tone} (/{command})"),
+                        None => tone.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            ServerCommand::Tone(Some(name)) => match self.tones.find(&name) {
+                Some((tone, _)) => {
+                    let tone = tone.clone();
+                    if let Some(client) = self.clients.get_mut(&from) {
+                        client.context.tone = tone.clone();
                     }
+                    format!("You now speak in a {tone} tone.")
                 }
-                Ok(()) = shutdown_rx.changed() => {
-                    break;
+                None => format!("There is no {name:?} tone. Use /tone to list them."),
+            },
+            ServerCommand::Roll(RollCommand::Dice(expression)) => {
+                return self.roll_dice(from, &expression).await;
+            }
+            ServerCommand::Roll(RollCommand::Seal) => format!(
+                "The dice are sealed with {}, after {} roll{}. Use /roll reveal to check them.",
+                self.dice.seal(),
+                self.dice.rolls(),
+                if self.dice.rolls() == 1 { "" } else { "s" }
+            ),
+            ServerCommand::Roll(RollCommand::Reveal) => return self.reveal_dice(from).await,
+            ServerCommand::Game(command) => match self.handle_game_command(from, command).await {
+                Some(reply) => reply,
+                None => return,
+            },
+            ServerCommand::Trivia(command) => match (&self.trivia, command) {
+                (None, _) => "There's no trivia in this tavern.".to_owned(),
+                (Some(trivia), TriviaCommand::Question) => match trivia.question() {
+                    Some(question) => format!(
+                        "The question in {} is: {}",
+                        self.display_name(ChatTarget::Room(trivia.room)),
+                        question.question
+                    ),
+                    None => "No trivia question is waiting for an answer.".to_owned(),
+                },
+                (Some(trivia), TriviaCommand::Top) => {
+                    let top = trivia.leaderboard.top(LEADERBOARD_LEN);
+                    if top.is_empty() {
+                        "Nobody has scored at trivia yet.".to_owned()
+                    } else {
+                        format!(
+                            "Trivia leaderboard: {}",
+                            top.iter()
+                                .enumerate()
+                                .map(|(i, score)| format!(
+                                    "{}. {} ({} point{})",
+                                    i + 1,
+                                    score.name,
+                                    score.points,
+                                    if score.points == 1 { "" } else { "s" }
+                                ))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        )
+                    }
+                }
+            },
+            ServerCommand::Reply { id, content } => match self.reply_to(from, id, &content) {
+                Ok(message) => return self.broadcast_message(message).await,
+                Err(e) => e,
+            },
+            ServerCommand::Thread(id) => {
+                let thread = self.thread(from, id);
+                if thread.is_empty() {
+                    format!("There is no message {id}.")
+                } else {
+                    self.notify_client(SystemNotification {
+                        to: from,
+                        content: format!(
+                            "Thread of {} message{}:",
+                            thread.len(),
+                            if thread.len() == 1 { "" } else { "s" }
+                        ),
+                    })
+                    .await;
+                    self.send_history(from, thread).await;
+                    "End of thread.".to_owned()
                 }
             }
-        }
-    })
+            ServerCommand::Mentions => {
+                let mentions = self
+                    .message_log
+                    .iter()
+                    .filter(|message| {
+                        self.mentions(from, message) && self.visible_in_history(from, message)
+                    })
+                    .cloned()
+                    .collect::<Vec<_>>();
+                if mentions.is_empty() {
+                    "Nobody mentioned you lately.".to_owned()
+                } else {
+                    self.notify_client(SystemNotification {
+                        to: from,
+                        content: format!(
+                            "{} message{} mentioning you:",
+                            mentions.len(),
+                            if mentions.len() == 1 { "" } else { "s" }
+                        ),
+                    })
+                    .await;
+                    self.send_history(from, mentions).await;
+                    "End of mentions.".to_owned()
+                }
+            }
+            ServerCommand::Export(query) => self.export_transcript(from, &query),
+            // Passwords are hashed aside, and answered once that's done.
+            ServerCommand::Register { name, password } => {
+                let Err(e) = self.register(from, name, password) else {
+                    return;
+                };
+                e
+            }
+            ServerCommand::Login { name, password } => {
+                let Err(e) = self.login(from, name, password) else {
+                    return;
+                };
+                e
+            }
+            ServerCommand::Mail(command) => self.handle_mail_command(from, command).await,
+            ServerCommand::Admin(password) => match &self.config.admin_password {
+                Some(admin_password) if *admin_password == *password => {
+                    if let Some(client) = self.clients.get_mut(&from) {
+                        client.is_admin = true;
+                    }
+                    "You are now an admin.".to_owned()
+                }
+                Some(_) => "Wrong password.".to_owned(),
+                None => "This tavern has no admins.".to_owned(),
+            },
+            ServerCommand::JoinRoom(name) => match self.find_room(&name) {
+                Some(room) if self.room_of(from) == Some(room) => {
+                    format!(
+                        "You're already in {}.",
+                        self.display_name(ChatTarget::Room(room))
+                    )
+                }
+                Some(room) => {
+                    self.join_room(from, room).await;
+                    self.describe_room(from, room)
+                }
+                None => format!("There is no room called {name:?}. Try /rooms."),
+            },
+            ServerCommand::LeaveRoom => match self.leave_room(from).await {
+                Some(room) => format!("You leave {}.", self.display_name(ChatTarget::Room(room))),
+                None => "You aren't in any room.".to_owned(),
+            },
+            ServerCommand::ListRooms => self.list_rooms(from),
+            ServerCommand::Booth(command) => self.handle_booth_command(from, command).await,
+            ServerCommand::Who => self.list_patrons(),
+            ServerCommand::Whois(name) => match self.find_user(&name) {
+                Some(id) => self.describe_patron(id),
+                None => format!("There is no patron called {name:?}."),
+            },
+        };
+        self.notify_client(SystemNotification {
+            to: from,
+            content: reply,
+        })
+        .await;
+    }
+
+    /// Replays the last `n` messages a patron may see from the message log, marked as history.
+    /// Only global messages are replayed if `global_only` is set, as when a client connects.
+    async fn replay_history(&mut self, id: UserId, n: usize, global_only: bool) {
+        let visible = self
+            .message_log
+            .iter()
+            .filter(|message| {
+                if global_only {
+                    message.to == ChatTarget::Global && message.heard(id)
+                } else {
+                    self.visible_in_history(id, message)
+                }
+            })
+            .collect::<Vec<_>>();
+        let history = visible[visible.len().saturating_sub(n)..]
+            .iter()
+            .map(|message| (*message).clone())
+            .collect::<Vec<_>>();
+        if history.is_empty() {
+            // Connecting to a quiet tavern doesn't need a notice.
+            if !global_only {
+                self.notify_client(SystemNotification {
+                    to: id,
+                    content: "There's nothing in the history yet.".to_owned(),
+                })
+                .await;
+            }
+            return;
+        }
+
+        self.notify_client(SystemNotification {
+            to: id,
+            content: format!(
+                "Replaying the last {} message{}:",
+                history.len(),
+                if history.len() == 1 { "" } else { "s" }
+            ),
+        })
+        .await;
+        self.send_history(id, history).await;
+        self.notify_client(SystemNotification {
+            to: id,
+            content: "End of history.".to_owned(),
+        })
+        .await;
+    }
+
+    /// Sends logged messages to a client, marked as history.
+    async fn send_history(&mut self, id: UserId, messages: Vec<Message>) {
+        for message in messages {
+            let Some(client) = self.clients.get(&id) else {
+                return;
+            };
+            if let Some(message_id) = message.id {
+                self.audiences.entry(message_id).or_default().insert(id);
+            }
+            let message = message.seen_by(id).into_owned();
+            let is_private = matches!(
+                message.to,
+                ChatTarget::User(_) | ChatTarget::Npc(_) | ChatTarget::Booth(_)
+            );
+            let output = match &client.protocol {
+                ClientProtocol::Plain => message.to_history_output(is_private),
+                ClientProtocol::Json => ServerOutput::History(message).to_json_line(),
+                ClientProtocol::Irc(_) => {
+                    let nick = self.display_name(ChatTarget::User(id));
+                    let from = message
+                        .from
+                        .map(|from| self.display_name(from))
+                        .unwrap_or_else(|| IRC_SERVER_NAME.to_owned());
+                    let time = DateTime::<Local>::from(message.timestamp).format("%H:%M");
+                    message
+                        .content
+                        .lines()
+                        .map(|line| {
+                            irc::format_line(
+                                IRC_SERVER_NAME,
+                                "NOTICE",
+                                &[
+                                    &nick,
+                                    &format!(
+                                        "{HISTORY_MARKER} {time} {from} {}: {line}",
+                                        message.tone
+                                    ),
+                                ],
+                            )
+                        })
+                        .collect()
+                }
+                ClientProtocol::EventStream { .. } => return,
+            };
+            self.send_to_client(id, output).await;
+        }
+    }
+
+    /// Searches the history for messages a patron may see, just as `/history` replays them:
+    /// global messages, those of the room and booths they're in, and their own private ones.
+    async fn search_history(&mut self, id: UserId, query: SearchQuery) {
+        let from = match &query.from {
+            Some(name) => match self.find_speaker(name) {
+                Some(from) => Some(from),
+                None => {
+                    return self
+                        .notify_client(SystemNotification {
+                            to: id,
+                            content: format!("There is nobody called {name:?}."),
+                        })
+                        .await;
+                }
+            },
+            None => None,
+        };
+        let since = query.since_time();
+        let found = self.search_index.search(&query.terms, |message| {
+            self.visible_in_history(id, message)
+                && (from.is_none() || message.from == from)
+                && since.is_none_or(|since| message.timestamp >= since)
+        });
+        let results = found[found.len().saturating_sub(SEARCH_RESULTS_LEN)..]
+            .iter()
+            .map(|message| (*message).clone())
+            .collect::<Vec<_>>();
+
+        let content = match found.len() {
+            0 => "No messages found.".to_owned(),
+            1 => "Found 1 message:".to_owned(),
+            n if n > results.len() => {
+                format!("Found {n} messages, showing the last {}:", results.len())
+            }
+            n => format!("Found {n} messages:"),
+        };
+        self.notify_client(SystemNotification { to: id, content })
+            .await;
+        if results.is_empty() {
+            return;
+        }
+        self.send_history(id, results).await;
+        self.notify_client(SystemNotification {
+            to: id,
+            content: "End of search results.".to_owned(),
+        })
+        .await;
+    }
+
+    /// Finds a patron or an NPC by their name, or a patron by their ID.
+    fn find_speaker(&self, name: &str) -> Option<ChatTarget> {
+        self.find_user(name).map(ChatTarget::User).or_else(|| {
+            self.npcs
+                .iter()
+                .find(|(_, npc)| npc.name().eq_ignore_ascii_case(name.trim()))
+                .map(|(id, _)| ChatTarget::Npc(*id))
+        })
+    }
+
+    /// Returns true if a patron may see a logged message again.
+    fn visible_in_history(&self, id: UserId, message: &Message) -> bool {
+        let from_me = message.from == Some(ChatTarget::User(id));
+        match message.to {
+            ChatTarget::Global => message.heard(id),
+            // Replies to commands aren't worth replaying.
+            ChatTarget::User(to) => message.from.is_some() && (to == id || from_me),
+            ChatTarget::Npc(_) => from_me,
+            ChatTarget::Room(_) | ChatTarget::Booth(_) => self.can_target(id, message.to),
+        }
+    }
+
+    /// Finds a room by its name, or by its ID.
+    fn find_room(&self, name: &str) -> Option<RoomId> {
+        let id = name.trim().parse::<u32>().ok().map(RoomId);
+        self.rooms
+            .iter()
+            .find(|(room_id, room)| Some(**room_id) == id || room.is_called(name))
+            .map(|(id, _)| *id)
+    }
+
+    /// The room a patron is sitting in, if any.
+    fn room_of(&self, id: UserId) -> Option<RoomId> {
+        self.rooms
+            .iter()
+            .find(|(_, room)| room.members.contains(&id))
+            .map(|(id, _)| *id)
+    }
+
+    /// The rooms next to a room. Rooms are laid out in a row, in the order they were added.
+    fn adjacent_rooms(&self, room: RoomId) -> Vec<RoomId> {
+        let rooms = self.rooms.keys().copied().collect::<Vec<_>>();
+        let Some(position) = rooms.iter().position(|id| *id == room) else {
+            return vec![];
+        };
+        [position.checked_sub(1), Some(position + 1)]
+            .into_iter()
+            .flatten()
+            .filter_map(|position| rooms.get(position).copied())
+            .collect()
+    }
+
+    /// The room an NPC is sitting in, if any.
+    fn npc_room(&self, id: NpcId) -> Option<RoomId> {
+        self.rooms
+            .iter()
+            .find(|(_, room)| room.npcs.contains(&id))
+            .map(|(id, _)| *id)
+    }
+
+    /// Returns true if a patron is allowed to talk to a target.
+    fn can_target(&self, id: UserId, to: ChatTarget) -> bool {
+        match to {
+            ChatTarget::Global => true,
+            ChatTarget::User(to) => self.clients.contains_key(&to),
+            // NPCs sitting in a room can only be talked to from within it.
+            ChatTarget::Npc(to) => {
+                self.npcs.contains_key(&to)
+                    && self
+                        .npc_room(to)
+                        .is_none_or(|room| self.room_of(id) == Some(room))
+            }
+            ChatTarget::Room(room) => self.room_of(id) == Some(room),
+            ChatTarget::Booth(booth) => self
+                .booths
+                .get(&booth)
+                .is_some_and(|booth| booth.members.contains(&id)),
+        }
+    }
+
+    /// The patrons in a room or booth. Returns None if there is no such room or booth.
+    fn members_of(&self, target: ChatTarget) -> Option<Vec<UserId>> {
+        let members = match target {
+            ChatTarget::Room(room) => &self.rooms.get(&room)?.members,
+            ChatTarget::Booth(booth) => &self.booths.get(&booth)?.members,
+            _ => return None,
+        };
+        Some(members.iter().copied().collect())
+    }
+
+    /// Resets a patron's target to the whole tavern if they can't talk to it anymore.
+    fn retarget_if_unreachable(&mut self, id: UserId) {
+        let target = self
+            .clients
+            .get(&id)
+            .map(|client| client.context.current_target);
+        if target.is_some_and(|target| !self.can_target(id, target))
+            && let Some(client) = self.clients.get_mut(&id)
+        {
+            client.context.current_target = ChatTarget::Global;
+        }
+    }
+
+    /// Moves a patron into a room, out of the one they were in, and talks to the room from now on.
+    async fn join_room(&mut self, id: UserId, room: RoomId) {
+        self.leave_room(id).await;
+        let Some(joined) = self.rooms.get_mut(&room) else {
+            return;
+        };
+        joined.members.insert(id);
+        let channel = joined.irc_channel();
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.context.current_target = ChatTarget::Room(room);
+        }
+
+        let prefix = irc::user_prefix(&self.display_name(ChatTarget::User(id)));
+        self.irc_members_announce(
+            ChatTarget::Room(room),
+            irc::format_line(&prefix, "JOIN", &[&channel]),
+        )
+        .await;
+    }
+
+    /// Takes a patron out of the room they are in. Returns the room they left, if any.
+    async fn leave_room(&mut self, id: UserId) -> Option<RoomId> {
+        let room = self.room_of(id)?;
+        let prefix = irc::user_prefix(&self.display_name(ChatTarget::User(id)));
+        let channel = self.irc_name(ChatTarget::Room(room));
+        self.irc_members_announce(
+            ChatTarget::Room(room),
+            irc::format_line(&prefix, "PART", &[&channel]),
+        )
+        .await;
+
+        if let Some(left) = self.rooms.get_mut(&room) {
+            left.members.remove(&id);
+        }
+        // Stop talking to the room, or to the NPCs left behind in it.
+        self.retarget_if_unreachable(id);
+        Some(room)
+    }
+
+    /// Tells a patron who else is in the room they just joined.
+    fn describe_room(&self, id: UserId, room: RoomId) -> String {
+        let Some(joined) = self.rooms.get(&room) else {
+            return String::new();
+        };
+        let mut others = joined
+            .members
+            .iter()
+            .filter(|member| **member != id)
+            .map(|member| self.display_name(ChatTarget::User(*member)))
+            .chain(
+                joined
+                    .npcs
+                    .iter()
+                    .map(|npc| self.display_name(ChatTarget::Npc(*npc))),
+            )
+            .collect::<Vec<_>>();
+        others.sort();
+        if others.is_empty() {
+            format!("You join {}. It's empty.", joined.name())
+        } else {
+            format!(
+                "You join {}. Also here: {}.",
+                joined.name(),
+                others.join(", ")
+            )
+        }
+    }
+
+    /// Lists the rooms of the tavern, and who is in them.
+    fn list_rooms(&self, id: UserId) -> String {
+        let current = self.room_of(id);
+        let mut lines = vec!["Rooms of the tavern:".to_owned()];
+        for (room_id, room) in self.rooms.iter() {
+            let mut line = format!(
+                "  {} ({}): {} patron{}",
+                room.name(),
+                room.key(),
+                room.members.len(),
+                if room.members.len() == 1 { "" } else { "s" }
+            );
+            if !room.npcs.is_empty() {
+                let npcs = room
+                    .npcs
+                    .iter()
+                    .map(|npc| self.display_name(ChatTarget::Npc(*npc)))
+                    .collect::<Vec<_>>();
+                line.push_str(&format!(", with {}", npcs.join(", ")));
+            }
+            if current == Some(*room_id) {
+                line.push_str(" (you are here)");
+            }
+            lines.push(line);
+        }
+        lines.push("Use /join <room> to sit down, and /leave to get back up.".to_owned());
+        lines.join("\n")
+    }
+
+    /// Checks that a patron may change a message: they wrote it, or they're an admin.
+    /// Returns true if they wrote it.
+    fn check_author(&self, id: UserId, message_id: MessageId) -> Result<bool, String> {
+        let Some(message) = self.search_index.get(message_id) else {
+            return Err(format!("There is no message {message_id}."));
+        };
+        let account = self.account_ids.get(&id);
+        let is_author = match message.from {
+            Some(ChatTarget::User(author)) => {
+                author == id || account.is_some_and(|a| self.account_ids.get(&author) == Some(a))
+            }
+            _ => false,
+        };
+        let is_admin = self.clients.get(&id).is_some_and(|client| client.is_admin);
+        if is_author || is_admin {
+            Ok(is_author)
+        } else {
+            Err("You can only change your own messages.".to_owned())
+        }
+    }
+
+    /// Changes a message in the history. Returns the edited message.
+    fn apply_edit(&mut self, id: MessageId, content: &str) -> Option<Message> {
+        let message = self.search_index.edit(id, content)?.clone();
+        if let Some(logged) = self.message_log.iter_mut().find(|m| m.id == Some(id)) {
+            *logged = message.clone();
+        }
+        Some(message)
+    }
+
+    /// Removes a message from the history, along with its quote in the replies to it.
+    /// Returns the deleted message.
+    fn apply_delete(&mut self, id: MessageId) -> Option<Message> {
+        self.message_log.retain(|message| message.id != Some(id));
+        let replies = self
+            .message_log
+            .iter_mut()
+            .chain(self.search_index.messages_mut())
+            .filter_map(|message| message.reply_to.as_mut())
+            .filter(|reply_to| reply_to.id == id);
+        for reply_to in replies {
+            reply_to.forget_quote();
+        }
+        self.search_index.remove(id)
+    }
+
+    /// Speaks in a tone from now on, given its name or command, and says something in it if given.
+    /// Returns the reply to the patron, if they wouldn't see their message otherwise.
+    async fn speak_in_tone(
+        &mut self,
+        from: UserId,
+        name: &str,
+        content: Option<&str>,
+    ) -> Option<String> {
+        let tone = self.tones.find(name)?.0.clone();
+        let client = self.clients.get_mut(&from)?;
+        client.context.tone = tone.clone();
+        let to = client.context.current_target;
+        let content = content?;
+        self.broadcast_message(Message::new(
+            Some(ChatTarget::User(from)),
+            to,
+            content,
+            Some(tone),
+        ))
+        .await;
+        match to {
+            ChatTarget::User(_) | ChatTarget::Npc(_) => Some(format!("To {to}: {content}")),
+            _ => None,
+        }
+    }
+
+    /// How far a message carries, given its tone.
+    fn reach_of(&self, message: &Message) -> Reach {
+        self.tones
+            .get(&message.tone)
+            .map(|tone| tone.reach)
+            .unwrap_or_default()
+    }
+
+    /// Lets the patrons in the rooms next to the one a loud message was sent to hear it too.
+    async fn carry_to_adjacent_rooms(&mut self, message: &Message, irc_from: Option<&str>) {
+        let ChatTarget::Room(room) = message.to else {
+            return;
+        };
+        if self.reach_of(message) != Reach::Adjacent {
+            return;
+        }
+        let mut overheard = message.clone();
+        overheard.content = format!("(from the next room) {}", message.content);
+        for next_room in self.adjacent_rooms(room) {
+            let irc_to = self.irc_name(ChatTarget::Room(next_room));
+            let members = self
+                .members_of(ChatTarget::Room(next_room))
+                .unwrap_or_default();
+            for id in members {
+                if let Some(client) = self.clients.get_mut(&id)
+                    && let Some(output) = client_output(id, client, &overheard, irc_from, &irc_to)
+                {
+                    let _ = to_client(&mut client.send_tx, id, output).await;
+                }
+            }
+        }
+    }
+
+    /// Lets the NPCs hearing a patron react to their tone, if it's one they react to.
+    async fn react_to_tone(&mut self, message: &Message) {
+        let (Some(ChatTarget::User(speaker)), MessageKind::Speech) = (message.from, message.kind)
+        else {
+            return;
+        };
+        let Some(reaction) = self
+            .tones
+            .get(&message.tone)
+            .and_then(|tone| tone.npc_reaction(&self.display_name(ChatTarget::User(speaker))))
+        else {
+            return;
+        };
+        let (npcs, to) = match message.to {
+            ChatTarget::Npc(npc) => (vec![npc], ChatTarget::User(speaker)),
+            ChatTarget::Room(room) => match self.rooms.get(&room) {
+                Some(room) => (room.npcs.iter().copied().collect(), message.to),
+                None => return,
+            },
+            _ => return,
+        };
+        for npc in npcs {
+            // Bots speak for themselves.
+            let is_bot = self.api_tokens.values().any(|bot| *bot == npc);
+            if is_bot || self.npcs.get(&npc).is_none_or(Npc::is_disabled) {
+                continue;
+            }
+            let _ = self
+                .event_tx
+                .send(Event::BroadcastMessage {
+                    message: Message::new(Some(ChatTarget::Npc(npc)), to, &reaction, None),
+                })
+                .await;
+        }
+    }
+
+    /// Does an emote where the patron is talking, at a target if given. Returns the reply
+    /// to the patron, if they wouldn't see the emote otherwise.
+    async fn perform_emote(
+        &mut self,
+        from: UserId,
+        name: &str,
+        target: Option<&str>,
+    ) -> Option<String> {
+        let Some(emote) = self.emotes.get(name).cloned() else {
+            return Some("Unknown command.".to_owned());
+        };
+        let to = self
+            .clients
+            .get(&from)
+            .map(|client| client.context.current_target)
+            .unwrap_or_default();
+        let target = match target {
+            Some(name) => match self.find_speaker(name) {
+                Some(target) => Some(target),
+                None => return Some(format!("There is nobody called {name:?}.")),
+            },
+            // Emotes done while talking to someone are done at them.
+            None if emote.at.is_some()
+                && matches!(to, ChatTarget::User(_) | ChatTarget::Npc(_)) =>
+            {
+                Some(to)
+            }
+            None => None,
+        };
+        let actor = self.display_name(ChatTarget::User(from));
+        let target_name = target.map(|target| self.display_name(target));
+        let lines = match emote.perform(&actor, target_name.as_deref()) {
+            Ok(lines) => lines,
+            Err(e) => return Some(e),
+        };
+
+        let mut message = Message::new(
+            Some(ChatTarget::User(from)),
+            to,
+            &lines.others,
+            Some(emote.tone),
+        );
+        message.kind = MessageKind::Emote;
+        message.emote = Some(EmoteView {
+            name: emote.name,
+            actor_line: lines.actor.clone(),
+            target: match target {
+                Some(ChatTarget::User(id)) => Some(id),
+                _ => None,
+            },
+            target_line: lines.target,
+        });
+        self.broadcast_message(message).await;
+        matches!(to, ChatTarget::User(_) | ChatTarget::Npc(_)).then_some(lines.actor)
+    }
+
+    /// Rolls dice for a patron, for everyone they talk to to see.
+    async fn roll_dice(&mut self, from: UserId, expression: &DiceExpression) {
+        let to = self
+            .clients
+            .get(&from)
+            .map(|client| client.context.current_target)
+            .unwrap_or_default();
+        let roll = self.dice.roll(expression);
+        let mut message = Message::new(Some(ChatTarget::User(from)), to, &roll.to_string(), None);
+        message.kind = MessageKind::Roll;
+        message.roll = Some(roll);
+        self.broadcast_message(message).await;
+    }
+
+    /// Tells the whole tavern the seed of the dice, so anyone can check the rolls made with
+    /// them, then seals new dice.
+    async fn reveal_dice(&mut self, from: UserId) {
+        let revealed = std::mem::take(&mut self.dice);
+        let content = format!(
+            "{} revealed the dice sealed with {}: their seed is {}, after {} roll{}. \
+             The new dice are sealed with {}.",
+            self.display_name(ChatTarget::User(from)),
+            revealed.seal(),
+            revealed.seed(),
+            revealed.rolls(),
+            if revealed.rolls() == 1 { "" } else { "s" },
+            self.dice.seal()
+        );
+        self.broadcast_message(Message::new(None, ChatTarget::Global, &content, None))
+            .await;
+    }
+
+    /// Plays the game where the patron is. What happens is announced to everyone there, and
+    /// the patron is only answered when nothing happens.
+    async fn handle_game_command(&mut self, from: UserId, command: GameCommand) -> Option<String> {
+        let place = self
+            .clients
+            .get(&from)
+            .map(|client| client.context.current_target)
+            .unwrap_or_default();
+        if matches!(place, ChatTarget::User(_) | ChatTarget::Npc(_)) {
+            return Some("Games are played in a room, or with the whole tavern.".to_owned());
+        }
+        let name = self.display_name(ChatTarget::User(from));
+        match command {
+            GameCommand::Start(kind) => {
+                if let Some(game) = self.games.get(&place) {
+                    return Some(format!("There's already a game of {} here.", game.kind));
+                }
+                self.games.insert(place, Game::new(kind, from, &name));
+                self.schedule_game_timeout(place);
+                self.announce(
+                    place,
+                    &format!("{name} wants to play {kind}. /game join to play too."),
+                )
+                .await;
+                None
+            }
+            GameCommand::Status => Some(match self.games.get(&place) {
+                Some(game) => game.status(),
+                None => NO_GAME.to_owned(),
+            }),
+            GameCommand::Join => {
+                self.play_game(place, |game, _| game.join(from, &name))
+                    .await
+            }
+            GameCommand::Roll => {
+                self.play_game(place, |game, dice| game.roll(from, dice))
+                    .await
+            }
+            GameCommand::Hold(positions) => {
+                self.play_game(place, |game, _| game.hold(from, &positions))
+                    .await
+            }
+            GameCommand::Stand => self.play_game(place, |game, _| game.stand(from)).await,
+        }
+    }
+
+    /// Changes the game played somewhere, and announces what happened. Returns why nothing
+    /// happened otherwise. Games are put away once over, and players are given a deadline
+    /// whenever the turn changes.
+    async fn play_game(
+        &mut self,
+        place: ChatTarget,
+        play: impl FnOnce(&mut Game, &mut Dice) -> Result<Vec<String>, String>,
+    ) -> Option<String> {
+        let Some(game) = self.games.get_mut(&place) else {
+            return Some(NO_GAME.to_owned());
+        };
+        let turn = game.turn();
+        let lines = match play(game, &mut self.dice) {
+            Ok(lines) => lines,
+            Err(e) => return Some(e),
+        };
+        if game.is_over() {
+            self.games.remove(&place);
+        } else if game.turn() != turn {
+            self.schedule_game_timeout(place);
+        }
+        self.announce(place, &lines.join("\n")).await;
+        None
+    }
+
+    /// Tells the server to check on the game played somewhere once its turn may have timed out.
+    fn schedule_game_timeout(&self, place: ChatTarget) {
+        let Some(turn) = self.games.get(&place).map(Game::turn) else {
+            return;
+        };
+        let event_tx = self.event_tx.clone();
+        let timeout = self.config.game_turn_timeout;
+        tokio::spawn(async move {
+            tokio::time::sleep(timeout).await;
+            let _ = event_tx.send(Event::GameTimeout { place, turn }).await;
+        });
+    }
+
+    /// Asks the next trivia question, or closes the one nobody answered in time. Questions are
+    /// only asked when someone is in the quizmaster's room to hear them.
+    async fn run_trivia(&mut self, round: u64) {
+        let Some(trivia) = self
+            .trivia
+            .as_mut()
+            .filter(|trivia| trivia.round() == round)
+        else {
+            return;
+        };
+        let line = match trivia.question() {
+            Some(_) => trivia.expire(),
+            None if self
+                .rooms
+                .get(&trivia.room)
+                .is_some_and(|room| !room.members.is_empty()) =>
+            {
+                trivia.ask(&mut self.dice)
+            }
+            None => None,
+        };
+        if let Some(line) = line {
+            self.quizmaster_says(&line).await;
+        }
+        self.schedule_trivia();
+    }
+
+    /// Lets the quizmaster hear answers to its question, said in its room.
+    async fn check_trivia_answer(&mut self, message: &Message) {
+        let (Some(ChatTarget::User(from)), MessageKind::Speech) = (message.from, message.kind)
+        else {
+            return;
+        };
+        let name = self.display_name(ChatTarget::User(from));
+        let account = self.clients.get(&from).and_then(|c| c.account.clone());
+        let Some(trivia) = self
+            .trivia
+            .as_mut()
+            .filter(|trivia| message.to == ChatTarget::Room(trivia.room))
+        else {
+            return;
+        };
+        if let Some(line) = trivia.answer(&name, account.as_deref(), &message.content) {
+            self.quizmaster_says(&line).await;
+            self.schedule_trivia();
+        }
+    }
+
+    async fn quizmaster_says(&self, content: &str) {
+        let Some(trivia) = &self.trivia else {
+            return;
+        };
+        let _ = self
+            .event_tx
+            .send(Event::BroadcastMessage {
+                message: Message::new(
+                    Some(ChatTarget::Npc(trivia.npc)),
+                    ChatTarget::Room(trivia.room),
+                    content,
+                    None,
+                ),
+            })
+            .await;
+    }
+
+    /// Tells the server to move trivia on once its round may have timed out.
+    fn schedule_trivia(&self) {
+        let Some(round) = self.trivia.as_ref().map(Trivia::round) else {
+            return;
+        };
+        let event_tx = self.event_tx.clone();
+        let interval = self.config.trivia_interval;
+        tokio::spawn(async move {
+            tokio::time::sleep(interval).await;
+            let _ = event_tx.send(Event::TriviaTimeout { round }).await;
+        });
+    }
+
+    /// Says something from the house to everyone somewhere.
+    async fn announce(&mut self, place: ChatTarget, content: &str) {
+        if !content.is_empty() {
+            self.broadcast_message(Message::new(None, place, content, None))
+                .await;
+        }
+    }
+
+    /// Builds a reply to a message the patron can see. It's sent where the parent was, or back
+    /// to the author of a direct message.
+    fn reply_to(&self, from: UserId, id: MessageId, content: &str) -> Result<Message, String> {
+        let parent = self
+            .search_index
+            .get(id)
+            .filter(|parent| self.visible_in_history(from, parent))
+            .ok_or_else(|| format!("There is no message {id}."))?;
+        let to = match (parent.to, parent.from) {
+            (ChatTarget::User(to), Some(author)) if to == from => author,
+            (to, _) => to,
+        };
+        if !matches!(
+            to,
+            ChatTarget::Global | ChatTarget::User(_) | ChatTarget::Npc(_)
+        ) && !self.can_target(from, to)
+        {
+            return Err(format!("You can't reply to {id} from here."));
+        }
+        let mut message = Message::new(Some(ChatTarget::User(from)), to, content, None);
+        message.reply_to = Some(ReplyTo::new(
+            id,
+            &self.display_name(parent.from.unwrap_or_default()),
+            &parent.content,
+        ));
+        Ok(message)
+    }
+
+    /// The thread a message belongs to, as far as the patron can see it: the message its replies
+    /// lead back to, and every reply to it, oldest first.
+    fn thread(&self, from: UserId, id: MessageId) -> Vec<Message> {
+        let Some(mut root) = self.search_index.get(id) else {
+            return vec![];
+        };
+        while let Some(parent) = root
+            .reply_to
+            .as_ref()
+            .and_then(|reply_to| self.search_index.get(reply_to.id))
+        {
+            root = parent;
+        }
+        // Replies always come after their parent, so a single pass finds them all.
+        let mut ids = HashSet::from([root.id]);
+        self.search_index
+            .messages()
+            .skip_while(|message| message.id != root.id)
+            .filter(|message| {
+                let in_thread = message.id == root.id
+                    || message
+                        .reply_to
+                        .as_ref()
+                        .is_some_and(|reply_to| ids.contains(&Some(reply_to.id)));
+                if in_thread {
+                    ids.insert(message.id);
+                }
+                in_thread && self.visible_in_history(from, message)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Adds or takes back a reaction to a message in the history. Returns the message.
+    fn apply_reaction(&mut self, id: MessageId, by: UserId, reaction: &str) -> Option<Message> {
+        let message = self.search_index.get_mut(id)?;
+        message.toggle_reaction(by, reaction);
+        let message = message.clone();
+        if let Some(logged) = self.message_log.iter_mut().find(|m| m.id == Some(id)) {
+            *logged = message.clone();
+        }
+        Some(message)
+    }
+
+    /// Reacts to a message the patron can see, and shows its reactions to everyone who saw it.
+    async fn react_to_message(&mut self, from: UserId, id: MessageId, reaction: &str) -> String {
+        let visible = self
+            .search_index
+            .get(id)
+            .is_some_and(|message| self.visible_in_history(from, message));
+        let reaction = reaction.chars().take(MAX_REACTION_LEN).collect::<String>();
+        let Some(message) = visible
+            .then(|| self.apply_reaction(id, from, &reaction))
+            .flatten()
+        else {
+            return format!("There is no message {id}.");
+        };
+        self.journal(JournalEntry::React {
+            id,
+            by: from,
+            reaction: reaction.clone(),
+            timestamp: SystemTime::now(),
+        });
+        let reacted = message
+            .reactions
+            .get(&reaction)
+            .is_some_and(|by| by.contains(&from));
+        let name = self.display_name(ChatTarget::User(from));
+        let text = match message.reactions.is_empty() {
+            true => format!("{name} took back their reaction to {id}."),
+            false => format!("Reactions to {id}: {}", message.reaction_summary()),
+        };
+        self.broadcast_update(
+            &message,
+            text,
+            ServerOutput::Reactions {
+                id,
+                reactions: message.reactions.clone(),
+            },
+        )
+        .await;
+        match reacted {
+            true => format!("You reacted to {id} with {reaction}."),
+            false => format!("You took back {reaction} on {id}."),
+        }
+    }
+
+    async fn edit_message(&mut self, from: UserId, id: MessageId, content: &str) -> String {
+        let is_author = match self.check_author(from, id) {
+            Ok(is_author) => is_author,
+            Err(e) => return e,
+        };
+        // Rolls are made by the server, and stay as they were rolled.
+        if self
+            .search_index
+            .get(id)
+            .is_some_and(|message| message.kind == MessageKind::Roll)
+        {
+            return "Dice rolls can't be edited.".to_owned();
+        }
+        let Some(message) = self.apply_edit(id, content) else {
+            return format!("There is no message {id}.");
+        };
+        self.journal(JournalEntry::Edit {
+            id,
+            content: content.to_owned(),
+            timestamp: SystemTime::now(),
+        });
+        if !is_author {
+            self.log_moderation(ModerationAction::new(
+                Some(ChatTarget::User(from)),
+                &format!("edit {id}"),
+                message.from.unwrap_or_default(),
+            ));
+        }
+        let name = self.display_name(ChatTarget::User(from));
+        self.broadcast_update(
+            &message,
+            format!("{name} edited {id}: {content}"),
+            ServerOutput::Edit(message.clone()),
+        )
+        .await;
+        format!("Message {id} edited.")
+    }
+
+    async fn delete_message(&mut self, from: UserId, id: MessageId) -> String {
+        let is_author = match self.check_author(from, id) {
+            Ok(is_author) => is_author,
+            Err(e) => return e,
+        };
+        let Some(message) = self.apply_delete(id) else {
+            return format!("There is no message {id}.");
+        };
+        self.journal(JournalEntry::Delete {
+            id,
+            timestamp: SystemTime::now(),
+        });
+        if !is_author {
+            self.log_moderation(ModerationAction::new(
+                Some(ChatTarget::User(from)),
+                &format!("delete {id}"),
+                message.from.unwrap_or_default(),
+            ));
+        }
+        self.scrub_journal(id);
+        let name = self.display_name(ChatTarget::User(from));
+        self.broadcast_update(
+            &message,
+            format!("{name} deleted {id}."),
+            ServerOutput::Delete { id },
+        )
+        .await;
+        self.audiences.remove(&id);
+        format!("Message {id} deleted.")
+    }
+
+    /// The patrons a new message reaches: its author, and everyone who hears it.
+    fn audience_of(&self, message: &Message) -> HashSet<UserId> {
+        let mut audience = match message.to {
+            ChatTarget::Global => self
+                .clients
+                .keys()
+                .copied()
+                .filter(|id| message.heard(*id))
+                .collect(),
+            ChatTarget::Room(room) => {
+                let mut rooms = vec![room];
+                if self.reach_of(message) == Reach::Adjacent {
+                    rooms.extend(self.adjacent_rooms(room));
+                }
+                rooms
+                    .into_iter()
+                    .flat_map(|room| self.members_of(ChatTarget::Room(room)).unwrap_or_default())
+                    .collect()
+            }
+            ChatTarget::Booth(_) => self
+                .members_of(message.to)
+                .unwrap_or_default()
+                .into_iter()
+                .collect(),
+            ChatTarget::User(to) => HashSet::from([to]),
+            ChatTarget::Npc(_) => HashSet::new(),
+        };
+        if let Some(ChatTarget::User(author)) = message.from {
+            audience.insert(author);
+        }
+        audience
+    }
+
+    /// Tells everyone a message was sent or replayed to that it changed. JSON clients get
+    /// `output`, everyone else reads `text`.
+    async fn broadcast_update(&mut self, message: &Message, text: String, output: ServerOutput) {
+        let audience = message
+            .id
+            .and_then(|id| self.audiences.get(&id))
+            .cloned()
+            .unwrap_or_default();
+        let event = match output {
+            ServerOutput::Edit(_) => "edit",
+            ServerOutput::Delete { .. } => "delete",
+            ServerOutput::Reactions { .. } => "reactions",
+            _ => "message",
+        };
+        let ids = self.clients.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            let Some(client) = self.clients.get(&id) else {
+                continue;
+            };
+            let sees = audience.contains(&id) || message.from == Some(ChatTarget::User(id));
+            let line = match &client.protocol {
+                _ if !sees && !matches!(client.protocol, ClientProtocol::EventStream { .. }) => {
+                    continue;
+                }
+                ClientProtocol::Plain => SystemNotification {
+                    to: id,
+                    content: text.clone(),
+                }
+                .to_output(),
+                ClientProtocol::Json => output.to_json_line(),
+                ClientProtocol::Irc(session) if session.registered => irc::format_line(
+                    IRC_SERVER_NAME,
+                    "NOTICE",
+                    &[&self.display_name(ChatTarget::User(id)), &text],
+                ),
+                ClientProtocol::Irc(_) => continue,
+                ClientProtocol::EventStream { bot } if visible_to_bot(message, *bot) => {
+                    http::sse_event(event, &serde_json::to_value(&output).unwrap_or_default())
+                }
+                ClientProtocol::EventStream { .. } => continue,
+            };
+            self.send_to_client(id, line).await;
+        }
+    }
+
+    /// Finds the patrons mentioned in a message as `@name`, by the name they're known by.
+    fn find_mentions(&self, content: &str) -> Vec<UserId> {
+        let mut mentions = vec![];
+        for name in content.split('@').skip(1).map(|rest| {
+            rest.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+                .next()
+                .unwrap_or_default()
+        }) {
+            let mentioned = self
+                .clients
+                .iter()
+                .filter(|(_, client)| {
+                    !matches!(client.protocol, ClientProtocol::EventStream { .. })
+                })
+                .map(|(id, _)| *id)
+                .find(|id| {
+                    !name.is_empty()
+                        && self
+                            .display_name(ChatTarget::User(*id))
+                            .eq_ignore_ascii_case(name)
+                });
+            if let Some(id) = mentioned
+                && !mentions.contains(&id)
+            {
+                mentions.push(id);
+            }
+        }
+        mentions
+    }
+
+    /// Lets mentioned patrons know, if they might miss the message: when they're away,
+    /// or sitting in another room.
+    async fn notify_mentions(&mut self, message: &Message) {
+        let Some(from) = message.from else {
+            return;
+        };
+        let sender = self.display_name(from);
+        for &id in &message.mentions {
+            if from == ChatTarget::User(id) {
+                continue;
+            }
+            let elsewhere = match message.to {
+                ChatTarget::Room(room) => self.room_of(id) != Some(room),
+                _ => self.room_of(id).is_some(),
+            };
+            if !elsewhere && self.status_of(id).is_none() {
+                continue;
+            }
+            self.notify_client(SystemNotification {
+                to: id,
+                content: format!(
+                    "{sender} mentioned you in {}: {}",
+                    self.display_name(message.to),
+                    message.content
+                ),
+            })
+            .await;
+        }
+    }
+
+    /// Returns true if a message mentions a patron, during this visit or a previous one
+    /// made with the same account.
+    fn mentions(&self, id: UserId, message: &Message) -> bool {
+        let account = self.account_ids.get(&id);
+        message.mentions.iter().any(|mentioned| {
+            *mentioned == id || account.is_some_and(|a| self.account_ids.get(mentioned) == Some(a))
+        })
+    }
+
+    /// Checks whether a patron may give a password now, and notes that one is being checked.
+    fn start_password_check(&mut self, id: UserId) -> Result<(), String> {
+        let Some(client) = self.clients.get_mut(&id) else {
+            return Err(String::new());
+        };
+        if let Some(account) = &client.account {
+            return Err(format!("You're already logged in as {account}."));
+        }
+        if client.checking_password {
+            return Err("Your last password is still being checked.".to_owned());
+        }
+        if client.failed_logins >= MAX_FAILED_LOGINS {
+            return Err("Too many wrong passwords. Reconnect to try again.".to_owned());
+        }
+        client.checking_password = true;
+        Ok(())
+    }
+
+    /// Hashes the password of a new account away from the event loop. The account is created
+    /// once the hash comes back, in `finish_register`.
+    fn register(&mut self, id: UserId, name: String, password: Password) -> Result<(), String> {
+        self.check_new_account(id, &name)?;
+        self.start_password_check(id)?;
+        let event_tx = self.event_tx.clone();
+        tokio::task::spawn_blocking(move || {
+            let password = PasswordHash::new(&password);
+            let _ = event_tx.blocking_send(Event::PasswordHashed {
+                from: id,
+                name,
+                password,
+            });
+        });
+        Ok(())
+    }
+
+    /// Creates an account, and logs the patron into it.
+    fn finish_register(&mut self, id: UserId, name: &str, password: PasswordHash) -> String {
+        match self.clients.get_mut(&id) {
+            Some(client) => client.checking_password = false,
+            None => return String::new(),
+        }
+        if let Err(e) = self.check_new_account(id, name) {
+            return e;
+        }
+        if !self.accounts.register(name, password) {
+            return format!("There is already an account called {name}.");
+        }
+        self.save_accounts();
+        self.log_in(id, name);
+        format!("Welcome, {name}! Your account is registered, with an empty mailbox.")
+    }
+
+    /// Checks that a patron can register an account with a name.
+    fn check_new_account(&self, id: UserId, name: &str) -> Result<(), String> {
+        if let Some(account) = self.clients.get(&id).and_then(|c| c.account.as_ref()) {
+            return Err(format!("You're already logged in as {account}."));
+        }
+        let valid = (2..=20).contains(&name.chars().count())
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            // Names like "user3" would be mistaken for another patron.
+            && ["user", "npc"].iter().all(|prefix| {
+                name.to_lowercase()
+                    .strip_prefix(prefix)
+                    .is_none_or(|id| id.parse::<u32>().is_err())
+            });
+        if !valid {
+            return Err(format!(
+                "{name:?} can't be used as a name. Use 2 to 20 letters, digits, '-' or '_'."
+            ));
+        }
+        if self.find_user(name).is_some_and(|user| user != id) {
+            return Err(format!("{name} is already in the tavern."));
+        }
+        if self.accounts.get(name).is_some() {
+            return Err(format!("There is already an account called {name}."));
+        }
+        Ok(())
+    }
+
+    /// Checks the password of an account away from the event loop. The patron is logged in
+    /// once the check comes back, in `finish_login`.
+    fn login(&mut self, id: UserId, name: String, password: Password) -> Result<(), String> {
+        self.start_password_check(id)?;
+        let account = self.accounts.get(&name).cloned();
+        let event_tx = self.event_tx.clone();
+        tokio::task::spawn_blocking(move || {
+            let valid = account
+                .as_ref()
+                .is_some_and(|account| account.has_password(&password));
+            let rehashed = account
+                .as_ref()
+                .filter(|account| valid && account.has_legacy_password())
+                .map(|_| PasswordHash::new(&password));
+            let _ = event_tx.blocking_send(Event::LoginChecked {
+                from: id,
+                name: account.map_or(name, |account| account.name),
+                valid,
+                rehashed,
+            });
+        });
+        Ok(())
+    }
+
+    /// Logs a patron into their account, telling them about new mail.
+    fn finish_login(
+        &mut self,
+        id: UserId,
+        name: &str,
+        valid: bool,
+        rehashed: Option<PasswordHash>,
+    ) -> String {
+        let Some(client) = self.clients.get_mut(&id) else {
+            return String::new();
+        };
+        client.checking_password = false;
+        if !valid {
+            client.failed_logins += 1;
+            return "Wrong name or password.".to_owned();
+        }
+        client.failed_logins = 0;
+        let Some(account) = self.accounts.get(name) else {
+            return "Wrong name or password.".to_owned();
+        };
+        let (name, unread) = (account.name.clone(), account.unread());
+        if self.online_account_holder(&name).is_some() {
+            return format!("{name} is already in the tavern.");
+        }
+        if let Some(password) = rehashed
+            && let Some(account) = self.accounts.get_mut(&name)
+        {
+            account.set_password(password);
+            self.save_accounts();
+        }
+
+        self.log_in(id, &name);
+        match unread {
+            0 => format!("Welcome back, {name}!"),
+            1 => format!("Welcome back, {name}! You have 1 new mail. Use /mail list to read it."),
+            n => format!(
+                "Welcome back, {name}! You have {n} new mails. Use /mail list to read them."
+            ),
+        }
+    }
+
+    fn log_in(&mut self, id: UserId, name: &str) {
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.account = Some(name.to_owned());
+        }
+        self.account_ids.insert(id, name.to_owned());
+    }
+
+    /// The patron logged into an account, if they're in the tavern.
+    fn online_account_holder(&self, name: &str) -> Option<UserId> {
+        self.clients
+            .iter()
+            .find(|(_, client)| {
+                client
+                    .account
+                    .as_ref()
+                    .is_some_and(|account| account.eq_ignore_ascii_case(name))
+            })
+            .map(|(id, _)| *id)
+    }
+
+    fn save_accounts(&self) {
+        if let Err(e) = self.accounts.save() {
+            println!("Failed to save accounts: {e}");
+        }
+    }
+
+    /// Answers a mail command. Anyone can send mail, but only registered patrons receive it.
+    async fn handle_mail_command(&mut self, from: UserId, command: MailCommand) -> String {
+        let name = self
+            .clients
+            .get(&from)
+            .and_then(|client| client.account.clone());
+        let account = name.and_then(|name| self.accounts.get_mut(&name));
+        let reply = match (command, account) {
+            (MailCommand::Send { to, content }, _) => {
+                return match self.online_account_holder(&to) {
+                    Some(holder) => {
+                        self.broadcast_message(Message::new(
+                            Some(ChatTarget::User(from)),
+                            ChatTarget::User(holder),
+                            &content,
+                            Some(MessageTone::WHISPERED),
+                        ))
+                        .await;
+                        format!(
+                            "To {}: {content}",
+                            self.display_name(ChatTarget::User(holder))
+                        )
+                    }
+                    None => {
+                        self.leave_mail(Some(ChatTarget::User(from)), &to, &content)
+                            .await
+                    }
+                };
+            }
+            (_, None) => {
+                return "Only registered patrons have a mailbox. \
+                        Use /register <name> <password> or /login <name> <password>."
+                    .to_owned();
+            }
+            (MailCommand::List, Some(account)) if account.mailbox.is_empty() => {
+                "Your mailbox is empty.".to_owned()
+            }
+            (MailCommand::List, Some(account)) => {
+                let mut lines = vec![format!(
+                    "Your mailbox ({} of {}, {} new):",
+                    account.mailbox.len(),
+                    self.config.mailbox_len,
+                    account.unread()
+                )];
+                for (n, mail) in account.mailbox.iter().enumerate() {
+                    let preview = mail.content.chars().take(40).collect::<String>();
+                    lines.push(format!(
+                        "{}. {}{} from {}: {preview}{}",
+                        n + 1,
+                        if mail.read { "" } else { "[new] " },
+                        DateTime::<Local>::from(mail.timestamp).format("%Y-%m-%d %H:%M"),
+                        mail.from,
+                        if preview.len() < mail.content.len() {
+                            "…"
+                        } else {
+                            ""
+                        }
+                    ));
+                }
+                return lines.join("\n");
+            }
+            (MailCommand::Read(n), Some(account)) => {
+                match account.mailbox.get_mut(n.wrapping_sub(1)) {
+                    Some(mail) => {
+                        mail.read = true;
+                        format!(
+                            "Mail {n} from {}, {}:\n{}",
+                            mail.from,
+                            DateTime::<Local>::from(mail.timestamp).format("%Y-%m-%d %H:%M"),
+                            mail.content
+                        )
+                    }
+                    None => return format!("There is no mail {n}. Try /mail list."),
+                }
+            }
+            (MailCommand::Delete(n), Some(account)) if (1..=account.mailbox.len()).contains(&n) => {
+                account.mailbox.remove(n - 1);
+                format!("Mail {n} deleted.")
+            }
+            (MailCommand::Delete(n), Some(_)) => {
+                return format!("There is no mail {n}. Try /mail list.");
+            }
+        };
+        self.save_accounts();
+        reply
+    }
+
+    /// Leaves a message in an account's mailbox. Returns the reply for the sender.
+    async fn leave_mail(&mut self, from: Option<ChatTarget>, to: &str, content: &str) -> String {
+        let sender = from
+            .map(|from| self.display_name(from))
+            .unwrap_or_else(|| "The tavern".to_owned());
+        let mailbox_len = self.config.mailbox_len;
+        let Some(account) = self.accounts.get_mut(to) else {
+            return format!("There is no patron or account called {to:?}.");
+        };
+        let name = account.name.clone();
+        if account.mailbox.len() >= mailbox_len {
+            return format!("{name}'s mailbox is full.");
+        }
+        account.mailbox.push(Mail {
+            from: sender.clone(),
+            content: content.to_owned(),
+            timestamp: SystemTime::now(),
+            read: false,
+        });
+        let n = account.mailbox.len();
+        self.save_accounts();
+
+        match self.online_account_holder(&name) {
+            // Direct messages to a patron's previous visit still reach them.
+            Some(holder) => {
+                self.notify_client(SystemNotification {
+                    to: holder,
+                    content: format!("You've got mail from {sender}. Use /mail read {n}."),
+                })
+                .await;
+                format!("Your message was left in {name}'s mailbox.")
+            }
+            None => format!("{name} isn't in the tavern. Your message was left in their mailbox."),
+        }
+    }
+
+    /// Lists every patron of the tavern, with the room they're in and how long they've been idle.
+    fn list_patrons(&self) -> String {
+        let mut patrons = self
+            .clients
+            .iter()
+            .filter(|(_, client)| !matches!(client.protocol, ClientProtocol::EventStream { .. }))
+            .map(|(id, client)| {
+                let room = match self.room_of(*id) {
+                    Some(room) => format!("in {}", self.display_name(ChatTarget::Room(room))),
+                    None => "not in any room".to_owned(),
+                };
+                let status = self.status_of(*id).unwrap_or_else(|| {
+                    format!(
+                        "active {} ago",
+                        format_duration(client.last_active.elapsed())
+                    )
+                });
+                let line = format!(
+                    "  {} ({id}): {room}, {status}",
+                    self.display_name(ChatTarget::User(*id)),
+                );
+                (*id, line)
+            })
+            .collect::<Vec<_>>();
+        patrons.sort();
+        let header = format!(
+            "{} patron{} in the tavern:",
+            patrons.len(),
+            if patrons.len() == 1 { "" } else { "s" }
+        );
+        std::iter::once(header)
+            .chain(patrons.into_iter().map(|(_, line)| line))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Describes a patron who is away or idle. Returns None if they're around.
+    fn status_of(&self, id: UserId) -> Option<String> {
+        let client = self.clients.get(&id)?;
+        match client.context.away.as_deref() {
+            Some("") => Some("away".to_owned()),
+            Some(reason) => Some(format!("away ({reason})")),
+            None => {
+                let idle = client.last_active.elapsed();
+                (idle >= self.config.idle_after)
+                    .then(|| format!("idle for {}", format_duration(idle)))
+            }
+        }
+    }
+
+    /// Describes a patron: when they walked in, their tone and where they are.
+    fn describe_patron(&self, id: UserId) -> String {
+        let Some(client) = self.clients.get(&id) else {
+            return format!("There is no patron called {id}.");
+        };
+        let connected = client.connected_at.elapsed().unwrap_or_default();
+        let room = match self.room_of(id) {
+            Some(room) => self.display_name(ChatTarget::Room(room)),
+            None => "none".to_owned(),
+        };
+        [
+            format!("{} ({id})", self.display_name(ChatTarget::User(id))),
+            format!(
+                "  Connected: {} ({} ago), via {}",
+                DateTime::<Local>::from(client.connected_at).format("%Y-%m-%d %H:%M:%S"),
+                format_duration(connected),
+                protocol_name(&client.protocol)
+            ),
+            format!("  Tone: {}", client.context.tone),
+            format!("  Room: {room}"),
+            format!("  Idle: {}", format_duration(client.last_active.elapsed())),
+            format!(
+                "  Status: {}",
+                self.status_of(id).unwrap_or_else(|| "here".to_owned())
+            ),
+        ]
+        .join("\n")
+    }
+
+    /// Answers a booth command. Invites and kicks apply to the booth currently talked to.
+    async fn handle_booth_command(&mut self, from: UserId, command: BoothCommand) -> String {
+        match command {
+            BoothCommand::Create { name, password } => {
+                match self.create_booth(from, &name, password.as_deref()).await {
+                    Ok(booth) => format!(
+                        "You create the booth {}. Invite others with /booth invite <user>.",
+                        self.display_name(ChatTarget::Booth(booth))
+                    ),
+                    Err(e) => e,
+                }
+            }
+            BoothCommand::Join { name, password } => match self.find_booth(&name) {
+                Some(booth) => match self.join_booth(from, booth, password.as_deref()).await {
+                    Ok(()) => format!(
+                        "You sit down in the booth {}.",
+                        self.display_name(ChatTarget::Booth(booth))
+                    ),
+                    Err(e) => e,
+                },
+                None => format!("There is no booth called {name:?}."),
+            },
+            BoothCommand::Invite(name) => {
+                let booth = match self.owned_booth(from) {
+                    Ok(booth) => booth,
+                    Err(e) => return e,
+                };
+                let Some(guest) = self.find_user(&name) else {
+                    return format!("There is no patron called {name:?}.");
+                };
+                let guest_name = self.display_name(ChatTarget::User(guest));
+                let Some(invited) = self.booths.get_mut(&booth) else {
+                    return format!("There is no booth called {name:?}.");
+                };
+                if invited.members.contains(&guest) {
+                    return format!("{guest_name} is already in {}.", invited.name());
+                }
+                invited.invited.insert(guest);
+                let (booth_name, key) = (invited.name().to_owned(), invited.key());
+                let host = self.display_name(ChatTarget::User(from));
+                self.notify_client(SystemNotification {
+                    to: guest,
+                    content: format!(
+                        "{host} invites you to the booth {booth_name}. Use /booth join {key} to sit down."
+                    ),
+                })
+                .await;
+                format!("You invite {guest_name} to {booth_name}.")
+            }
+            BoothCommand::Kick(name) => {
+                let booth = match self.owned_booth(from) {
+                    Ok(booth) => booth,
+                    Err(e) => return e,
+                };
+                let Some(guest) = self.find_user(&name) else {
+                    return format!("There is no patron called {name:?}.");
+                };
+                if guest == from {
+                    return "You can't kick yourself out. Use /booth leave instead.".to_owned();
+                }
+                let guest_name = self.display_name(ChatTarget::User(guest));
+                let booth_name = self.display_name(ChatTarget::Booth(booth));
+                if !self.booths.get(&booth).is_some_and(|booth| {
+                    booth.members.contains(&guest) || booth.invited.contains(&guest)
+                }) {
+                    return format!("{guest_name} isn't in {booth_name}.");
+                }
+
+                let prefix = irc::user_prefix(&self.display_name(ChatTarget::User(from)));
+                let channel = self.irc_name(ChatTarget::Booth(booth));
+                self.irc_members_announce(
+                    ChatTarget::Booth(booth),
+                    irc::format_line(&prefix, "KICK", &[&channel, &guest_name, "Kicked out"]),
+                )
+                .await;
+                if let Some(kicked) = self.booths.get_mut(&booth) {
+                    kicked.remove_member(guest);
+                }
+                self.log_moderation(ModerationAction::new(
+                    Some(ChatTarget::User(from)),
+                    "kick",
+                    ChatTarget::User(guest),
+                ));
+                self.retarget_if_unreachable(guest);
+                self.notify_client(SystemNotification {
+                    to: guest,
+                    content: format!("You've been kicked out of the booth {booth_name}."),
+                })
+                .await;
+                format!("You kick {guest_name} out of {booth_name}.")
+            }
+            BoothCommand::Leave(name) => {
+                let current = match self.clients.get(&from).map(|c| c.context.current_target) {
+                    Some(ChatTarget::Booth(booth)) => Some(booth),
+                    _ => None,
+                };
+                let mine = self
+                    .booths
+                    .iter()
+                    .filter(|(_, booth)| {
+                        booth.members.contains(&from) || booth.invited.contains(&from)
+                    })
+                    .map(|(booth, _)| *booth)
+                    .collect::<Vec<_>>();
+                let booth = match (name, current, mine.as_slice()) {
+                    (Some(name), _, _) => match self.find_booth(&name) {
+                        Some(booth) if mine.contains(&booth) => booth,
+                        Some(_) => return format!("You aren't in the booth {name}."),
+                        None => return format!("There is no booth called {name:?}."),
+                    },
+                    (None, Some(booth), _) => booth,
+                    (None, None, [booth]) => *booth,
+                    (None, None, []) => return "You aren't in any booth.".to_owned(),
+                    (None, None, _) => {
+                        return "You're in several booths. Use /booth leave <name>.".to_owned();
+                    }
+                };
+                let booth_name = self.display_name(ChatTarget::Booth(booth));
+                self.leave_booth(from, booth).await;
+                format!("You leave the booth {booth_name}.")
+            }
+            BoothCommand::List => {
+                let mut lines = vec!["Your booths:".to_owned()];
+                for booth in self.booths.values() {
+                    let invited = booth.invited.contains(&from);
+                    if !booth.members.contains(&from) && !invited {
+                        continue;
+                    }
+                    lines.push(format!(
+                        "  {} ({}): {} member{}, owned by {}{}",
+                        booth.name(),
+                        booth.key(),
+                        booth.members.len(),
+                        if booth.members.len() == 1 { "" } else { "s" },
+                        self.display_name(ChatTarget::User(booth.owner())),
+                        if invited { " (invited)" } else { "" }
+                    ));
+                }
+                if lines.len() == 1 {
+                    "You aren't in any booth. Create one with /booth create <name> [password]."
+                        .to_owned()
+                } else {
+                    lines.join("\n")
+                }
+            }
+        }
+    }
+
+    /// Finds a booth by its name, or by its ID.
+    fn find_booth(&self, name: &str) -> Option<BoothId> {
+        let id = name.trim().parse::<u32>().ok().map(BoothId);
+        self.booths
+            .iter()
+            .find(|(booth_id, booth)| Some(**booth_id) == id || booth.is_called(name))
+            .map(|(id, _)| *id)
+    }
+
+    /// Finds a connected patron by their ID or name.
+    fn find_user(&self, name: &str) -> Option<UserId> {
+        let id = name.trim().parse::<u32>().ok().map(UserId);
+        self.clients
+            .iter()
+            .filter(|(_, client)| !matches!(client.protocol, ClientProtocol::EventStream { .. }))
+            .map(|(id, _)| *id)
+            .find(|user| {
+                Some(*user) == id
+                    || self
+                        .display_name(ChatTarget::User(*user))
+                        .eq_ignore_ascii_case(name.trim())
+            })
+    }
+
+    /// The booth a patron is talking to, as long as they own it.
+    fn owned_booth(&self, id: UserId) -> Result<BoothId, String> {
+        let target = self
+            .clients
+            .get(&id)
+            .map(|client| client.context.current_target);
+        let Some(ChatTarget::Booth(booth)) = target else {
+            return Err("Talk to your booth first, with /booth join <name>.".to_owned());
+        };
+        match self.booths.get(&booth) {
+            Some(owned) if owned.owner() == id => Ok(booth),
+            Some(owned) => Err(format!("Only the owner of {} can do that.", owned.name())),
+            None => Err("Your booth is gone.".to_owned()),
+        }
+    }
+
+    /// Creates a booth owned by a patron, who starts talking to it.
+    async fn create_booth(
+        &mut self,
+        owner: UserId,
+        name: &str,
+        password: Option<&str>,
+    ) -> Result<BoothId, String> {
+        if room_key(name).is_empty() {
+            return Err(
+                "Invalid booth name. please use /booth create <name> [password]".to_owned(),
+            );
+        }
+        if self.find_booth(name).is_some() {
+            return Err(format!("There's already a booth called {name:?}."));
+        }
+        let id = BoothId(self.next_booth_id);
+        self.next_booth_id += 1;
+        self.booths.insert(id, Booth::new(name, owner, password));
+        if let Some(client) = self.clients.get_mut(&owner) {
+            client.context.current_target = ChatTarget::Booth(id);
+        }
+
+        let prefix = irc::user_prefix(&self.display_name(ChatTarget::User(owner)));
+        let channel = self.irc_name(ChatTarget::Booth(id));
+        self.irc_members_announce(
+            ChatTarget::Booth(id),
+            irc::format_line(&prefix, "JOIN", &[&channel]),
+        )
+        .await;
+        Ok(id)
+    }
+
+    /// Sits a patron down in a booth if they are allowed in, and talks to it from now on.
+    async fn join_booth(
+        &mut self,
+        id: UserId,
+        booth: BoothId,
+        password: Option<&str>,
+    ) -> Result<(), String> {
+        let Some(joined) = self.booths.get_mut(&booth) else {
+            return Err("There is no such booth.".to_owned());
+        };
+        if !joined.can_enter(id, password) {
+            return Err(format!(
+                "The booth {} is private. You need an invitation, or its password.",
+                joined.name()
+            ));
+        }
+        let is_new = joined.members.insert(id);
+        joined.invited.remove(&id);
+        if let Some(client) = self.clients.get_mut(&id) {
+            client.context.current_target = ChatTarget::Booth(booth);
+        }
+
+        if is_new {
+            let name = self.display_name(ChatTarget::User(id));
+            let channel = self.irc_name(ChatTarget::Booth(booth));
+            self.irc_members_announce(
+                ChatTarget::Booth(booth),
+                irc::format_line(&irc::user_prefix(&name), "JOIN", &[&channel]),
+            )
+            .await;
+            let content = format!(
+                "{name} joins the booth {}.",
+                self.display_name(ChatTarget::Booth(booth))
+            );
+            self.notify_members(ChatTarget::Booth(booth), id, &content)
+                .await;
+        }
+        Ok(())
+    }
+
+    /// Takes a patron out of a booth, handing it over if they owned it.
+    /// The booth is closed once its last member leaves.
+    async fn leave_booth(&mut self, id: UserId, booth: BoothId) {
+        let name = self.display_name(ChatTarget::User(id));
+        let channel = self.irc_name(ChatTarget::Booth(booth));
+        self.irc_members_announce(
+            ChatTarget::Booth(booth),
+            irc::format_line(&irc::user_prefix(&name), "PART", &[&channel]),
+        )
+        .await;
+
+        let Some(left) = self.booths.get_mut(&booth) else {
+            return;
+        };
+        let was_member = left.members.contains(&id);
+        let new_owner = left.remove_member(id);
+        let booth_name = left.name().to_owned();
+        if left.members.is_empty() {
+            self.booths.remove(&booth);
+        }
+        self.retarget_if_unreachable(id);
+
+        if was_member {
+            let content = format!("{name} leaves the booth {booth_name}.");
+            self.notify_members(ChatTarget::Booth(booth), id, &content)
+                .await;
+        }
+        if let Some(owner) = new_owner {
+            self.notify_client(SystemNotification {
+                to: owner,
+                content: format!("You now own the booth {booth_name}."),
+            })
+            .await;
+        }
+    }
+
+    /// Notifies the members of a room or booth, except IRC clients who see JOIN and PART lines.
+    async fn notify_members(&mut self, target: ChatTarget, except: UserId, content: &str) {
+        let members = self
+            .members_of(target)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|id| {
+                *id != except
+                    && !matches!(
+                        self.clients.get(id).map(|client| &client.protocol),
+                        Some(ClientProtocol::Irc(_))
+                    )
+            })
+            .collect::<Vec<_>>();
+        for id in members {
+            self.notify_client(SystemNotification {
+                to: id,
+                content: content.to_owned(),
+            })
+            .await;
+        }
+    }
+
+    /// Handles a raw line sent by an IRC client, translating it into tavern events.
+    async fn handle_irc_message(&mut self, from: UserId, message_raw: String) {
+        let Some(command) = IrcCommand::parse(&message_raw) else {
+            return;
+        };
+        let nick = self
+            .clients
+            .get(&from)
+            .and_then(|client| client.context.nickname.clone())
+            .unwrap_or_else(|| "*".to_owned());
+        let registered = matches!(
+            self.clients.get(&from).map(|client| &client.protocol),
+            Some(ClientProtocol::Irc(session)) if session.registered
+        );
+
+        match (command.command.as_str(), registered) {
+            ("PING", _) => {
+                let token = command.param(0).unwrap_or(IRC_SERVER_NAME);
+                self.send_to_client(
+                    from,
+                    irc::format_line(IRC_SERVER_NAME, "PONG", &[IRC_SERVER_NAME, token]),
+                )
+                .await;
+            }
+            ("PONG", _) => {}
+            ("QUIT", _) => {
+                let _ = self
+                    .event_tx
+                    .send(Event::DisconnectClient { id: from })
+                    .await;
+            }
+            ("NICK", _) => self.set_irc_nick(from, &nick, command.param(0)).await,
+            ("USER", false) => {
+                let Some(username) = command.param(0) else {
+                    let output = irc::numeric("461", &nick, &["USER", "Not enough parameters"]);
+                    self.send_to_client(from, output).await;
+                    return;
+                };
+                if let Some(session) = self.irc_session_mut(from) {
+                    session.username = Some(username.to_owned());
+                }
+                self.try_register_irc(from).await;
+            }
+            ("USER", true) => {
+                let output = irc::numeric("462", &nick, &["You may not reregister"]);
+                self.send_to_client(from, output).await;
+            }
+            (_, false) => {
+                let output = irc::numeric("451", &nick, &["You have not registered"]);
+                self.send_to_client(from, output).await;
+            }
+            ("JOIN", true) => {
+                let keys = command
+                    .param(1)
+                    .unwrap_or("")
+                    .split(',')
+                    .collect::<Vec<_>>();
+                for (i, channel) in command.param(0).unwrap_or("").split(',').enumerate() {
+                    // Booths are local channels, created by the first patron joining them.
+                    if channel.starts_with('&') {
+                        let password = keys.get(i).copied().filter(|key| !key.is_empty());
+                        let joined = match self.find_booth(channel) {
+                            Some(booth) if self.can_target(from, ChatTarget::Booth(booth)) => {
+                                continue;
+                            }
+                            Some(booth) => self
+                                .join_booth(from, booth, password)
+                                .await
+                                .map(|()| booth)
+                                .map_err(|_| {
+                                    irc::numeric("475", &nick, &[channel, "Cannot join channel"])
+                                }),
+                            None => self
+                                .create_booth(from, channel.trim_start_matches('&'), password)
+                                .await
+                                .map_err(|_| {
+                                    irc::numeric("403", &nick, &[channel, "No such channel"])
+                                }),
+                        };
+                        match joined {
+                            Ok(booth) => {
+                                self.send_irc_topic(from, &nick, ChatTarget::Booth(booth))
+                                    .await
+                            }
+                            Err(output) => self.send_to_client(from, output).await,
+                        }
+                        continue;
+                    }
+                    // Rooms are channels of their own, but patrons sit in one room at a time.
+                    if let Some(ChatTarget::Room(room)) = self.resolve_irc_name(channel) {
+                        if self.room_of(from) != Some(room) {
+                            self.join_room(from, room).await;
+                            self.send_irc_topic(from, &nick, ChatTarget::Room(room))
+                                .await;
+                        }
+                        continue;
+                    }
+                    if !channel.eq_ignore_ascii_case(IRC_CHANNEL) {
+                        let output = irc::numeric("403", &nick, &[channel, "No such channel"]);
+                        self.send_to_client(from, output).await;
+                        continue;
+                    }
+                    if let Some(session) = self.irc_session_mut(from)
+                        && !session.joined
+                    {
+                        session.joined = true;
+                        let line =
+                            irc::format_line(&irc::user_prefix(&nick), "JOIN", &[IRC_CHANNEL]);
+                        self.irc_announce(None, line).await;
+                        let output = irc::numeric("332", &nick, &[IRC_CHANNEL, irc::IRC_TOPIC]);
+                        self.send_to_client(from, output).await;
+                        self.send_irc_names(from, &nick, ChatTarget::Global).await;
+                        self.replay_history(from, self.config.replay_len, true)
+                            .await;
+                    }
+                }
+            }
+            ("PART", true) => {
+                let channel = command
+                    .param(0)
+                    .and_then(|name| self.resolve_irc_name(name))
+                    .filter(|target| matches!(target, ChatTarget::Room(_) | ChatTarget::Booth(_)));
+                if let Some(channel) = channel {
+                    if self.can_target(from, channel) {
+                        match channel {
+                            ChatTarget::Booth(booth) => self.leave_booth(from, booth).await,
+                            _ => {
+                                self.leave_room(from).await;
+                            }
+                        }
+                    } else {
+                        let channel = self.irc_name(channel);
+                        let output =
+                            irc::numeric("442", &nick, &[&channel, "You're not on that channel"]);
+                        self.send_to_client(from, output).await;
+                    }
+                } else if self
+                    .irc_session_mut(from)
+                    .is_some_and(|session| session.joined)
+                {
+                    let line = irc::format_line(&irc::user_prefix(&nick), "PART", &[IRC_CHANNEL]);
+                    self.irc_announce(None, line).await;
+                    if let Some(session) = self.irc_session_mut(from) {
+                        session.joined = false;
+                    }
+                } else {
+                    let output =
+                        irc::numeric("442", &nick, &[IRC_CHANNEL, "You're not on that channel"]);
+                    self.send_to_client(from, output).await;
+                }
+            }
+            ("AWAY", true) => {
+                let reason = command.param(0).unwrap_or_default().to_owned();
+                let output = match self.clients.get_mut(&from) {
+                    Some(client) if reason.is_empty() => {
+                        client.context.away = None;
+                        irc::numeric("305", &nick, &["You are no longer marked as being away"])
+                    }
+                    Some(client) => {
+                        client.context.away = Some(reason);
+                        irc::numeric("306", &nick, &["You have been marked as being away"])
+                    }
+                    None => return,
+                };
+                self.send_to_client(from, output).await;
+            }
+            ("WHO", true) => {
+                let mask = command.param(0).unwrap_or("*").to_owned();
+                let channel = match self.resolve_irc_name(&mask) {
+                    Some(room @ ChatTarget::Room(_)) => room,
+                    Some(booth @ ChatTarget::Booth(_)) if self.can_target(from, booth) => booth,
+                    _ => ChatTarget::Global,
+                };
+                let members = match channel {
+                    ChatTarget::Global => self
+                        .clients
+                        .iter()
+                        .filter(|(_, client)| in_irc_channel(client))
+                        .map(|(id, _)| *id)
+                        .collect(),
+                    _ => self.members_of(channel).unwrap_or_default(),
+                };
+                let channel_name = self.irc_name(channel);
+                let mut output = members
+                    .into_iter()
+                    .map(|id| {
+                        let name = self.display_name(ChatTarget::User(id));
+                        irc::numeric(
+                            "352",
+                            &nick,
+                            &[
+                                &channel_name,
+                                &name,
+                                IRC_SERVER_NAME,
+                                IRC_SERVER_NAME,
+                                &name,
+                                "H",
+                                &format!("0 {name}"),
+                            ],
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                output.push(irc::numeric("315", &nick, &[&mask, "End of /WHO list"]));
+                self.send_to_client(from, output.concat()).await;
+            }
+            ("WHOIS", true) => {
+                // The target may follow a server name, as in `WHOIS server nick`.
+                let target = command
+                    .params
+                    .last()
+                    .map(String::as_str)
+                    .unwrap_or_default()
+                    .to_owned();
+                let mut output = vec![];
+                match self.find_user(&target) {
+                    Some(id) => {
+                        let name = self.display_name(ChatTarget::User(id));
+                        output.push(irc::numeric(
+                            "311",
+                            &nick,
+                            &[&name, &name, IRC_SERVER_NAME, "*", &name],
+                        ));
+                        if let Some(client) = self.clients.get(&id) {
+                            let signon = client
+                                .connected_at
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap_or_default();
+                            output.push(irc::numeric(
+                                "317",
+                                &nick,
+                                &[
+                                    &name,
+                                    &client.last_active.elapsed().as_secs().to_string(),
+                                    &signon.as_secs().to_string(),
+                                    "seconds idle, signon time",
+                                ],
+                            ));
+                        }
+                    }
+                    None => output.push(irc::numeric("401", &nick, &[&target, "No such nick"])),
+                }
+                output.push(irc::numeric("318", &nick, &[&target, "End of /WHOIS list"]));
+                self.send_to_client(from, output.concat()).await;
+            }
+            ("NAMES", true) => {
+                let channel = match command
+                    .param(0)
+                    .and_then(|name| self.resolve_irc_name(name))
+                {
+                    Some(room @ ChatTarget::Room(_)) => room,
+                    // Who sits in a booth is only known to its members.
+                    Some(booth @ ChatTarget::Booth(_)) if self.can_target(from, booth) => booth,
+                    _ => ChatTarget::Global,
+                };
+                self.send_irc_names(from, &nick, channel).await
+            }
+            ("PRIVMSG" | "NOTICE", true) => {
+                // Notices must never trigger automatic error replies.
+                let is_notice = command.command == "NOTICE";
+                let error = match (command.param(0), command.param(1)) {
+                    (None, _) => Some(irc::numeric("411", &nick, &["No recipient given"])),
+                    (Some(_), None | Some("")) => {
+                        Some(irc::numeric("412", &nick, &["No text to send"]))
+                    }
+                    (Some(target), Some(text)) => match self.resolve_irc_name(target) {
+                        Some(ChatTarget::Global)
+                            if !self.clients.get(&from).is_some_and(in_irc_channel) =>
+                        {
+                            Some(irc::numeric(
+                                "404",
+                                &nick,
+                                &[target, "Cannot send to channel"],
+                            ))
+                        }
+                        Some(to @ (ChatTarget::Room(_) | ChatTarget::Booth(_)))
+                            if !self.can_target(from, to) =>
+                        {
+                            Some(irc::numeric(
+                                "404",
+                                &nick,
+                                &[target, "Cannot send to channel"],
+                            ))
+                        }
+                        // NPCs sitting in other rooms can't hear you.
+                        Some(ChatTarget::Npc(npc))
+                            if !self.can_target(from, ChatTarget::Npc(npc)) =>
+                        {
+                            Some(irc::numeric(
+                                "401",
+                                &nick,
+                                &[target, "No such nick/channel"],
+                            ))
+                        }
+                        Some(to) => {
+                            let tone = self
+                                .clients
+                                .get(&from)
+                                .map(|client| client.context.tone.clone())
+                                .unwrap_or_default();
+                            // CTCP ACTIONs, sent by `/me`, are actions in the tavern too.
+                            let action = text
+                                .strip_prefix("\x01ACTION ")
+                                .map(|action| action.trim_end_matches('\x01'));
+                            let mut message = Message::new(
+                                Some(ChatTarget::User(from)),
+                                to,
+                                action.unwrap_or(text),
+                                Some(tone),
+                            );
+                            if action.is_some() {
+                                message.kind = MessageKind::Action;
+                            }
+                            let _ = self
+                                .event_tx
+                                .send(Event::BroadcastMessage { message })
+                                .await;
+                            None
+                        }
+                        None => Some(irc::numeric(
+                            "401",
+                            &nick,
+                            &[target, "No such nick/channel"],
+                        )),
+                    },
+                };
+                if let Some(output) = error
+                    && !is_notice
+                {
+                    self.send_to_client(from, output).await;
+                }
+            }
+            (unknown, true) => {
+                let output = irc::numeric("421", &nick, &[unknown, "Unknown command"]);
+                self.send_to_client(from, output).await;
+            }
+        }
+    }
+
+    /// Changes an IRC client's nickname, if it's valid and not already taken.
+    async fn set_irc_nick(&mut self, from: UserId, current: &str, nick: Option<&str>) {
+        let error = match nick {
+            None => Some(irc::numeric("431", current, &["No nickname given"])),
+            Some(nick) if !irc::is_valid_nick(nick) => {
+                Some(irc::numeric("432", current, &[nick, "Erroneous nickname"]))
+            }
+            Some(nick)
+                if self
+                    .resolve_irc_name(nick)
+                    .is_some_and(|target| target != ChatTarget::User(from)) =>
+            {
+                Some(irc::numeric(
+                    "433",
+                    current,
+                    &[nick, "Nickname is already in use"],
+                ))
+            }
+            Some(_) => None,
+        };
+        if let Some(output) = error {
+            self.send_to_client(from, output).await;
+            return;
+        }
+
+        let Some(client) = self.clients.get_mut(&from) else {
+            return;
+        };
+        let nick = nick.unwrap_or_default().to_owned();
+        client.context.nickname = Some(nick.clone());
+        match &client.protocol {
+            ClientProtocol::Irc(session) if session.registered => {
+                let line = irc::format_line(&irc::user_prefix(current), "NICK", &[&nick]);
+                if !session.joined {
+                    self.send_to_client(from, line.clone()).await;
+                }
+                self.irc_announce(None, line).await;
+            }
+            _ => self.try_register_irc(from).await,
+        }
+    }
+
+    /// Completes IRC registration once both NICK and USER have been received.
+    async fn try_register_irc(&mut self, from: UserId) {
+        let Some(Client {
+            context,
+            protocol: ClientProtocol::Irc(session),
+            ..
+        }) = self.clients.get_mut(&from)
+        else {
+            return;
+        };
+        let Some(nick) = context.nickname.clone() else {
+            return;
+        };
+        if session.registered || session.username.is_none() {
+            return;
+        }
+        session.registered = true;
+
+        let welcome = format!("Welcome to the Tavern, {}", irc::user_prefix(&nick));
+        let output = [
+            irc::numeric("001", &nick, &[&welcome]),
+            irc::numeric("002", &nick, &["Your host is the Rust Tavern"]),
+            irc::numeric(
+                "422",
+                &nick,
+                &[&format!("Type /join {IRC_CHANNEL} to enter the tavern")],
+            ),
+        ]
+        .concat();
+        self.send_to_client(from, output).await;
+        self.announce_presence(from, "walks into the tavern").await;
+    }
+
+    /// Sends the topic and names of a room or booth's channel, to a client who just joined it.
+    async fn send_irc_topic(&mut self, to: UserId, nick: &str, channel: ChatTarget) {
+        let topic = self.display_name(channel);
+        let output = irc::numeric("332", nick, &[&self.irc_name(channel), &topic]);
+        self.send_to_client(to, output).await;
+        self.send_irc_names(to, nick, channel).await;
+    }
+
+    /// Sends the list of everyone in the tavern channel, or in a room or booth's channel,
+    /// NPCs included.
+    async fn send_irc_names(&mut self, to: UserId, nick: &str, channel: ChatTarget) {
+        let targets = match channel {
+            ChatTarget::Room(_) | ChatTarget::Booth(_) => {
+                let npcs = match channel {
+                    ChatTarget::Room(room) => self
+                        .rooms
+                        .get(&room)
+                        .map(|room| room.npcs.iter().copied().collect())
+                        .unwrap_or_default(),
+                    _ => vec![],
+                };
+                self.members_of(channel)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(ChatTarget::User)
+                    .chain(npcs.into_iter().map(ChatTarget::Npc))
+                    .collect::<Vec<_>>()
+            }
+            _ => self
+                .clients
+                .iter()
+                .filter(|(_, client)| in_irc_channel(client))
+                .map(|(id, _)| ChatTarget::User(*id))
+                .chain(self.npcs.keys().map(|id| ChatTarget::Npc(*id)))
+                .collect(),
+        };
+        let names = targets
+            .into_iter()
+            .filter(|target| match target {
+                ChatTarget::Npc(id) => self.npcs.get(id).is_some_and(|npc| !npc.is_disabled()),
+                _ => true,
+            })
+            .map(|target| self.display_name(target))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let channel = self.irc_name(channel);
+        let output = [
+            irc::numeric("353", nick, &["=", &channel, &names]),
+            irc::numeric("366", nick, &[&channel, "End of /NAMES list"]),
+        ]
+        .concat();
+        self.send_to_client(to, output).await;
+    }
+
+    /// Sends a raw line to every IRC client in the tavern channel.
+    async fn irc_announce(&mut self, except: Option<UserId>, line: String) {
+        let recipients = self
+            .clients
+            .iter()
+            .filter(|(id, client)| {
+                Some(**id) != except
+                    && matches!(&client.protocol, ClientProtocol::Irc(_))
+                    && in_irc_channel(client)
+            })
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in recipients {
+            self.send_to_client(id, line.clone()).await;
+        }
+    }
+
+    /// Sends a raw line to every IRC client sitting in a room or booth.
+    async fn irc_members_announce(&mut self, target: ChatTarget, line: String) {
+        let recipients = self
+            .members_of(target)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|id| {
+                self.clients.get(id).is_some_and(|client| {
+                    matches!(&client.protocol, ClientProtocol::Irc(session) if session.registered)
+                })
+            })
+            .collect::<Vec<_>>();
+        for id in recipients {
+            self.send_to_client(id, line.clone()).await;
+        }
+    }
+
+    fn irc_session_mut(&mut self, id: UserId) -> Option<&mut irc::IrcSession> {
+        match self.clients.get_mut(&id).map(|client| &mut client.protocol) {
+            Some(ClientProtocol::Irc(session)) => Some(session),
+            _ => None,
+        }
+    }
+
+    /// The name a tavern patron, NPC or room is known by, on IRC and in the HTTP API.
+    fn display_name(&self, target: ChatTarget) -> String {
+        match target {
+            ChatTarget::Global => target.to_string(),
+            ChatTarget::User(id) => self
+                .clients
+                .get(&id)
+                .and_then(|client| client.context.nickname.clone())
+                .or_else(|| self.account_ids.get(&id).cloned())
+                .unwrap_or_else(|| format!("user{}", id.0)),
+            ChatTarget::Npc(id) => self
+                .npcs
+                .get(&id)
+                .map(|npc| irc::sanitize_nick(npc.name()))
+                .filter(|name| !name.is_empty())
+                .unwrap_or_else(|| format!("npc{}", id.0)),
+            ChatTarget::Room(id) => self
+                .rooms
+                .get(&id)
+                .map(|room| room.name().to_owned())
+                .unwrap_or_else(|| id.to_string()),
+            ChatTarget::Booth(id) => self
+                .booths
+                .get(&id)
+                .map(|booth| booth.name().to_owned())
+                .unwrap_or_else(|| id.to_string()),
+        }
+    }
+
+    /// The nickname or channel a chat target is available as on IRC.
+    fn irc_name(&self, target: ChatTarget) -> String {
+        match target {
+            ChatTarget::Global => IRC_CHANNEL.to_owned(),
+            ChatTarget::Room(id) => self
+                .rooms
+                .get(&id)
+                .map(Room::irc_channel)
+                .unwrap_or_else(|| format!("#room{}", id.0)),
+            ChatTarget::Booth(id) => self
+                .booths
+                .get(&id)
+                .map(Booth::irc_channel)
+                .unwrap_or_else(|| format!("&booth{}", id.0)),
+            _ => self.display_name(target),
+        }
+    }
+
+    /// Finds the chat target an IRC channel or nickname refers to.
+    fn resolve_irc_name(&self, name: &str) -> Option<ChatTarget> {
+        if name.eq_ignore_ascii_case(IRC_CHANNEL) {
+            return Some(ChatTarget::Global);
+        }
+        if name.starts_with('#') {
+            return self
+                .rooms
+                .iter()
+                .find(|(_, room)| room.irc_channel().eq_ignore_ascii_case(name))
+                .map(|(id, _)| ChatTarget::Room(*id));
+        }
+        if name.starts_with('&') {
+            return self.find_booth(name).map(ChatTarget::Booth);
+        }
+        self.clients
+            .keys()
+            .map(|id| ChatTarget::User(*id))
+            .chain(self.npcs.keys().map(|id| ChatTarget::Npc(*id)))
+            .find(|target| self.display_name(*target).eq_ignore_ascii_case(name))
+    }
+}
+
+async fn manage_tcp_connections(
+    address: String,
+    protocol: ClientProtocol,
+    event_dispatch: mpsc::Sender<Event>,
+    mut shutdown: watch::Receiver<()>,
+) -> anyhow::Result<JoinHandle<()>> {
+    let listener = TcpListener::bind(&address).await?;
+
+    Ok(tokio::spawn(async move {
+        println!(
+            "☎️ Rust Tavern server awaiting {:?} connections on {:?}",
+            protocol, address
+        );
+
+        loop {
+            tokio::select! {
+                Ok((socket, addr)) = listener.accept() => {
+                    println!("🍺 New client connected: {addr}");
+                    let _ = event_dispatch.send(Event::NewClient { connection: socket, addr, protocol: protocol.clone() }).await;
+                }
+                Ok(()) = shutdown.changed() => {
+                    break;
+                }
+            }
+        }
+    }))
+}
+
+/// Renders a message for a single recipient, according to the protocol it speaks.
+/// Returns None if the recipient should not see the message.
+/// Room and booth messages are rendered for anyone, the caller only picks their members.
+fn client_output(
+    id: UserId,
+    client: &Client,
+    message: &Message,
+    irc_from: Option<&str>,
+    irc_to: &str,
+) -> Option<String> {
+    let message = &*message.seen_by(id);
+    let is_private = matches!(
+        message.to,
+        ChatTarget::User(_) | ChatTarget::Npc(_) | ChatTarget::Booth(_)
+    );
+    let is_recipient = match message.to {
+        ChatTarget::Global | ChatTarget::Room(_) | ChatTarget::Booth(_) => true,
+        ChatTarget::User(to) => to == id,
+        ChatTarget::Npc(_) => false,
+    };
+    match &client.protocol {
+        ClientProtocol::Plain if message.mentions.contains(&id) => {
+            is_recipient.then(|| message.to_mention_output(is_private))
+        }
+        ClientProtocol::Plain => is_recipient.then(|| message.to_output(is_private)),
+        ClientProtocol::Json => {
+            is_recipient.then(|| ServerOutput::Message(message.clone()).to_json_line())
+        }
+        ClientProtocol::Irc(session) => {
+            // IRC clients don't expect their own messages to be echoed back.
+            if !session.registered || message.from == Some(ChatTarget::User(id)) {
+                return None;
+            }
+            let in_channel = message.to != ChatTarget::Global || session.joined;
+            (is_recipient && in_channel).then(|| irc::message_line(irc_from, irc_to, message))
+        }
+        ClientProtocol::EventStream { bot } => visible_to_bot(message, *bot).then(|| {
+            http::sse_event(
+                "message",
+                &serde_json::to_value(message).unwrap_or_default(),
+            )
+        }),
+    }
+}
+
+/// The name of a protocol, as shown to users and in the HTTP API.
+fn protocol_name(protocol: &ClientProtocol) -> &'static str {
+    match protocol {
+        ClientProtocol::Plain => "plain",
+        ClientProtocol::Json => "json",
+        ClientProtocol::Irc(_) => "irc",
+        ClientProtocol::EventStream { .. } => "event stream",
+    }
+}
+
+/// Formats a duration roughly, e.g. `42s`, `5m` or `2h 10m`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d {}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+/// Returns true if a bot is allowed to see the message through the HTTP API.
+fn visible_to_bot(message: &Message, bot: NpcId) -> bool {
+    (matches!(message.to, ChatTarget::Global | ChatTarget::Room(_)) && message.heard_by.is_none())
+        || message.to == ChatTarget::Npc(bot)
+        || message.from == Some(ChatTarget::Npc(bot))
+}
+
+/// Returns true if the client can be seen from the IRC channel.
+fn in_irc_channel(client: &Client) -> bool {
+    match &client.protocol {
+        ClientProtocol::Plain | ClientProtocol::Json => true,
+        ClientProtocol::Irc(session) => session.registered && session.joined,
+        ClientProtocol::EventStream { .. } => false,
+    }
+}
+
+async fn to_client(send_tx: &mut ClientWriter, id: UserId, message: String) -> ServerResult {
+    // Ignore error when broadcasting.
+    match send_tx {
+        ClientWriter::Tcp(send_tx) => {
+            send_tx
+                .write_all(message.as_bytes())
+                .await
+                .map_err(|_| ServerError::TcpConnectionFailed(id))?;
+            send_tx
+                .flush()
+                .await
+                .map_err(|_| ServerError::TcpConnectionFailed(id))?;
+        }
+        // Never wait on an in-process client, as it may be waiting on the server itself.
+        ClientWriter::Channel(output_tx) => output_tx
+            .try_send(message)
+            .map_err(|_| ServerError::TcpConnectionFailed(id))?,
+    }
+    Ok(())
+}
+
+/// A new TCP client has been connected to the server.
+fn watch_client(
+    id: UserId,
+    read_half: OwnedReadHalf,
+    event_tx: mpsc::Sender<Event>,
+    mut shutdown_rx: watch::Receiver<()>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut lines = BufReader::new(read_half).lines();
+        loop {
+            tokio::select! {
+                res  = lines.next_line() => {
+                    match res {
+                        Ok(Some(incoming)) => {
+                            let _ = event_tx.send(Event::ReceiveUserMessage{from: id, message_raw: incoming}).await;
+                        },
+                        Ok(None) | Err(_) => {
+                            println!("❌ Error in connecting to user: {:?}", id);
+                            let _ = event_tx.send(Event::DisconnectClient { id }).await;
+                            break;
+                        },
+                    }
+                }
+                Ok(()) = shutdown_rx.changed() => {
+                    break;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn durations_are_rounded_for_display() {
+        assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+        assert_eq!(format_duration(Duration::from_secs(5 * 60 + 59)), "5m");
+        assert_eq!(
+            format_duration(Duration::from_secs(2 * 3600 + 600)),
+            "2h 10m"
+        );
+        assert_eq!(
+            format_duration(Duration::from_secs(3 * 86400 + 3600)),
+            "3d 1h"
+        );
+    }
 }
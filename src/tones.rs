@@ -0,0 +1,153 @@
+//! Contains the tones messages are spoken in, such as `yelled` or `muttered`. They're read from a
+//! JSON file, each with the rules changing how its messages read and how far they carry, and how
+//! NPCs react to hearing them.
+
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use crate::common::MessageTone;
+
+/// The tones known when the server isn't given a file of its own.
+pub const DEFAULT_TONES: &str = include_str!("../tones.json");
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tone {
+    /// How a message in this tone was spoken, e.g. `yelled`.
+    pub name: String,
+    /// Says something in this tone, e.g. `yell` for `/yell <message>`.
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Messages are shown in capitals.
+    #[serde(default)]
+    pub uppercase: bool,
+    /// Pairs of text to replace in messages, in order.
+    #[serde(default)]
+    pub replace: Vec<(String, String)>,
+    /// Wraps messages, with `{content}` replaced by the message.
+    #[serde(default)]
+    pub format: Option<String>,
+    #[serde(default)]
+    pub reach: Reach,
+    /// Said by NPCs hearing a message in this tone, with `{speaker}` replaced by who spoke.
+    #[serde(default)]
+    pub npc_reaction: Option<String>,
+}
+
+/// How far messages in a tone carry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Reach {
+    /// Messages reach whoever they're sent to.
+    #[default]
+    Target,
+    /// Messages sent to the whole tavern only reach the patrons sitting in the speaker's room.
+    Nearby,
+    /// Messages sent to a room are also heard in the rooms next to it.
+    Adjacent,
+}
+
+impl Tone {
+    /// Applies the tone's rules to the content of a message.
+    pub fn render(&self, content: &str) -> String {
+        let mut content = match self.uppercase {
+            true => content.to_uppercase(),
+            false => content.to_owned(),
+        };
+        for (from, to) in &self.replace {
+            content = content.replace(from, to);
+        }
+        match &self.format {
+            Some(format) => format.replace("{content}", &content),
+            None => content,
+        }
+    }
+
+    /// What an NPC says on hearing `speaker` in this tone, if anything.
+    pub fn npc_reaction(&self, speaker: &str) -> Option<String> {
+        self.npc_reaction
+            .as_ref()
+            .map(|reaction| reaction.replace("{speaker}", speaker))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Tones {
+    /// Tones by name.
+    tones: BTreeMap<MessageTone, Tone>,
+}
+
+impl Tones {
+    /// Reads tones from a JSON list.
+    pub fn parse(json: &str) -> io::Result<Self> {
+        let tones = serde_json::from_str::<Vec<Tone>>(json)?
+            .into_iter()
+            .map(|tone| (MessageTone::new(&tone.name), tone))
+            .collect();
+        Ok(Self { tones })
+    }
+
+    /// Loads the tones in `path`, or the default ones without it.
+    pub fn load(path: Option<&Path>) -> io::Result<Self> {
+        match path {
+            Some(path) => Self::parse(&fs::read_to_string(path)?),
+            None => Ok(Self::default()),
+        }
+    }
+
+    pub fn get(&self, tone: &MessageTone) -> Option<&Tone> {
+        self.tones.get(tone)
+    }
+
+    /// Finds a tone by its name or command, e.g. `yelled` or `yell`.
+    pub fn find(&self, name: &str) -> Option<(&MessageTone, &Tone)> {
+        let name = name.trim().trim_start_matches('/');
+        self.tones.iter().find(|(_, tone)| {
+            tone.name.eq_ignore_ascii_case(name)
+                || tone
+                    .command
+                    .as_ref()
+                    .is_some_and(|command| command.eq_ignore_ascii_case(name))
+        })
+    }
+
+    /// Every tone, by name.
+    pub fn iter(&self) -> impl Iterator<Item = (&MessageTone, &Tone)> {
+        self.tones.iter()
+    }
+}
+
+impl Default for Tones {
+    fn default() -> Self {
+        Self::parse(DEFAULT_TONES).expect("The default tones are valid")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn tones_change_how_messages_read() {
+        let tones = Tones::default();
+        let yelled = tones.get(&MessageTone::YELLED).unwrap();
+        assert_eq!(yelled.render("More ale!"), "MORE ALE!");
+        assert_eq!(yelled.reach, Reach::Adjacent);
+        assert_eq!(
+            yelled.npc_reaction("Alice").as_deref(),
+            Some("Easy there, Alice, no need to shout!")
+        );
+
+        let (tone, slurred) = tones.find("slur").unwrap();
+        assert_eq!(*tone, MessageTone::new("slurred"));
+        assert_eq!(slurred.render("Sixty sips"), "Shixty shipsh");
+        assert_eq!(
+            tones.find("SANG").unwrap().1.render("Ale, ale"),
+            "♪ Ale, ale ♪"
+        );
+        assert_eq!(
+            tones.get(&MessageTone::WHISPERED).unwrap().reach,
+            Reach::Nearby
+        );
+        assert!(tones.find("hummed").is_none());
+    }
+}
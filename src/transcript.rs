@@ -0,0 +1,241 @@
+//! Contains transcript export: messages from the history written out as JSON, as plain text
+//! the way clients see them, or as a standalone HTML page.
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+use std::{str::FromStr, time::SystemTime};
+
+use crate::common::{ChatTarget, Message, MessageKind, MessageTone};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TranscriptFormat {
+    Json,
+    #[default]
+    Text,
+    Html,
+}
+
+impl TranscriptFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TranscriptFormat::Json => "json",
+            TranscriptFormat::Text => "txt",
+            TranscriptFormat::Html => "html",
+        }
+    }
+}
+
+impl FromStr for TranscriptFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(TranscriptFormat::Json),
+            "text" | "txt" => Ok(TranscriptFormat::Text),
+            "html" => Ok(TranscriptFormat::Html),
+            _ => Err(format!("Unknown transcript format {s:?}.")),
+        }
+    }
+}
+
+/// Which messages to export, and how.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TranscriptQuery {
+    pub format: TranscriptFormat,
+    /// Name or ID of the room. Messages sent anywhere are exported without one.
+    pub room: Option<String>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+}
+
+impl TranscriptQuery {
+    /// Parses `<format> [room:<name>] [since:<time>] [until:<time>]`.
+    pub fn parse(args: &str) -> Result<Self, String> {
+        let mut words = args.split_whitespace();
+        let mut query = Self {
+            format: words.next().ok_or("Missing transcript format.")?.parse()?,
+            ..Default::default()
+        };
+        for word in words {
+            match word.split_once(':') {
+                Some(("room", room)) if !room.is_empty() => query.room = Some(room.to_owned()),
+                Some(("since", time)) => query.since = Some(parse_time(time, false)?),
+                Some(("until", time)) => query.until = Some(parse_time(time, true)?),
+                _ => return Err(format!("Unexpected {word:?}.")),
+            }
+        }
+        Ok(query)
+    }
+
+    /// Returns true if a message sent at `time` falls within the query's time range.
+    pub fn contains(&self, time: SystemTime) -> bool {
+        let time = DateTime::<Local>::from(time).naive_local();
+        self.since.is_none_or(|since| time >= since) && self.until.is_none_or(|until| time <= until)
+    }
+}
+
+/// Parses a local time, either `YYYY-MM-DD` or `YYYY-MM-DDTHH:MM`.
+/// A lone date means the start of that day, or its end if `end_of_day` is set.
+pub fn parse_time(time: &str, end_of_day: bool) -> Result<NaiveDateTime, String> {
+    if let Ok(time) = NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M") {
+        return Ok(time);
+    }
+    let day = NaiveDate::parse_from_str(time, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid time {time:?}. Use YYYY-MM-DD or YYYY-MM-DDTHH:MM."))?;
+    Ok(if end_of_day {
+        day.and_hms_milli_opt(23, 59, 59, 999).unwrap_or_default()
+    } else {
+        day.and_hms_opt(0, 0, 0).unwrap_or_default()
+    })
+}
+
+/// Renders messages as a transcript.
+pub fn render(messages: &[&Message], format: TranscriptFormat) -> String {
+    match format {
+        TranscriptFormat::Json => serde_json::to_string_pretty(messages).unwrap_or_default(),
+        TranscriptFormat::Text => messages
+            .iter()
+            .map(|message| message.to_output(is_private(message)))
+            .collect(),
+        TranscriptFormat::Html => render_html(messages),
+    }
+}
+
+fn is_private(message: &Message) -> bool {
+    matches!(
+        message.to,
+        ChatTarget::User(_) | ChatTarget::Npc(_) | ChatTarget::Booth(_)
+    )
+}
+
+fn render_html(messages: &[&Message]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Tavern transcript</title>
+<style>
+body { background: #2b2118; color: #f3e6cf; font-family: Georgia, serif; margin: 2em; }
+p { margin: 0.3em 0; }
+.time { color: #9c8b74; font-size: 0.85em; }
+.from { color: #e0b25c; font-weight: bold; }
+.private { color: #9c8b74; font-style: italic; }
+.said { color: #f3e6cf; }
+.yelled { color: #ff6b4a; font-weight: bold; }
+.laughed { color: #f5d547; }
+.whispered { color: #b8a9d9; font-style: italic; }
+.reply { color: #9c8b74; font-size: 0.85em; }
+.action { color: #c9d98b; font-style: italic; }
+</style>
+</head>
+<body>
+<h1>Tavern transcript</h1>
+",
+    );
+    for message in messages {
+        let tone = &message.tone;
+        let from = format!(
+            "<span class=\"from\">{}</span>",
+            escape_html(&message.from.unwrap_or_default().to_string())
+        );
+        let privately = if is_private(message) {
+            " <span class=\"private\">privately</span>"
+        } else {
+            ""
+        };
+        // Actions and emotes read as a sentence, so they don't name their tone.
+        let (class, speaker) = match message.kind {
+            MessageKind::Speech => (
+                tone_class(tone),
+                format!("{from} {}{privately}: ", escape_html(&tone.to_string())),
+            ),
+            MessageKind::Action | MessageKind::Roll => ("action".to_owned(), format!("* {from} ")),
+            MessageKind::Emote => ("action".to_owned(), "* ".to_owned()),
+        };
+        html.push_str(&format!(
+            "<p class=\"{class}\"><span class=\"time\">{}</span> {speaker}{}{}{}</p>\n",
+            DateTime::<Local>::from(message.timestamp).format("%Y-%m-%d %H:%M:%S"),
+            message
+                .reply_to
+                .as_ref()
+                .map(|reply_to| format!(
+                    "<span class=\"reply\">{}</span> ",
+                    escape_html(&reply_to.to_string())
+                ))
+                .unwrap_or_default(),
+            escape_html(&message.content).replace('\n', "<br>"),
+            if message.kind == MessageKind::Speech {
+                ""
+            } else {
+                privately
+            },
+        ));
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// The CSS class of a tone. Tones can be configured, so only lowercase letters and dashes of
+/// their name are kept.
+fn tone_class(tone: &MessageTone) -> String {
+    tone.to_string()
+        .chars()
+        .filter(|c| c.is_ascii_lowercase() || *c == '-')
+        .collect()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn queries_take_a_format_room_and_time_range() {
+        let query =
+            TranscriptQuery::parse("html room:back-room since:2025-06-01 until:2025-06-02T18:30")
+                .unwrap();
+        assert_eq!(query.format, TranscriptFormat::Html);
+        assert_eq!(query.room.as_deref(), Some("back-room"));
+        assert_eq!(query.since, parse_time("2025-06-01T00:00", false).ok());
+        assert_eq!(query.until, parse_time("2025-06-02T18:30", false).ok());
+        assert_eq!(
+            parse_time("2025-06-02", true).unwrap().to_string(),
+            "2025-06-02 23:59:59.999"
+        );
+        assert!(TranscriptQuery::parse("pdf").is_err());
+        assert!(TranscriptQuery::parse("json since:tomorrow").is_err());
+    }
+
+    #[test]
+    fn html_transcripts_escape_content_and_colour_tones() {
+        let message = Message::new(
+            Some(ChatTarget::user(1)),
+            ChatTarget::Global,
+            "<b>Ale!</b>",
+            Some(MessageTone::YELLED),
+        );
+        let html = render(&[&message], TranscriptFormat::Html);
+        assert!(html.contains("<p class=\"yelled\">"), "{html}");
+        assert!(html.contains("&lt;b&gt;Ale!&lt;/b&gt;"), "{html}");
+
+        let sneaky = Message::new(
+            Some(ChatTarget::user(1)),
+            ChatTarget::Global,
+            "Hello",
+            Some(MessageTone("x\"><script>alert(1)</script>".into())),
+        );
+        let html = render(&[&sneaky], TranscriptFormat::Html);
+        assert!(!html.contains("<script>"), "{html}");
+        assert!(html.contains("<p class=\"xscriptalertscript\">"), "{html}");
+        assert_eq!(
+            render(&[&message], TranscriptFormat::Text),
+            message.to_output(false)
+        );
+    }
+}
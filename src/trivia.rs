@@ -0,0 +1,333 @@
+//! Contains the trivia run by the quizmaster NPC. Questions are read from a JSON file, asked in
+//! the quizmaster's room on a timer, and answered in plain chat. Answers are matched loosely,
+//! forgiving case, punctuation and small typos. Patrons logged into an account score points,
+//! kept on a leaderboard saved to disk if the server is configured with a file for it.
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::common::{NpcId, RoomId};
+use crate::dice::Dice;
+
+/// The questions asked when the server isn't given a file of its own.
+pub const DEFAULT_QUESTIONS: &str = include_str!("../trivia.json");
+pub const QUIZMASTER_NAME: &str = "Quizmaster";
+/// How long patrons have to answer a question, and how long until the next one.
+pub const TRIVIA_INTERVAL: Duration = Duration::from_secs(45);
+/// Number of patrons shown by `/trivia top`.
+pub const LEADERBOARD_LEN: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Question {
+    pub question: String,
+    /// Every accepted answer. The first one is given when nobody finds it.
+    pub answers: Vec<String>,
+}
+
+impl Question {
+    /// Returns true if a line of chat contains one of the answers, give or take a few typos.
+    pub fn is_answered_by(&self, guess: &str) -> bool {
+        let guess = words(guess);
+        self.answers.iter().any(|answer| {
+            let answer = words(answer);
+            !answer.is_empty()
+                && guess
+                    .windows(answer.len())
+                    .any(|window| roughly_equal(&window.join(" "), &answer.join(" ")))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct QuestionBank {
+    questions: Vec<Question>,
+}
+
+impl QuestionBank {
+    /// Reads questions from a JSON list.
+    pub fn parse(json: &str) -> io::Result<Self> {
+        Ok(Self {
+            questions: serde_json::from_str(json)?,
+        })
+    }
+
+    /// Loads the questions in `path`, or the default ones without it.
+    pub fn load(path: Option<&Path>) -> io::Result<Self> {
+        match path {
+            Some(path) => Self::parse(&fs::read_to_string(path)?),
+            None => Self::parse(DEFAULT_QUESTIONS),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.questions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.questions.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Score {
+    pub name: String,
+    pub points: u32,
+}
+
+/// Points of every account whose patron ever answered a question.
+#[derive(Debug, Clone, Default)]
+pub struct Leaderboard {
+    /// Where scores are saved. They only live in memory without one.
+    path: Option<PathBuf>,
+    /// Scores by lowercase account name.
+    scores: BTreeMap<String, Score>,
+}
+
+impl Leaderboard {
+    /// Loads the scores saved in `path`, if it exists yet.
+    pub fn load(path: Option<&Path>) -> io::Result<Self> {
+        let scores = match path {
+            Some(path) if path.exists() => serde_json::from_str(&fs::read_to_string(path)?)?,
+            _ => Default::default(),
+        };
+        Ok(Self {
+            path: path.map(Path::to_owned),
+            scores,
+        })
+    }
+
+    /// Gives a point to an account. Returns its points.
+    pub fn award(&mut self, name: &str) -> u32 {
+        let score = self
+            .scores
+            .entry(name.to_lowercase())
+            .or_insert_with(|| Score {
+                name: name.to_owned(),
+                points: 0,
+            });
+        score.points += 1;
+        score.points
+    }
+
+    /// The patrons with the most points, best first.
+    pub fn top(&self, len: usize) -> Vec<&Score> {
+        let mut scores = self.scores.values().collect::<Vec<_>>();
+        scores.sort_by(|a, b| b.points.cmp(&a.points).then_with(|| a.name.cmp(&b.name)));
+        scores.truncate(len);
+        scores
+    }
+
+    /// Writes the scores to disk. The file is replaced at once, so a crash leaves the
+    /// previous version intact.
+    pub fn save(&self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let temp_path = path.with_extension("tmp");
+        fs::write(&temp_path, serde_json::to_string_pretty(&self.scores)?)?;
+        fs::rename(temp_path, path)
+    }
+}
+
+/// The trivia run by the quizmaster in its room.
+#[derive(Debug, Clone)]
+pub struct Trivia {
+    pub npc: NpcId,
+    pub room: RoomId,
+    pub bank: QuestionBank,
+    pub leaderboard: Leaderboard,
+    /// Questions not asked since the bank was last gone through, by index.
+    unasked: Vec<usize>,
+    /// The question waiting for an answer, if any.
+    current: Option<usize>,
+    /// Counts questions asked and closed, so stale timers can be told apart.
+    round: u64,
+}
+
+impl Trivia {
+    pub fn new(npc: NpcId, room: RoomId) -> Self {
+        Self {
+            npc,
+            room,
+            bank: Default::default(),
+            leaderboard: Default::default(),
+            unasked: vec![],
+            current: None,
+            round: 0,
+        }
+    }
+
+    pub fn round(&self) -> u64 {
+        self.round
+    }
+
+    pub fn question(&self) -> Option<&Question> {
+        self.current.and_then(|i| self.bank.questions.get(i))
+    }
+
+    /// Asks a question picked at random, going through the whole bank before repeating one.
+    /// Returns what the quizmaster says, unless there's nothing to ask.
+    pub fn ask(&mut self, dice: &mut Dice) -> Option<String> {
+        if self.bank.is_empty() {
+            return None;
+        }
+        if self.unasked.is_empty() {
+            self.unasked = (0..self.bank.len()).collect();
+        }
+        let picked = dice.draw(1, self.unasked.len() as u32)[0] as usize - 1;
+        let question = self.unasked.swap_remove(picked);
+        self.current = Some(question);
+        self.round += 1;
+        Some(format!(
+            "Trivia time! {}",
+            self.bank.questions[question].question
+        ))
+    }
+
+    /// Closes the question nobody answered. Returns what the quizmaster says.
+    pub fn expire(&mut self) -> Option<String> {
+        let question = self.question()?.clone();
+        self.current = None;
+        self.round += 1;
+        Some(format!(
+            "Time's up! The answer was {}.",
+            question.answers.first().map_or("a mystery", String::as_str)
+        ))
+    }
+
+    /// Checks a line of chat against the question. Returns what the quizmaster says if the
+    /// patron found the answer, who then wins a point if they're logged into an account.
+    /// Guests' names are reused by others, so they don't score.
+    pub fn answer(&mut self, name: &str, account: Option<&str>, guess: &str) -> Option<String> {
+        let question = self.question()?;
+        if !question.is_answered_by(guess) {
+            return None;
+        }
+        let answer = question.answers.first().cloned().unwrap_or_default();
+        self.current = None;
+        self.round += 1;
+        let Some(account) = account else {
+            return Some(format!(
+                "Correct, {name}! It was {answer}. Use /register or /login to score points."
+            ));
+        };
+        let points = self.leaderboard.award(account);
+        if let Err(e) = self.leaderboard.save() {
+            println!("Failed to save the trivia leaderboard: {e}");
+        }
+        Some(format!(
+            "Correct, {account}! It was {answer}. {account} has {points} point{}.",
+            if points == 1 { "" } else { "s" }
+        ))
+    }
+}
+
+/// The words of a text, lowercase and without punctuation or articles.
+fn words(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .replace(['\'', '’'], "")
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty() && !["the", "a", "an"].contains(word))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Compares a guess to an answer, letting one typo in every five letters through.
+/// Numbers must be exact.
+fn roughly_equal(guess: &str, answer: &str) -> bool {
+    let tolerance = match answer.chars().any(|c| c.is_ascii_digit()) {
+        true => 0,
+        false => answer.chars().count() / 5,
+    };
+    edit_distance(guess, answer) <= tolerance
+}
+
+/// The number of letters to add, remove, change or swap with their neighbour to turn a string
+/// into another.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b) = (a.chars().collect::<Vec<_>>(), b.chars().collect::<Vec<_>>());
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in 0..=a.len() {
+        for j in 0..=b.len() {
+            distances[i][j] = match (i, j) {
+                (0, j) => j,
+                (i, 0) => i,
+                (i, j) => {
+                    let mut distance = (distances[i - 1][j] + 1)
+                        .min(distances[i][j - 1] + 1)
+                        .min(distances[i - 1][j - 1] + usize::from(a[i - 1] != b[j - 1]));
+                    if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                        distance = distance.min(distances[i - 2][j - 2] + 1);
+                    }
+                    distance
+                }
+            };
+        }
+    }
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn answers_are_matched_loosely() {
+        let question = Question {
+            question: "Who wrote The Hobbit?".to_owned(),
+            answers: vec!["J. R. R. Tolkien".to_owned(), "Tolkien".to_owned()],
+        };
+        for guess in [
+            "tolkien",
+            "Is it Tolkein?",
+            "J.R.R. TOLKIEN!",
+            "jrr tolkien",
+        ] {
+            assert!(question.is_answered_by(guess), "{guess:?}");
+        }
+        for guess in ["Lewis", "tol", "C. S. Lewis wrote it"] {
+            assert!(!question.is_answered_by(guess), "{guess:?}");
+        }
+
+        let question = Question {
+            question: "How many days are there in a leap year?".to_owned(),
+            answers: vec!["366".to_owned()],
+        };
+        assert!(question.is_answered_by("366 days"));
+        assert!(!question.is_answered_by("365"));
+        let question = Question {
+            question: "What is the largest ocean on Earth?".to_owned(),
+            answers: vec!["The Pacific".to_owned()],
+        };
+        assert!(question.is_answered_by("pacific"));
+        assert!(!question.is_answered_by("the"));
+    }
+
+    #[test]
+    fn leaderboards_are_saved() {
+        let path = std::env::temp_dir().join(format!("tavern-trivia-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut leaderboard = Leaderboard::load(Some(&path)).unwrap();
+        leaderboard.award("Bob");
+        leaderboard.award("Alice");
+        assert_eq!(leaderboard.award("bob"), 2);
+        leaderboard.save().unwrap();
+
+        let leaderboard = Leaderboard::load(Some(&path)).unwrap();
+        let top = leaderboard
+            .top(LEADERBOARD_LEN)
+            .iter()
+            .map(|score| (score.name.as_str(), score.points))
+            .collect::<Vec<_>>();
+        assert_eq!(top, [("Bob", 2), ("Alice", 1)]);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,1348 @@
+//! Integration tests running a whole tavern in-process, through the library API.
+
+use futures::StreamExt;
+use std::time::Duration;
+use tavern_chat::{
+    TavernClient, TavernServer,
+    client::ClientEvent,
+    common::{
+        ChatTarget, DELETED_QUOTE, Event, Message, MessageKind, MessageTone, SystemNotification,
+    },
+    dice::{Dice, DiceExpression},
+    http::ApiTokens,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::mpsc,
+    task::JoinHandle,
+    time::timeout,
+};
+
+fn start_tavern() -> (JoinHandle<anyhow::Result<()>>, mpsc::Sender<Event>) {
+    let (mut server, event_tx) = TavernServer::builder()
+        .without_listeners()
+        .npc("Barkeep")
+        .build();
+    (tokio::spawn(async move { server.run().await }), event_tx)
+}
+
+/// Waits for a notification starting with `prefix`, such as the answer to a command,
+/// skipping everything else. Passwords take a while to hash in debug builds, so answers are
+/// given a few seconds.
+async fn wait_for_notification(client: &mut TavernClient, prefix: &str) -> SystemNotification {
+    let mut events = client.events();
+    loop {
+        match timeout(Duration::from_secs(5), events.next()).await {
+            Ok(Some(ClientEvent::Notification(notification)))
+                if notification.content.starts_with(prefix) =>
+            {
+                return notification;
+            }
+            Ok(Some(_)) => {}
+            Ok(None) | Err(_) => panic!("Expected a notification starting with {prefix:?}"),
+        }
+    }
+}
+
+/// Waits for a message containing `text`, skipping everything else.
+async fn wait_for_message(client: &mut TavernClient, text: &str) -> Message {
+    let mut events = client.events();
+    loop {
+        match timeout(Duration::from_secs(2), events.next()).await {
+            Ok(Some(ClientEvent::Message(message))) if message.content.contains(text) => {
+                return message;
+            }
+            Ok(Some(_)) => {}
+            Ok(None) | Err(_) => panic!("Expected a message containing {text:?}"),
+        }
+    }
+}
+
+/// Collects every message a client receives until nothing arrives for a short while.
+async fn received_messages(client: &mut TavernClient) -> Vec<Message> {
+    let mut messages = vec![];
+    let mut events = client.events();
+    while let Ok(Some(event)) = timeout(Duration::from_millis(200), events.next()).await {
+        if let ClientEvent::Message(message) = event {
+            messages.push(message);
+        }
+    }
+    messages
+}
+
+/// Collects the messages replayed from the history until the client goes quiet.
+async fn received_history(client: &mut TavernClient) -> Vec<Message> {
+    let mut messages = vec![];
+    let mut events = client.events();
+    while let Ok(Some(event)) = timeout(Duration::from_millis(200), events.next()).await {
+        if let ClientEvent::History(message) = event {
+            messages.push(message);
+        }
+    }
+    messages
+}
+
+#[tokio::test]
+async fn private_messages_only_reach_their_recipient() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut carol = TavernClient::in_process(event_tx.clone()).await.unwrap();
+
+    alice.say("A round for everyone!").await.unwrap();
+    alice.whisper(bob.id(), "Not you, Carol.").await.unwrap();
+
+    let bob_messages = received_messages(&mut bob).await;
+    assert!(
+        bob_messages
+            .iter()
+            .any(|m| m.content == "A round for everyone!")
+    );
+    assert!(bob_messages.iter().any(|m| m.content == "Not you, Carol."));
+
+    let carol_messages = received_messages(&mut carol).await;
+    assert!(
+        carol_messages
+            .iter()
+            .any(|m| m.content == "A round for everyone!")
+    );
+    assert!(
+        !carol_messages
+            .iter()
+            .any(|m| m.content == "Not you, Carol.")
+    );
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn npcs_configured_by_the_builder_can_be_targeted() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+
+    // The Barkeep is the first entity created, so it has the first ID.
+    alice.set_target(ChatTarget::npc(0)).await.unwrap();
+    alice.say("An ale, please.").await.unwrap();
+
+    // Messages to a missing target would be answered with a failure notification.
+    let mut events = alice.events();
+    while let Ok(Some(event)) = timeout(Duration::from_millis(200), events.next()).await {
+        if let ClientEvent::Notification(notification) = event {
+            assert!(!notification.content.contains("Failed"), "{notification:?}");
+        }
+    }
+    drop(events);
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn everyone_hears_about_shutdown() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    let messages = received_messages(&mut alice).await;
+    assert!(
+        messages
+            .iter()
+            .any(|m| m.content.contains("Server Shutdown"))
+    );
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn room_messages_only_reach_the_room() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut carol = TavernClient::in_process(event_tx.clone()).await.unwrap();
+
+    alice.send_raw("/join the back room").await.unwrap();
+    let joined = wait_for_notification(&mut alice, "You join").await;
+    assert_eq!(joined.content, "You join The Back Room. It's empty.");
+    bob.send_raw("/join back-room").await.unwrap();
+    let joined = wait_for_notification(&mut bob, "You join").await;
+    assert!(joined.content.contains("Also here: user"), "{joined:?}");
+
+    alice.say("Did you bring the map?").await.unwrap();
+    let bob_messages = received_messages(&mut bob).await;
+    assert!(
+        bob_messages
+            .iter()
+            .any(|m| m.content == "Did you bring the map?" && matches!(m.to, ChatTarget::Room(_)))
+    );
+    let carol_messages = received_messages(&mut carol).await;
+    assert!(
+        !carol_messages
+            .iter()
+            .any(|m| m.content == "Did you bring the map?")
+    );
+
+    // Once out of the room, Alice talks to the whole tavern again.
+    alice.send_raw("/leave").await.unwrap();
+    let left = wait_for_notification(&mut alice, "You leave").await;
+    assert_eq!(left.content, "You leave The Back Room.");
+    alice.say("I'm back!").await.unwrap();
+    let carol_messages = received_messages(&mut carol).await;
+    assert!(carol_messages.iter().any(|m| m.content == "I'm back!"));
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn booths_are_invite_only_and_outlive_their_owner() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut carol = TavernClient::in_process(event_tx.clone()).await.unwrap();
+
+    alice.send_raw("/booth create snug").await.unwrap();
+    wait_for_notification(&mut alice, "You create the booth snug").await;
+    carol.send_raw("/booth join snug").await.unwrap();
+    wait_for_notification(&mut carol, "The booth snug is private").await;
+
+    alice
+        .send_raw(&format!("/booth invite {}", bob.id().0))
+        .await
+        .unwrap();
+    let invite = format!("user{} invites you to the booth snug.", alice.id().0);
+    wait_for_notification(&mut bob, &invite).await;
+    bob.send_raw("/booth join snug").await.unwrap();
+    wait_for_notification(&mut bob, "You sit down in the booth snug.").await;
+
+    alice.say("Just between us.").await.unwrap();
+    assert!(
+        received_messages(&mut bob)
+            .await
+            .iter()
+            .any(|m| m.content == "Just between us.")
+    );
+    assert!(
+        !received_messages(&mut carol)
+            .await
+            .iter()
+            .any(|m| m.content == "Just between us.")
+    );
+
+    // The owner leaving hands the booth over, and it's closed once everyone is gone. Booths
+    // can be left while talking elsewhere.
+    alice.send_raw("/global").await.unwrap();
+    alice.send_raw("/booth leave").await.unwrap();
+    wait_for_notification(&mut alice, "You leave the booth snug.").await;
+    wait_for_notification(&mut bob, "You now own the booth snug.").await;
+    bob.send_raw("/global").await.unwrap();
+    bob.send_raw("/booth leave snug").await.unwrap();
+    wait_for_notification(&mut bob, "You leave the booth snug.").await;
+    carol.send_raw("/booth create snug").await.unwrap();
+    wait_for_notification(&mut carol, "You create the booth snug").await;
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn patrons_see_who_comes_and_goes() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let bob_name = format!("user{}", bob.id().0);
+
+    wait_for_notification(&mut alice, &format!("{bob_name} walks into the tavern.")).await;
+    alice.send_raw("/who").await.unwrap();
+    let who = wait_for_notification(&mut alice, "2 patrons in the tavern:").await;
+    assert!(who.content.contains(&format!(
+        "{bob_name} ({}): not in any room, active",
+        bob.id()
+    )));
+    alice.send_raw(&format!("/whois {bob_name}")).await.unwrap();
+    let whois = wait_for_notification(&mut alice, &bob_name).await;
+    assert!(whois.content.contains("Tone: said"), "{whois:?}");
+
+    drop(bob);
+    wait_for_notification(&mut alice, &format!("{bob_name} leaves the tavern.")).await;
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn direct_messages_to_away_patrons_get_an_auto_reply() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let bob_name = format!("user{}", bob.id().0);
+
+    bob.send_raw("/away out back").await.unwrap();
+    alice.whisper(bob.id(), "Are you there?").await.unwrap();
+    wait_for_notification(&mut alice, &format!("{bob_name} is away (out back).")).await;
+    alice.send_raw("/who").await.unwrap();
+    let who = wait_for_notification(&mut alice, "2 patrons in the tavern:").await;
+    assert!(
+        who.content.contains("not in any room, away (out back)"),
+        "{who:?}"
+    );
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn api_requests_are_answered_while_the_tavern_carries_on() {
+    // A free port, picked by the system.
+    let address = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .unwrap()
+        .to_string();
+    let (mut server, event_tx) = TavernServer::builder()
+        .without_listeners()
+        .http_address(Some(&address))
+        .api_tokens(ApiTokens::parse("dashboard:s3cret"))
+        .build();
+    let server = tokio::spawn(async move { server.run().await });
+    // The history grows far larger than what a connection buffers.
+    let tale = "Once upon a time, ".repeat(5_000);
+    for _ in 0..100 {
+        let message = Message::new(None, ChatTarget::Global, &tale, None);
+        event_tx
+            .send(Event::BroadcastMessage { message })
+            .await
+            .unwrap();
+    }
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+
+    let connect = async |request: &str| {
+        for _ in 0..20 {
+            if let Ok(mut connection) = TcpStream::connect(&address).await {
+                connection.write_all(request.as_bytes()).await.unwrap();
+                return connection;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        panic!("The HTTP API never started");
+    };
+    // Nothing is read from the history until the tavern has moved on.
+    let mut stalled =
+        connect("GET /history HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n").await;
+    alice.say("Anyone there?").await.unwrap();
+    wait_for_message(&mut bob, "Anyone there?").await;
+
+    let mut answered = connect("GET /users HTTP/1.1\r\nAuthorization: Bearer s3cret\r\n\r\n").await;
+    let mut users = String::new();
+    let mut history = String::new();
+    for (connection, response) in [(&mut answered, &mut users), (&mut stalled, &mut history)] {
+        timeout(Duration::from_secs(5), connection.read_to_string(response))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response:.100}");
+    }
+    assert!(users.contains(&format!("\"name\":\"user{}\"", alice.id().0)));
+    assert!(history.len() > 90 * tale.len());
+
+    let body = r#"{"content":"hi","tone":"x\"><script>"}"#;
+    let mut posted = connect(&format!(
+        "POST /messages HTTP/1.1\r\nAuthorization: Bearer s3cret\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    ))
+    .await;
+    let mut response = String::new();
+    posted.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 400"), "{response}");
+    assert!(response.contains("No such tone"), "{response}");
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn history_is_replayed_to_newcomers_without_private_messages() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    alice.say("First round's on me.").await.unwrap();
+    alice.whisper(bob.id(), "Not really.").await.unwrap();
+    // Wait for the messages to be broadcast, since the parser queues them behind new events.
+    received_messages(&mut alice).await;
+
+    let mut carol = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut history = vec![];
+    let mut events = carol.events();
+    while let Ok(Some(event)) = timeout(Duration::from_millis(200), events.next()).await {
+        if let ClientEvent::History(message) = event {
+            history.push(message.content);
+        }
+    }
+    drop(events);
+    assert_eq!(history, vec!["First round's on me.".to_string()]);
+
+    // Asking for more shows private messages to those who sent them.
+    alice.send_raw("/history").await.unwrap();
+    let mut history = vec![];
+    let mut events = alice.events();
+    while let Ok(Some(event)) = timeout(Duration::from_millis(200), events.next()).await {
+        if let ClientEvent::History(message) = event {
+            history.push(message.content);
+        }
+    }
+    drop(events);
+    assert_eq!(history, vec!["First round's on me.", "Not really."]);
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn journaled_history_survives_a_restart() {
+    let dir = std::env::temp_dir().join(format!("tavern-journal-restart-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let start = || {
+        let (mut server, event_tx) = TavernServer::builder()
+            .without_listeners()
+            .journal_dir(Some(&dir))
+            .build();
+        (tokio::spawn(async move { server.run().await }), event_tx)
+    };
+
+    let (server, event_tx) = start();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    alice.say("Put it on my tab.").await.unwrap();
+    received_messages(&mut alice).await;
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+
+    let (server, event_tx) = start();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut history = vec![];
+    let mut events = bob.events();
+    while let Ok(Some(event)) = timeout(Duration::from_millis(200), events.next()).await {
+        if let ClientEvent::History(message) = event {
+            history.push(message.content);
+        }
+    }
+    drop(events);
+    assert!(
+        history.contains(&"Put it on my tab.".to_string()),
+        "{history:?}"
+    );
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn patrons_after_a_restart_cannot_take_over_restored_messages() {
+    let dir = std::env::temp_dir().join(format!("tavern-journal-ids-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let start = || {
+        let (mut server, event_tx) = TavernServer::builder()
+            .without_listeners()
+            .journal_dir(Some(&dir))
+            .build();
+        (tokio::spawn(async move { server.run().await }), event_tx)
+    };
+
+    let (server, event_tx) = start();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    alice
+        .whisper(bob.id(), "The key is under the mat.")
+        .await
+        .unwrap();
+    let whispered = wait_for_message(&mut bob, "The key is under the mat.").await;
+    let id = whispered.id.expect("Messages from patrons have an id");
+    received_messages(&mut alice).await;
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+
+    let (server, event_tx) = start();
+    let mut carol = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut dave = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    for guest in [&mut carol, &mut dave] {
+        assert!(![alice.id(), bob.id()].contains(&guest.id()));
+        received_history(guest).await;
+        guest.send_raw("/history").await.unwrap();
+        guest.send_raw("/search key").await.unwrap();
+        guest.send_raw(&format!("/thread {id}")).await.unwrap();
+        let seen = received_history(guest).await;
+        assert!(
+            seen.iter().all(|message| message.id != Some(id)),
+            "{seen:?}"
+        );
+        guest
+            .send_raw(&format!("/edit {id} The key is gone."))
+            .await
+            .unwrap();
+        wait_for_notification(guest, "You can only change your own messages.").await;
+        guest.send_raw(&format!("/delete {id}")).await.unwrap();
+        wait_for_notification(guest, "You can only change your own messages.").await;
+    }
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn search_only_finds_messages_the_patron_may_see() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut carol = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    alice.say("The dragon sleeps on its gold.").await.unwrap();
+    bob.whisper(carol.id(), "I know where the dragon is.")
+        .await
+        .unwrap();
+    received_messages(&mut bob).await;
+
+    let search = async |client: &mut TavernClient, query: &str| {
+        client.send_raw(&format!("/search {query}")).await.unwrap();
+        let mut found = vec![];
+        let mut events = client.events();
+        while let Ok(Some(event)) = timeout(Duration::from_millis(200), events.next()).await {
+            if let ClientEvent::History(message) = event {
+                found.push(message.content);
+            }
+        }
+        found
+    };
+    assert_eq!(
+        search(&mut alice, "DRAGON").await,
+        vec!["The dragon sleeps on its gold."]
+    );
+    assert_eq!(
+        search(&mut bob, "dragon").await,
+        vec![
+            "The dragon sleeps on its gold.",
+            "I know where the dragon is."
+        ]
+    );
+    assert_eq!(
+        search(&mut bob, &format!("dragon from:user{}", alice.id().0)).await,
+        vec!["The dragon sleeps on its gold."]
+    );
+
+    // Room chat is found by those in the room.
+    for patron in [&mut alice, &mut bob] {
+        patron.send_raw("/join back room").await.unwrap();
+        wait_for_notification(patron, "You join").await;
+    }
+    alice.say("The dragon took the map.").await.unwrap();
+    received_messages(&mut bob).await;
+    assert_eq!(
+        search(&mut bob, "map").await,
+        vec!["The dragon took the map."]
+    );
+    assert_eq!(search(&mut carol, "map").await, Vec::<String>::new());
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn admins_can_export_transcripts() {
+    let dir = std::env::temp_dir().join(format!("tavern-transcripts-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let (mut server, event_tx) = TavernServer::builder()
+        .without_listeners()
+        .admin_password(Some("hunter2"))
+        .export_dir(&dir)
+        .build();
+    let server = tokio::spawn(async move { server.run().await });
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+
+    alice.say("Roll for initiative!").await.unwrap();
+    alice.send_raw("/export json").await.unwrap();
+    wait_for_notification(&mut alice, "Only admins can export transcripts.").await;
+    alice.send_raw("/admin hunter2").await.unwrap();
+    wait_for_notification(&mut alice, "You are now an admin.").await;
+    alice.send_raw("/export json").await.unwrap();
+    wait_for_notification(&mut alice, "Exported 1 message").await;
+
+    let file = std::fs::read_dir(&dir).unwrap().next().unwrap().unwrap();
+    let messages: Vec<Message> =
+        serde_json::from_str(&std::fs::read_to_string(file.path()).unwrap()).unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].content, "Roll for initiative!");
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn direct_messages_to_registered_patrons_wait_in_their_mailbox() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    alice.send_raw("/register Alice hunter2").await.unwrap();
+    wait_for_notification(&mut alice, "Welcome, Alice!").await;
+    let alice_id = alice.id();
+    drop(alice);
+    wait_for_notification(&mut bob, "Alice leaves the tavern.").await;
+
+    bob.whisper(alice_id, "Meet me at the docks.")
+        .await
+        .unwrap();
+    wait_for_notification(&mut bob, "Alice isn't in the tavern.").await;
+    bob.send_raw("/mail send alice Bring a lantern.")
+        .await
+        .unwrap();
+    wait_for_notification(&mut bob, "Alice isn't in the tavern.").await;
+
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    alice.send_raw("/login alice wrong").await.unwrap();
+    wait_for_notification(&mut alice, "Wrong name or password.").await;
+    alice.send_raw("/login alice hunter2").await.unwrap();
+    wait_for_notification(&mut alice, "Welcome back, Alice! You have 2 new mails.").await;
+    alice.send_raw("/mail read 1").await.unwrap();
+    let mail = wait_for_notification(&mut alice, "Mail 1 from").await;
+    assert!(mail.content.ends_with("Meet me at the docks."), "{mail:?}");
+    alice.send_raw("/mail delete 1").await.unwrap();
+    wait_for_notification(&mut alice, "Mail 1 deleted.").await;
+    alice.send_raw("/mail").await.unwrap();
+    let list = wait_for_notification(&mut alice, "Your mailbox (1 of 50, 1 new):").await;
+    assert!(list.content.ends_with("Bring a lantern."), "{list:?}");
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn passwords_are_checked_aside_and_guessing_them_is_limited() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut mallory = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    alice.send_raw("/register Alice hunter2").await.unwrap();
+    wait_for_notification(&mut alice, "Welcome, Alice!").await;
+    drop(alice);
+    wait_for_notification(&mut mallory, "Alice leaves the tavern.").await;
+
+    let notifications = async |client: &mut TavernClient, n: usize| {
+        let mut notifications = vec![];
+        let mut events = client.events();
+        while notifications.len() < n {
+            match timeout(Duration::from_secs(10), events.next()).await {
+                Ok(Some(ClientEvent::Notification(notification))) => {
+                    notifications.push(notification.content)
+                }
+                Ok(Some(_)) => {}
+                Ok(None) | Err(_) => panic!("Expected notifications, got {notifications:?}"),
+            }
+        }
+        notifications
+    };
+
+    // The tavern answers other commands while a password is being checked.
+    mallory.send_raw("/login alice letmein").await.unwrap();
+    mallory.send_raw("/login alice password").await.unwrap();
+    mallory.send_raw("/tone whisper").await.unwrap();
+    assert_eq!(
+        notifications(&mut mallory, 3).await,
+        vec![
+            "Your last password is still being checked.",
+            "You now speak in a whispered tone.",
+            "Wrong name or password.",
+        ]
+    );
+
+    for guess in ["123456", "qwerty", "dragon", "monkey"] {
+        mallory
+            .send_raw(&format!("/login alice {guess}"))
+            .await
+            .unwrap();
+        assert_eq!(
+            notifications(&mut mallory, 1).await,
+            vec!["Wrong name or password."]
+        );
+    }
+    mallory.send_raw("/login alice hunter2").await.unwrap();
+    assert_eq!(
+        notifications(&mut mallory, 1).await,
+        vec!["Too many wrong passwords. Reconnect to try again."]
+    );
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn mentioned_patrons_are_flagged_and_notified() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let alice_name = format!("user{}", alice.id().0);
+    alice.send_raw("/join back room").await.unwrap();
+    wait_for_notification(&mut alice, "You join").await;
+
+    bob.say(&format!("@{alice_name}, your ale is getting warm."))
+        .await
+        .unwrap();
+    let bob_name = format!("user{}", bob.id().0);
+    let notification = wait_for_notification(&mut alice, &bob_name).await;
+    assert_eq!(
+        notification.content,
+        format!("{bob_name} mentioned you in The World: @{alice_name}, your ale is getting warm.")
+    );
+    alice.send_raw("/mentions").await.unwrap();
+    let mut mentions = vec![];
+    let mut events = alice.events();
+    while let Ok(Some(event)) = timeout(Duration::from_millis(200), events.next()).await {
+        if let ClientEvent::History(message) = event {
+            mentions.push(message);
+        }
+    }
+    drop(events);
+    assert_eq!(mentions.len(), 1);
+    assert_eq!(mentions[0].mentions, vec![alice.id()]);
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn authors_can_edit_and_delete_their_messages() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    alice.say("Three ales, please.").await.unwrap();
+    let said = received_messages(&mut bob)
+        .await
+        .into_iter()
+        .find(|m| m.content == "Three ales, please.")
+        .unwrap();
+    let id = said.id.expect("Messages from patrons have an id");
+
+    alice
+        .send_raw(&format!("/edit {id} Two ales, please."))
+        .await
+        .unwrap();
+    let mut events = bob.events();
+    let edited = loop {
+        match timeout(Duration::from_secs(1), events.next()).await {
+            Ok(Some(ClientEvent::Edit(message))) => break message,
+            Ok(Some(_)) => {}
+            _ => panic!("Expected an edit"),
+        }
+    };
+    drop(events);
+    assert_eq!(edited.id, Some(id));
+    assert_eq!(edited.content, "Two ales, please.");
+    assert!(edited.edited);
+
+    bob.send_raw(&format!("/delete {id}")).await.unwrap();
+    wait_for_notification(&mut bob, "You can only change your own messages.").await;
+    alice.send_raw(&format!("/delete {id}")).await.unwrap();
+    let mut events = bob.events();
+    loop {
+        match timeout(Duration::from_secs(1), events.next()).await {
+            Ok(Some(ClientEvent::Delete(deleted))) => break assert_eq!(deleted, id),
+            Ok(Some(_)) => {}
+            _ => panic!("Expected a deletion"),
+        }
+    }
+    drop(events);
+    bob.send_raw("/history").await.unwrap();
+    wait_for_notification(&mut bob, "There's nothing in the history yet.").await;
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn edits_and_deletions_reach_those_who_saw_the_message() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut carol = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    for patron in [&mut alice, &mut bob] {
+        patron.send_raw("/join back room").await.unwrap();
+        wait_for_notification(patron, "You join").await;
+    }
+    alice.say("Three ales, please.").await.unwrap();
+    let said = wait_for_message(&mut bob, "Three ales, please.").await;
+    let id = said.id.expect("Messages from patrons have an id");
+
+    // Bob saw the message before leaving, and Carol walked in after it was said.
+    bob.send_raw("/leave").await.unwrap();
+    wait_for_notification(&mut bob, "You leave").await;
+    carol.send_raw("/join back room").await.unwrap();
+    wait_for_notification(&mut carol, "You join").await;
+
+    alice
+        .send_raw(&format!("/edit {id} Two ales, please."))
+        .await
+        .unwrap();
+    wait_for_notification(&mut alice, &format!("Message {id} edited.")).await;
+    alice.send_raw(&format!("/delete {id}")).await.unwrap();
+    wait_for_notification(&mut alice, &format!("Message {id} deleted.")).await;
+
+    let updates = async |client: &mut TavernClient| {
+        let mut updates = vec![];
+        let mut events = client.events();
+        while let Ok(Some(event)) = timeout(Duration::from_millis(200), events.next()).await {
+            match event {
+                ClientEvent::Edit(message) => updates.push(format!("edit {}", message.content)),
+                ClientEvent::Delete(deleted) => updates.push(format!("delete {deleted}")),
+                _ => {}
+            }
+        }
+        updates
+    };
+    assert_eq!(
+        updates(&mut bob).await,
+        vec!["edit Two ales, please.".to_string(), format!("delete {id}")]
+    );
+    assert_eq!(updates(&mut carol).await, Vec::<String>::new());
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn reactions_are_aggregated_and_kept_in_history() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    alice.say("Why did the bard cross the road?").await.unwrap();
+    let id = received_messages(&mut bob)
+        .await
+        .into_iter()
+        .find_map(|m| m.id)
+        .expect("Messages from patrons have an id");
+
+    alice.send_raw(&format!("/react {id} ha")).await.unwrap();
+    bob.send_raw(&format!("/react {id} 🍺")).await.unwrap();
+    bob.send_raw(&format!("/react {id} ha")).await.unwrap();
+    let mut events = alice.events();
+    let reactions = loop {
+        match timeout(Duration::from_secs(1), events.next()).await {
+            Ok(Some(ClientEvent::Reactions {
+                id: reacted,
+                reactions,
+            })) if reacted == id => {
+                if reactions.values().map(Vec::len).sum::<usize>() == 3 {
+                    break reactions;
+                }
+            }
+            Ok(Some(_)) => {}
+            _ => panic!("Expected reactions"),
+        }
+    };
+    drop(events);
+    assert_eq!(reactions["ha"].len(), 2);
+    assert_eq!(reactions["🍺"].len(), 1);
+
+    // Reacting the same way again takes the reaction back.
+    bob.send_raw(&format!("/react {id} ha")).await.unwrap();
+    wait_for_notification(&mut bob, &format!("You took back ha on {id}.")).await;
+    bob.send_raw("/history").await.unwrap();
+    let history = received_history(&mut bob).await;
+    let replayed = history.iter().find(|m| m.id == Some(id)).unwrap();
+    assert_eq!(replayed.reactions["ha"].len(), 1);
+    assert!(replayed.to_output(false).ends_with(" [ha 1 · 🍺 1]\n"));
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn replies_quote_their_parent_and_form_threads() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    alice.say("Why did the bard cross the road?").await.unwrap();
+    let joke = received_messages(&mut bob).await.remove(0);
+    let joke_id = joke.id.unwrap();
+
+    bob.send_raw(&format!("/reply {joke_id} To reach the other tavern."))
+        .await
+        .unwrap();
+    let reply = received_messages(&mut alice)
+        .await
+        .into_iter()
+        .find(|m| m.content == "To reach the other tavern.")
+        .unwrap();
+    let reply_to = reply
+        .reply_to
+        .clone()
+        .expect("Replies reference their parent");
+    assert_eq!(reply_to.id, joke_id);
+    assert_eq!(reply_to.quote, "Why did the bard cross the road?");
+    assert!(
+        reply
+            .to_output(false)
+            .contains(&format!("(↪ {joke_id} {}: \"Why did", reply_to.from)),
+        "{}",
+        reply.to_output(false)
+    );
+
+    bob.say("Unrelated: more ale!").await.unwrap();
+    alice
+        .send_raw(&format!("/reply {} Groan.", reply.id.unwrap()))
+        .await
+        .unwrap();
+    received_messages(&mut bob).await;
+
+    bob.send_raw(&format!("/thread {joke_id}")).await.unwrap();
+    let thread = received_history(&mut bob).await;
+    assert_eq!(
+        thread
+            .iter()
+            .map(|m| m.content.as_str())
+            .collect::<Vec<_>>(),
+        vec![
+            "Why did the bard cross the road?",
+            "To reach the other tavern.",
+            "Groan."
+        ]
+    );
+    bob.send_raw("/thread #zz").await.unwrap();
+    wait_for_notification(&mut bob, "There is no message #zz.").await;
+
+    // Once the joke is deleted, the replies no longer quote it.
+    alice.send_raw(&format!("/delete {joke_id}")).await.unwrap();
+    wait_for_notification(&mut alice, &format!("Message {joke_id} deleted.")).await;
+    bob.send_raw(&format!("/thread {}", reply.id.unwrap()))
+        .await
+        .unwrap();
+    let thread = received_history(&mut bob).await;
+    let mut carol = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let replayed = received_history(&mut carol).await;
+    for message in thread.iter().chain(&replayed) {
+        assert!(!message.to_output(false).contains("bard"), "{message:?}");
+    }
+    let reply = replayed
+        .iter()
+        .find(|m| m.content == "To reach the other tavern.")
+        .unwrap();
+    assert_eq!(reply.reply_to.as_ref().unwrap().quote, DELETED_QUOTE);
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn emotes_read_differently_to_the_actor_target_and_others() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut carol = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let alice_name = format!("user{}", alice.id().0);
+    let bob_name = format!("user{}", bob.id().0);
+
+    alice.send_raw(&format!("/wave {bob_name}")).await.unwrap();
+    let content = |messages: Vec<Message>| {
+        messages
+            .into_iter()
+            .find(|m| m.emote.is_some())
+            .map(|m| m.content)
+            .unwrap()
+    };
+    assert_eq!(
+        content(received_messages(&mut alice).await),
+        format!("You wave at {bob_name}. Wassup?")
+    );
+    assert_eq!(
+        content(received_messages(&mut bob).await),
+        format!("{alice_name} waves at you. Wassup?")
+    );
+    assert_eq!(
+        content(received_messages(&mut carol).await),
+        format!("{alice_name} waves at {bob_name}. Wassup?")
+    );
+
+    alice.send_raw("/poke").await.unwrap();
+    wait_for_notification(&mut alice, "/poke needs a target.").await;
+    alice.send_raw("/sulk").await.unwrap();
+    wait_for_notification(&mut alice, "Unknown command.").await;
+    alice.send_raw("/emotes").await.unwrap();
+    let emotes = wait_for_notification(&mut alice, "Emotes: ").await;
+    assert!(
+        emotes.content.contains("/poke <name>"),
+        "{}",
+        emotes.content
+    );
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn actions_read_in_the_third_person() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    alice
+        .send_raw("/me leans on the bar and sighs")
+        .await
+        .unwrap();
+    let action = received_messages(&mut bob).await.remove(0);
+    assert_eq!(action.kind, MessageKind::Action);
+    assert_eq!(action.content, "leans on the bar and sighs");
+    let output = action.to_output(false);
+    assert!(
+        output.ends_with(&format!(
+            "{} * {} leans on the bar and sighs\n",
+            action.id.unwrap(),
+            action.from.unwrap()
+        )),
+        "{output}"
+    );
+
+    bob.send_raw("/history").await.unwrap();
+    let history = received_history(&mut bob).await;
+    assert_eq!(history[0].kind, MessageKind::Action);
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn tones_change_how_far_messages_carry_and_how_npcs_react() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut carol = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let alice_name = format!("user{}", alice.id().0);
+
+    alice.send_raw("/tone hummed").await.unwrap();
+    wait_for_notification(&mut alice, "There is no \"hummed\" tone.").await;
+    alice.send_raw("/yell more ale").await.unwrap();
+    let yelled = received_messages(&mut bob).await.remove(0);
+    assert_eq!(yelled.tone, MessageTone::YELLED);
+    assert_eq!(yelled.content, "MORE ALE");
+
+    // Whispers to the whole tavern only reach the patrons sitting nearby.
+    for patron in [&mut alice, &mut carol] {
+        patron.send_raw("/join back room").await.unwrap();
+        patron.send_raw("/global").await.unwrap();
+    }
+    alice.send_raw("/tone whisper").await.unwrap();
+    wait_for_notification(&mut alice, "You now speak in a whispered tone.").await;
+    alice
+        .send_raw("The cellar key is under the mat.")
+        .await
+        .unwrap();
+    let overheard = |messages: Vec<Message>| {
+        messages
+            .iter()
+            .any(|m| m.content == "The cellar key is under the mat.")
+    };
+    assert!(overheard(received_messages(&mut carol).await));
+    assert!(!overheard(received_messages(&mut bob).await));
+
+    // New tones come with their own command, and NPCs react to them.
+    alice.send_raw("/to_npc 0").await.unwrap();
+    alice.send_raw("/slur one more drink").await.unwrap();
+    let reaction = received_messages(&mut alice)
+        .await
+        .into_iter()
+        .find(|m| m.from == Some(ChatTarget::npc(0)))
+        .expect("The barkeep reacts to slurring");
+    assert_eq!(
+        reaction.content,
+        format!("I think you've had enough, {alice_name}.")
+    );
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn whispers_stay_private_and_yells_carry_to_the_next_room() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut carol = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let carol_name = format!("user{}", carol.id().0);
+    for (patron, room) in [
+        (&mut alice, "common room"),
+        (&mut bob, "back room"),
+        (&mut carol, "fireplace table"),
+    ] {
+        patron.send_raw(&format!("/join {room}")).await.unwrap();
+        wait_for_notification(patron, "You join").await;
+    }
+
+    // Yells are heard in the rooms next door, but no further.
+    alice.send_raw("/yell last orders!").await.unwrap();
+    let heard = received_messages(&mut bob).await;
+    assert!(
+        heard
+            .iter()
+            .any(|m| m.content == "(from the next room) LAST ORDERS!"),
+        "{heard:?}"
+    );
+    assert!(received_messages(&mut carol).await.is_empty());
+
+    // Whispers to the whole tavern are kept from anyone out of earshot, mentioned or not.
+    alice.send_raw("/global").await.unwrap();
+    alice.send_raw("/tone whisper").await.unwrap();
+    wait_for_notification(&mut alice, "You now speak in a whispered tone.").await;
+    alice.send_raw("/say A round for everyone!").await.unwrap();
+    wait_for_message(&mut carol, "A round for everyone!").await;
+    wait_for_message(&mut bob, "A round for everyone!").await;
+    // Saying something picks the tone back up.
+    alice.send_raw("/tone whisper").await.unwrap();
+    wait_for_notification(&mut alice, "You now speak in a whispered tone.").await;
+    alice
+        .send_raw(&format!("@{carol_name} owes me a crown"))
+        .await
+        .unwrap();
+    let mut events = carol.events();
+    while let Ok(Some(event)) = timeout(Duration::from_millis(200), events.next()).await {
+        assert!(
+            !matches!(&event, ClientEvent::Message(m) if m.content.contains("crown")),
+            "{event:?}"
+        );
+        assert!(!matches!(event, ClientEvent::Notification(_)), "{event:?}");
+    }
+    drop(events);
+    assert!(received_messages(&mut bob).await.is_empty());
+    bob.send_raw("/history").await.unwrap();
+    let history = received_history(&mut bob).await;
+    assert!(!history.iter().any(|m| m.content.contains("crown")));
+    bob.send_raw("/search crown").await.unwrap();
+    assert!(received_history(&mut bob).await.is_empty());
+    // Nor is it replayed to those who come in afterwards.
+    let mut dave = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let replayed = received_history(&mut dave).await;
+    assert!(
+        replayed
+            .iter()
+            .any(|m| m.content == "A round for everyone!"),
+        "{replayed:?}"
+    );
+    assert!(
+        !replayed.iter().any(|m| m.content.contains("crown")),
+        "{replayed:?}"
+    );
+
+    // The whisperer still finds it in their own history.
+    alice.send_raw("/search crown").await.unwrap();
+    assert_eq!(received_history(&mut alice).await.len(), 1);
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn dice_are_rolled_by_the_server_and_can_be_checked() {
+    let (server, event_tx) = start_tavern();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+
+    alice.send_raw("/roll 2d6+").await.unwrap();
+    assert!(
+        received_messages(&mut alice)
+            .await
+            .iter()
+            .any(|m| m.from.is_none() && m.content.starts_with("Invalid dice \"2d6+\"."))
+    );
+    alice.send_raw("/roll seal").await.unwrap();
+    let sealed = wait_for_notification(&mut alice, "The dice are sealed with").await;
+    assert!(sealed.content.contains("after 0 rolls"), "{sealed:?}");
+
+    alice.send_raw("/roll 4d6kh3 + 2").await.unwrap();
+    let rolled = received_messages(&mut bob)
+        .await
+        .into_iter()
+        .find(|m| m.kind == MessageKind::Roll)
+        .expect("Everyone sees the roll");
+    let roll = rolled.roll.clone().unwrap();
+    assert_eq!(rolled.from, Some(ChatTarget::User(alice.id())));
+    assert!(
+        rolled.content.starts_with("rolls 4d6kh3+2: ["),
+        "{rolled:?}"
+    );
+    assert!(
+        rolled
+            .content
+            .ends_with(&format!("= {} (roll 1)", roll.total))
+    );
+    assert!((5..=20).contains(&roll.total));
+    assert!(sealed.content.contains(&roll.seal));
+
+    // Nobody can change a roll after the fact.
+    let id = rolled.id.unwrap();
+    alice
+        .send_raw(&format!("/edit {id} rolls a 20"))
+        .await
+        .unwrap();
+    wait_for_notification(&mut alice, "Dice rolls can't be edited.").await;
+
+    // Once revealed, the seed rolls the same dice again.
+    bob.send_raw("/roll reveal").await.unwrap();
+    let revealed = received_messages(&mut alice)
+        .await
+        .into_iter()
+        .find(|m| m.content.contains("revealed the dice"))
+        .expect("Everyone sees the seed");
+    let seed = revealed
+        .content
+        .split("their seed is ")
+        .nth(1)
+        .and_then(|rest| rest.split(',').next())
+        .unwrap();
+    let dice = Dice::from_seed(seed);
+    assert_eq!(dice.seal(), roll.seal);
+    let expression = DiceExpression::parse(&roll.expression).unwrap();
+    assert_eq!(dice.roll_as(roll.number, &expression), roll);
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn games_are_played_in_turns_and_announced_where_they_are() {
+    let (mut server, event_tx) = TavernServer::builder()
+        .without_listeners()
+        .game_turn_timeout(Duration::from_millis(300))
+        .build();
+    let server = tokio::spawn(async move { server.run().await });
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut carol = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let (alice_name, bob_name) = (
+        format!("user{}", alice.id().0),
+        format!("user{}", bob.id().0),
+    );
+
+    // Dice poker, with the whole tavern. Bob doesn't play his turn in time.
+    alice.send_raw("/game start dice-poker").await.unwrap();
+    wait_for_message(&mut carol, "wants to play dice poker").await;
+    bob.send_raw("/game roll").await.unwrap();
+    wait_for_notification(&mut bob, "You're not playing dice poker.").await;
+    bob.send_raw("/game join").await.unwrap();
+    alice.send_raw("/game roll").await.unwrap();
+    let rolled = wait_for_message(&mut carol, &format!("{alice_name} rolls [")).await;
+    assert!(rolled.content.starts_with("Dice poker begins between"));
+    bob.send_raw("/game hold 1").await.unwrap();
+    wait_for_notification(&mut bob, &format!("It's {alice_name}'s turn.")).await;
+    alice.send_raw("/game hold 1 2").await.unwrap();
+    alice.send_raw("/game roll").await.unwrap();
+    alice.send_raw("/game stand").await.unwrap();
+    wait_for_message(&mut carol, &format!("It's {bob_name}'s turn")).await;
+    let result = wait_for_message(&mut carol, "took too long").await;
+    assert!(
+        result
+            .content
+            .contains(&format!("{alice_name} wins the game of dice poker with")),
+        "{result:?}"
+    );
+    alice.send_raw("/game").await.unwrap();
+    wait_for_notification(&mut alice, "Nobody is playing here.").await;
+
+    // Arm wrestling, in a room. Alice pushes every round, and Bob never does.
+    for patron in [&mut alice, &mut bob] {
+        patron.send_raw("/join back room").await.unwrap();
+        wait_for_notification(patron, "You join").await;
+    }
+    alice.send_raw("/game start arm wrestling").await.unwrap();
+    bob.send_raw("/game join").await.unwrap();
+    wait_for_message(&mut alice, "lock hands over the table").await;
+    for _ in 0..2 {
+        alice.send_raw("/game roll").await.unwrap();
+        wait_for_message(
+            &mut alice,
+            &format!("{bob_name} hesitates.\n{alice_name} is ahead"),
+        )
+        .await;
+    }
+    alice.send_raw("/game roll").await.unwrap();
+    wait_for_message(
+        &mut bob,
+        &format!("{alice_name} slams {bob_name}'s arm onto the table, and wins!"),
+    )
+    .await;
+    assert!(
+        !received_messages(&mut carol)
+            .await
+            .iter()
+            .any(|m| m.content.contains("arm"))
+    );
+
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn the_quizmaster_runs_trivia_and_keeps_a_leaderboard() {
+    let dir = std::env::temp_dir().join(format!("tavern-trivia-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let questions = dir.join("questions.json");
+    std::fs::write(
+        &questions,
+        r#"[{"question": "Who wrote The Hobbit?", "answers": ["J. R. R. Tolkien", "Tolkien"]}]"#,
+    )
+    .unwrap();
+    let start = || {
+        let (mut server, event_tx) = TavernServer::builder()
+            .without_listeners()
+            .trivia_room(Some("The Back Room"))
+            .trivia_file(Some(&questions))
+            .trivia_scores_file(Some(&dir.join("scores.json")))
+            .trivia_interval(Duration::from_millis(500))
+            .build();
+        (tokio::spawn(async move { server.run().await }), event_tx)
+    };
+
+    let (server, event_tx) = start();
+    let mut alice = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    let bob_name = format!("user{}", bob.id().0);
+    alice.send_raw("/register Alice hunter2").await.unwrap();
+    wait_for_notification(&mut alice, "Welcome, Alice!").await;
+    alice.send_raw("/join back room").await.unwrap();
+    bob.send_raw("/join back room").await.unwrap();
+    let asked = wait_for_message(&mut alice, "Trivia time! Who wrote The Hobbit?").await;
+    assert!(matches!(asked.from, Some(ChatTarget::Npc(_))));
+    alice.send_raw("/trivia").await.unwrap();
+    wait_for_notification(
+        &mut alice,
+        "The question in The Back Room is: Who wrote The Hobbit?",
+    )
+    .await;
+
+    // Answers are said in the room like anything else, typos and all.
+    alice.send_raw("Is it Tolkein?").await.unwrap();
+    wait_for_message(
+        &mut alice,
+        "Correct, Alice! It was J. R. R. Tolkien. Alice has 1 point.",
+    )
+    .await;
+    // Guests can answer, but their names are anyone's, so they don't score.
+    wait_for_message(&mut bob, "Correct, Alice!").await;
+    wait_for_message(&mut bob, "Trivia time!").await;
+    bob.send_raw("Tolkien").await.unwrap();
+    wait_for_message(
+        &mut alice,
+        &format!("Correct, {bob_name}! It was J. R. R. Tolkien. Use /register or /login"),
+    )
+    .await;
+    wait_for_message(&mut alice, "Time's up! The answer was J. R. R. Tolkien.").await;
+    alice.send_raw("/trivia top").await.unwrap();
+    let leaderboard = "Trivia leaderboard: 1. Alice (1 point)";
+    assert_eq!(
+        wait_for_notification(&mut alice, leaderboard).await.content,
+        leaderboard
+    );
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+
+    let (server, event_tx) = start();
+    let mut bob = TavernClient::in_process(event_tx.clone()).await.unwrap();
+    bob.send_raw("/trivia top").await.unwrap();
+    wait_for_notification(&mut bob, leaderboard).await;
+    event_tx.send(Event::Shutdown).await.unwrap();
+    assert!(server.await.unwrap().is_ok());
+    let _ = std::fs::remove_dir_all(&dir);
+}